@@ -0,0 +1,69 @@
+use anyhow::{bail, Result};
+use chrono::TimeDelta;
+
+const SAMPLE_RATES: [u32; 13] = [96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350];
+
+#[derive(Debug, Clone, Copy)]
+pub struct AdtsFrame {
+    pub offset: usize,
+    pub len: usize,
+    pub header_len: usize,
+    pub samples: u32,
+    pub sample_rate: u32,
+    pub sample_rate_index: u8,
+    pub profile: u8,
+    pub channels: u8,
+}
+
+impl AdtsFrame {
+    pub fn parse(buf: &[u8], offset: usize) -> Option<Self> {
+        let h = buf.get(offset..offset + 7)?;
+        if h[0] != 0xFF || h[1] & 0xF6 != 0xF0 { return None; }
+        let sample_rate_index = (h[2] >> 2) & 0x0F;
+        let sample_rate = *SAMPLE_RATES.get(sample_rate_index as usize)?;
+        let len = (((h[3] & 0x03) as usize) << 11) | ((h[4] as usize) << 3) | ((h[5] >> 5) as usize);
+        let header_len = if h[1] & 0x01 == 0 { 9 } else { 7 };
+        if len < header_len { return None; }
+        Some(AdtsFrame {
+            offset,
+            len,
+            header_len,
+            samples: 1024 * ((h[6] & 0x03) as u32 + 1),
+            sample_rate,
+            sample_rate_index,
+            profile: h[2] >> 6,
+            channels: ((h[2] & 0x01) << 2) | (h[3] >> 6),
+        })
+    }
+    pub fn duration(&self) -> TimeDelta {
+        TimeDelta::microseconds(self.samples as i64 * 1_000_000 / self.sample_rate as i64)
+    }
+}
+
+// 先頭のID3v2タグ(あれば)を飛ばした音声データの開始位置
+pub fn skip_id3(buf: &[u8]) -> usize {
+    if buf.len() < 10 || &buf[..3] != b"ID3" { return 0; }
+    let size = buf[6..10].iter().fold(0usize, |acc, b| (acc << 7) | (*b & 0x7F) as usize);
+    let footer = if buf[5] & 0x10 != 0 { 10 } else { 0 };
+    (10 + size + footer).min(buf.len())
+}
+
+pub fn scan_frames(buf: &[u8]) -> Result<Vec<AdtsFrame>> {
+    let mut frames = vec![];
+    let mut pos = skip_id3(buf);
+    while pos < buf.len() {
+        let Some(frame) = AdtsFrame::parse(buf, pos) else {
+            bail!("ADTS sync lost at byte {pos}")
+        };
+        if pos + frame.len > buf.len() {
+            bail!("truncated ADTS frame at byte {pos}")
+        }
+        frames.push(frame);
+        pos += frame.len;
+    }
+    Ok(frames)
+}
+
+pub fn total_duration(frames: &[AdtsFrame]) -> TimeDelta {
+    frames.iter().map(|f| f.duration()).sum()
+}
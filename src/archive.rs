@@ -0,0 +1,239 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use serde_json::Value;
+use crate::ledger::ledger_key;
+use crate::matcher::{search_artist, MatchType};
+use crate::radiko::{jst, RadioChannel, RadioProgram};
+use crate::schedule::{ScheduleChange, ScheduleEvent};
+use crate::text::{bigrams, indexed_fields, query_terms, score, Readings};
+
+// 番組は放送日(JST)ごとに programs/YYYYMMDD.json、その日の索引を index/YYYYMMDD.json、番組表の変更を events/YYYYMMDD.json に置く
+pub struct ProgramArchive {
+    dir: PathBuf,
+    readings: Readings,
+}
+
+// 索引の作り方を変えたら上げる。古い索引は検索時に作り直す
+const INDEX_VERSION: u32 = 2;
+
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub program: RadioProgram,
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    pub text: Option<String>,
+    pub station: Option<String>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+pub(crate) fn broadcast_date(program: &RadioProgram) -> NaiveDate {
+    program.ft.with_timezone(&jst()).date_naive()
+}
+
+fn read_json<T: for<'de> Deserialize<'de> + Default>(path: &Path) -> Result<T> {
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(serde_json::from_str(&s)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_string(value)?)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DayIndex {
+    #[serde(default)]
+    version: u32,
+    keys: Vec<String>,
+    postings: BTreeMap<String, Vec<usize>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ArchiveMeta {
+    pub members_sha256: Option<String>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl ProgramArchive {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(dir.join("programs"))?;
+        fs::create_dir_all(dir.join("index"))?;
+        Ok(ProgramArchive { dir, readings: Readings::default() })
+    }
+    pub fn with_readings(self, readings: Readings) -> Self {
+        ProgramArchive { readings, ..self }
+    }
+    fn day_path(&self, date: NaiveDate) -> PathBuf {
+        self.dir.join("programs").join(format!("{}.json", date.format("%Y%m%d")))
+    }
+    fn events_path(&self, date: NaiveDate) -> PathBuf {
+        self.dir.join("events").join(format!("{}.json", date.format("%Y%m%d")))
+    }
+    fn index_path(&self, date: NaiveDate) -> PathBuf {
+        self.dir.join("index").join(format!("{}.json", date.format("%Y%m%d")))
+    }
+    pub fn meta(&self) -> Result<ArchiveMeta> {
+        read_json(&self.dir.join("meta.json"))
+    }
+    pub fn save_meta(&self, meta: &ArchiveMeta) -> Result<()> {
+        write_json(&self.dir.join("meta.json"), meta)
+    }
+    // 番組ドキュメントには局IDしか入らないので、局の情報は別に持っておく
+    fn stations(&self) -> Result<BTreeMap<String, RadioChannel>> {
+        read_json(&self.dir.join("stations.json"))
+    }
+    pub fn days(&self) -> Result<Vec<NaiveDate>> {
+        let mut days = fs::read_dir(self.dir.join("programs"))?.filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            NaiveDate::parse_from_str(name.strip_suffix(".json")?, "%Y%m%d").ok()
+        }).collect::<Vec<_>>();
+        days.sort();
+        Ok(days)
+    }
+    pub fn load_day(&self, date: NaiveDate) -> Result<Vec<RadioProgram>> {
+        let programs: BTreeMap<String, RadioProgram> = read_json(&self.day_path(date))?;
+        let stations = self.stations()?;
+        Ok(programs.into_values().map(|program| match stations.get(&program.radio_channel.id) {
+            Some(channel) => RadioProgram { radio_channel: channel.clone(), ..program },
+            None => program,
+        }).collect())
+    }
+    // 同じ番組は上書きする。ただし後から取った方がon_air_musicが少なければ前のものを残す
+    pub fn store(&self, programs: &[RadioProgram]) -> Result<usize> {
+        let mut stations = self.stations()?;
+        let mut by_day: BTreeMap<NaiveDate, Vec<&RadioProgram>> = BTreeMap::new();
+        for program in programs {
+            by_day.entry(broadcast_date(program)).or_default().push(program);
+            if !program.radio_channel.name.is_empty() {
+                stations.insert(program.radio_channel.id.clone(), program.radio_channel.clone());
+            }
+        }
+        write_json(&self.dir.join("stations.json"), &stations)?;
+        let mut stored = 0;
+        for (date, day_programs) in by_day {
+            let mut existing: BTreeMap<String, RadioProgram> = read_json(&self.day_path(date))?;
+            for program in day_programs {
+                let key = ledger_key(program);
+                if existing.get(&key).map(|old| old.on_air_music.len() > program.on_air_music.len()).unwrap_or(false) { continue; }
+                existing.insert(key, program.clone());
+                stored += 1;
+            }
+            let index = self.build_index(&existing);
+            write_json(&self.day_path(date), &existing)?;
+            write_json(&self.index_path(date), &index)?;
+        }
+        Ok(stored)
+    }
+    // 前回から番組表が変わった分を記録し、消えた番組は日ごとのファイルから外す
+    pub fn apply_changes(&self, events: &[ScheduleEvent]) -> Result<()> {
+        let mut by_day: BTreeMap<NaiveDate, Vec<&ScheduleEvent>> = BTreeMap::new();
+        for event in events {
+            by_day.entry(broadcast_date(&event.program)).or_default().push(event);
+        }
+        for (date, day_events) in by_day {
+            let mut existing: BTreeMap<String, RadioProgram> = read_json(&self.day_path(date))?;
+            let mut logged: BTreeMap<String, ScheduleEvent> = read_json(&self.events_path(date))?;
+            for event in day_events {
+                // 番組名が変わっただけなら、今回の分で上書きされる
+                if !matches!(event.change, ScheduleChange::Retitled { .. }) {
+                    existing.remove(&ledger_key(&event.program));
+                }
+                logged.entry(event.key()).or_insert_with(|| event.clone());
+            }
+            write_json(&self.day_path(date), &existing)?;
+            write_json(&self.index_path(date), &self.build_index(&existing))?;
+            write_json(&self.events_path(date), &logged)?;
+        }
+        Ok(())
+    }
+    pub fn events(&self, date: NaiveDate) -> Result<Vec<ScheduleEvent>> {
+        let logged: BTreeMap<String, ScheduleEvent> = read_json(&self.events_path(date))?;
+        Ok(logged.into_values().collect())
+    }
+    fn build_index(&self, programs: &BTreeMap<String, RadioProgram>) -> DayIndex {
+        let mut index = DayIndex { version: INDEX_VERSION, keys: programs.keys().cloned().collect(), ..Default::default() };
+        for (i, program) in programs.values().enumerate() {
+            let tokens = indexed_fields(program, &self.readings).iter().flat_map(|(_, text)| bigrams(text)).collect::<BTreeSet<_>>();
+            for token in tokens {
+                index.postings.entry(token).or_default().push(i);
+            }
+        }
+        index
+    }
+    fn load_index(&self, date: NaiveDate) -> Result<DayIndex> {
+        let index: DayIndex = read_json(&self.index_path(date))?;
+        if index.version == INDEX_VERSION { return Ok(index); }
+        let index = self.build_index(&read_json(&self.day_path(date))?);
+        write_json(&self.index_path(date), &index)?;
+        Ok(index)
+    }
+    pub fn search(&self, query: &SearchQuery) -> Result<Vec<SearchHit>> {
+        let terms = query.text.as_deref().map(query_terms).unwrap_or_default();
+        // 1文字の語は索引では引けないので、本文の確認だけに任せる
+        let query_tokens = terms.iter().flat_map(|term| bigrams(term)).filter(|token| token.chars().count() == 2).collect::<BTreeSet<_>>();
+        let mut found = vec![];
+        for date in self.days()? {
+            if query.from.map(|from| date < from).unwrap_or(false) || query.to.map(|to| date > to).unwrap_or(false) { continue; }
+            let candidates = if query_tokens.is_empty() {
+                None
+            } else {
+                let index = self.load_index(date)?;
+                let mut hits: Option<BTreeSet<usize>> = None;
+                for token in &query_tokens {
+                    let postings = index.postings.get(token).map(|p| p.iter().copied().collect::<BTreeSet<_>>()).unwrap_or_default();
+                    hits = Some(match hits {
+                        None => postings,
+                        Some(hits) => hits.intersection(&postings).copied().collect(),
+                    });
+                }
+                let hits = hits.unwrap_or_default();
+                if hits.is_empty() { continue; }
+                Some(hits.into_iter().filter_map(|i| index.keys.get(i).cloned()).collect::<BTreeSet<_>>())
+            };
+            for program in self.load_day(date)? {
+                if candidates.as_ref().map(|keys| !keys.contains(&ledger_key(&program))).unwrap_or(false) { continue; }
+                if query.station.as_ref().map(|s| s != &program.radio_channel.id).unwrap_or(false) { continue; }
+                // bigramがすべて含まれていても連続しているとは限らないので、最後に本文で確かめる
+                let Some(score) = score(&indexed_fields(&program, &self.readings), &terms) else { continue };
+                found.push(SearchHit { program, score });
+            }
+        }
+        found.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.program.ft.cmp(&b.program.ft)));
+        Ok(found)
+    }
+}
+
+pub fn members_sha256(member_json: &str) -> String {
+    format!("{:x}", Sha256::digest(member_json.as_bytes()))
+}
+
+// メンバー一覧が変わったとき、過去の番組にもう一度マッチングをかける
+pub fn rematch(programs: Vec<RadioProgram>, member_json: &Value, match_rules: &Value) -> Vec<(RadioProgram, BTreeMap<String, Vec<MatchType>>)> {
+    programs.into_iter().filter_map(|program| {
+        let res = search_artist(program.clone(), member_json.clone(), match_rules.clone());
+        if res.is_empty() { return None; }
+        let mut matched = BTreeMap::<String, Vec<MatchType>>::new();
+        for (name, match_type) in res {
+            matched.entry(name).or_default().push(match_type);
+        }
+        Some((program, matched))
+    }).collect()
+}
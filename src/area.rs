@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use anyhow::{bail, Result};
+use markup5ever_rcdom::{NodeData, RcDom};
+use reqwest::Client;
+use xml5ever::driver::{parse_document, XmlParseOpts};
+use xml5ever::tendril::*;
+use crate::radiko::{dig_xml, get_below_string, RadioChannel};
+
+// radikoのエリアIDは都道府県コードに対応している(JP1=北海道 … JP47=沖縄)
+pub const AREAS: [&str; 47] = [
+    "北海道", "青森", "岩手", "宮城", "秋田", "山形", "福島", "茨城", "栃木", "群馬", "埼玉", "千葉", "東京", "神奈川",
+    "新潟", "富山", "石川", "福井", "山梨", "長野", "岐阜", "静岡", "愛知", "三重", "滋賀", "京都", "大阪", "兵庫",
+    "奈良", "和歌山", "鳥取", "島根", "岡山", "広島", "山口", "徳島", "香川", "愛媛", "高知", "福岡", "佐賀", "長崎",
+    "熊本", "大分", "宮崎", "鹿児島", "沖縄",
+];
+
+pub fn area_name(area_id: &str) -> Option<&'static str> {
+    let index = area_id.strip_prefix("JP")?.parse::<usize>().ok()?;
+    AREAS.get(index.checked_sub(1)?).copied()
+}
+
+// 引数は「JP13」「13」「東京」のどれでもよい
+pub fn parse_area(s: &str) -> Result<String> {
+    if let Some(index) = AREAS.iter().position(|name| *name == s || s.strip_suffix(['都', '道', '府', '県']) == Some(name)) {
+        return Ok(format!("JP{}", index + 1));
+    }
+    let id = if s.starts_with("JP") { s.to_owned() } else { format!("JP{s}") };
+    if area_name(&id).is_none() { bail!("unknown area: {s}") }
+    Ok(id)
+}
+
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub id: String,
+    pub name: String,
+    pub ascii_name: String,
+    pub stations: Vec<RadioChannel>,
+}
+
+// region/full.xmlの<stations>ごとの地方名。局の並びはparse_channelsと同じ
+pub fn parse_regions(xml: &str, channels: &[RadioChannel]) -> Vec<Region> {
+    let doc = parse_document(RcDom::default(), XmlParseOpts::default()).from_utf8().read_from(&mut xml.as_bytes()).unwrap();
+    let regions = dig_xml(doc.document, vec!["region", "stations"], |handle| match &handle.data {
+        NodeData::Element { attrs, .. } => {
+            let attrs = attrs.borrow().iter().map(|a| (a.name.local.to_string(), a.value.to_string())).collect::<HashMap<_, _>>();
+            let ids = dig_xml(handle.clone(), vec!["station", "id"], get_below_string);
+            Some((attrs, ids))
+        }
+        _ => None,
+    });
+    regions.into_iter().map(|(attrs, ids)| Region {
+        id: attrs.get("region_id").cloned().unwrap_or_default(),
+        name: attrs.get("region_name").cloned().unwrap_or_default(),
+        ascii_name: attrs.get("ascii_name").cloned().unwrap_or_default(),
+        stations: channels.iter().filter(|c| ids.contains(&c.id)).cloned().collect(),
+    }).collect()
+}
+
+// station/list/{area}.xml はそのエリアで受信できる局(他エリアの局も含む)の一覧
+pub fn parse_area_station_ids(xml: &str) -> Vec<String> {
+    let doc = parse_document(RcDom::default(), XmlParseOpts::default()).from_utf8().read_from(&mut xml.as_bytes()).unwrap();
+    dig_xml(doc.document, vec!["stations", "station", "id"], get_below_string)
+}
+
+pub async fn area_station_ids(client: &Client, area_id: &str) -> Result<Vec<String>> {
+    let xml = client.get(format!("https://radiko.jp/v3/station/list/{area_id}.xml")).send().await?.error_for_status()?.text().await?;
+    Ok(parse_area_station_ids(&xml))
+}
+
+// エリアフリーでなければ、タイムフリー・ライブとも聴けるのは認証で判定されたエリアの局だけ
+pub async fn receivable_channels(client: &Client, channels: &[RadioChannel], area_id: &str) -> Result<Vec<RadioChannel>> {
+    let ids = area_station_ids(client, area_id).await?;
+    Ok(channels.iter().filter(|c| ids.contains(&c.id)).cloned().collect())
+}
@@ -0,0 +1,98 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use chrono::{TimeDelta, Utc};
+use firestore::{FirestoreDb, FirestoreDbOptions, FirestoreTimestamp};
+use futures::TryStreamExt;
+use kdam::tqdm;
+use radiko_cacher::export::{write_json, write_parquet, ArchivedProgram, ExportFormat};
+use radiko_cacher::schema::{catalog_parent_in, match_parent_in, MatchRecord, ProgramDocument, DATA_ROOT, MATCH_COLLECTION, PROGRAM_COLLECTION};
+use radiko_cacher::watchlist::load_watchlists;
+
+fn usage() -> ! {
+    eprintln!("usage: archive_expiring [--days N] [--out DIR] [--format json|parquet]");
+    eprintln!("       parquet writes one expiring_{{time}}.parquet per collection");
+    std::process::exit(2)
+}
+
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1)
+}
+
+// 1つのコレクションの、期限が近い番組を該当情報ごと書き出す
+async fn export(db: &FirestoreDb, root: &str, days: i64, root_dir: &Path, format: ExportFormat) -> anyhow::Result<usize> {
+    let documents: Vec<ProgramDocument> = db
+        .fluent()
+        .select()
+        .from(PROGRAM_COLLECTION)
+        .parent(catalog_parent_in(db, root)?)
+        .filter(|q| q.for_all([q.field("expire_at").less_than(FirestoreTimestamp(Utc::now() + TimeDelta::days(days)))]))
+        .obj()
+        .stream_query_with_errors().await?
+        .try_collect().await?;
+    let mut archived = vec![];
+    for document in tqdm!(documents.into_iter(),desc=root) {
+        let matches: Vec<MatchRecord> = db.fluent().list().from(MATCH_COLLECTION).parent(match_parent_in(db, root, document.program.id)?)
+            .obj().stream_all_with_errors().await?.try_collect().await
+            .with_context(|| format!("matches of program {}", document.program.id))?;
+        archived.push(ArchivedProgram { document, matches });
+    }
+    println!();
+    match format {
+        ExportFormat::Json => for program in &archived {
+            write_json(root_dir, program)?;
+        },
+        ExportFormat::Parquet if archived.is_empty() => {}
+        ExportFormat::Parquet => write_parquet(&root_dir.join(format!("expiring_{}.parquet", Utc::now().format("%Y%m%dT%H%M%SZ"))), &archived)?,
+    }
+    Ok(archived.len())
+}
+
+// TTLで消される前に、期限が近い番組を該当情報ごとローカルのJSON/Parquetに書き出す
+#[tokio::main]
+async fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let mut days = 3;
+    let mut out = PathBuf::from(env::var("RADIKO_EXPORT_DIR").unwrap_or("firestore_archive".to_owned()));
+    let mut format = ExportFormat::Json;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let value = rest.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--days" => days = value.parse().unwrap_or_else(|_| usage()),
+            "--out" => out = PathBuf::from(value),
+            "--format" => format = ExportFormat::parse(value).unwrap_or_else(|| usage()),
+            _ => usage(),
+        }
+    }
+
+    let credentials = PathBuf::from(env::var("FIRESTORE_CRED_JSON").unwrap_or_else(|_| fail("FIRESTORE_CRED_JSON is not set")));
+    let firestore_db = FirestoreDb::with_options_service_account_key_file(FirestoreDbOptions::new("hello-radiko".to_owned()), credentials).await
+        .unwrap_or_else(|err| fail(format!("failed to connect to Firestore: {err:#}")));
+    // ウォッチリストごとのコレクションも同じように期限が来るので全部見る
+    let watchlists = load_watchlists(include_str!("../../src/watchlists.json"), &PathBuf::from(env::var("RADIKO_WATCHLISTS").unwrap_or("watchlists.json".to_owned())))
+        .unwrap_or_else(|err| fail(format!("failed to load watchlists: {err:#}")));
+    let mut roots = vec![DATA_ROOT.to_owned()];
+    for watchlist in watchlists {
+        if !roots.contains(&watchlist.collection) { roots.push(watchlist.collection); }
+    }
+
+    let mut exported = 0;
+    let mut failed = vec![];
+    for root in &roots {
+        // 既定のコレクションは今まで通りout直下、それ以外はコレクション名の下に書く
+        let root_dir = if root == DATA_ROOT { out.clone() } else { out.join(root) };
+        match export(&firestore_db, root, days, &root_dir, format).await {
+            Ok(count) => exported += count,
+            Err(err) => {
+                eprintln!("failed to export {root}: {err:#}");
+                failed.push(root.as_str());
+            }
+        }
+    }
+    println!("{exported} programs from {} collections exported to {}", roots.len() - failed.len(), out.display());
+    if !failed.is_empty() {
+        fail(format!("failed collections: {}", failed.join(", ")));
+    }
+}
@@ -0,0 +1,53 @@
+use std::env;
+use std::path::PathBuf;
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use reqwest::Client;
+use radiko_cacher::clip::{clip_recording, resolve_hints, ClipHint};
+use radiko_cacher::radiko::{jst, parse_channels, parse_programs, OnAirMusic, RadioProgram};
+use radiko_cacher::tagging::{write_tags, TagInfo};
+
+fn usage() -> ! {
+    eprintln!("usage: clip <recording.aac> <station_id> <ft:YYYYMMDDHHMMSS> [--at HH:MM:SS-HH:MM:SS[=TITLE]]... [--music LITERAL]... [--desc] [--out DIR]");
+    std::process::exit(2)
+}
+
+#[tokio::main]
+async fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let [recording, station_id, ft] = &args.get(..3).unwrap_or_else(|| usage()) else { usage() };
+    let recording = PathBuf::from(recording);
+    let ft: DateTime<Utc> = NaiveDateTime::parse_from_str(ft, "%Y%m%d%H%M%S").unwrap_or_else(|_| usage())
+        .and_local_timezone(jst()).unwrap().with_timezone(&Utc);
+    let mut hints = vec![];
+    let mut out_dir = recording.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    let mut rest = args[3..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--at" => hints.push(ClipHint::parse_manual(rest.next().unwrap_or_else(|| usage())).unwrap()),
+            "--music" => hints.push(ClipHint::Music(rest.next().unwrap_or_else(|| usage()).clone())),
+            "--desc" => hints.push(ClipHint::Desc),
+            "--out" => out_dir = PathBuf::from(rest.next().unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+
+    let client = Client::new();
+    let channels = parse_channels(client.get("https://radiko.jp/v3/station/region/full.xml").send().await.unwrap().text().await.unwrap().as_str());
+    let channel = channels.into_iter().find(|c| &c.id == station_id).expect("station not found.");
+    // radikoの番組表は5時始まり
+    let date = (ft.with_timezone(&jst()) - TimeDelta::hours(5)).format("%Y%m%d");
+    let xml = client.get(format!("https://radiko.jp/v3/program/station/date/{}/{}.xml", date, channel.id)).send().await.unwrap().text().await.unwrap();
+    let program = parse_programs(xml.as_str(), &channel).into_iter().find(|p| p.ft == ft).expect("program not found.");
+    let program = RadioProgram { on_air_music: OnAirMusic::get_on_air_music(program.clone(), client.clone()).await, ..program };
+
+    let segments = resolve_hints(&program, &hints);
+    if segments.is_empty() {
+        println!("no segment found.");
+        return;
+    }
+    let info = TagInfo::from_program(&program, &client).await;
+    for (path, clip_info) in clip_recording(&recording, &out_dir, &segments, &info).unwrap() {
+        write_tags(&path, &clip_info).unwrap();
+        println!("{}: {}", path.display(), clip_info.title);
+    }
+}
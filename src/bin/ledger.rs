@@ -0,0 +1,46 @@
+use std::env;
+use radiko_cacher::ledger::{DownloadStatus, Ledger, LedgerEntry};
+
+fn usage() -> ! {
+    eprintln!("usage: ledger <list [queued|in_progress|done|failed|deleted|cancelled]|show KEY|reset KEY>");
+    std::process::exit(2)
+}
+
+fn status_name(status: &DownloadStatus) -> &'static str {
+    match status {
+        DownloadStatus::Queued => "queued",
+        DownloadStatus::InProgress => "in_progress",
+        DownloadStatus::Done => "done",
+        DownloadStatus::Failed { .. } => "failed",
+        DownloadStatus::Deleted { .. } => "deleted",
+        DownloadStatus::Cancelled { .. } => "cancelled",
+    }
+}
+
+fn print_entry(key: &str, entry: &LedgerEntry) {
+    let detail = match &entry.status {
+        DownloadStatus::Failed { reason } | DownloadStatus::Deleted { reason } | DownloadStatus::Cancelled { reason } => reason.clone(),
+        _ => entry.file_path.as_ref().map(|p| p.display().to_string()).unwrap_or_default(),
+    };
+    println!("{key}\t{}\t{}\t{}\t{}", status_name(&entry.status), entry.attempts, entry.title, detail);
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let mut ledger = Ledger::open(env::var("RADIKO_LEDGER").unwrap_or("download_ledger.json".to_owned())).unwrap();
+    match args.iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+        ["list"] => ledger.entries.iter().for_each(|(key, entry)| print_entry(key, entry)),
+        ["list", status] => ledger.entries.iter().filter(|(_, entry)| status_name(&entry.status) == *status)
+            .for_each(|(key, entry)| print_entry(key, entry)),
+        ["show", key] => match ledger.entries.get(*key) {
+            None => println!("{key} not found."),
+            Some(entry) => println!("{}", serde_json::to_string_pretty(entry).unwrap()),
+        },
+        ["reset", key] => {
+            ledger.entries.remove(*key);
+            ledger.save().unwrap();
+            println!("{key} removed.");
+        }
+        _ => usage(),
+    }
+}
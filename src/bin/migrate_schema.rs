@@ -0,0 +1,83 @@
+use std::env;
+use std::path::PathBuf;
+use firestore::{FirestoreDb, FirestoreDbOptions};
+use futures::{StreamExt, TryStreamExt};
+use kdam::tqdm;
+use serde_json::Value;
+use unicode_normalization::UnicodeNormalization;
+use radiko_cacher::matcher::MatchedProgram;
+use radiko_cacher::expiry::ExpiryPolicy;
+use radiko_cacher::firestore_write::write_documents;
+use radiko_cacher::review::ReviewBook;
+use radiko_cacher::schema::{merge_legacy, program_writes, CATALOG_DOC, DATA_ROOT, LEGACY_PROGRAMS_DOC};
+
+// 旧形式(該当者ごとに番組をコピー)から、programs + matches の形式へ移す
+#[tokio::main]
+async fn main() {
+    let (apply, delete_legacy) = match env::args().skip(1).collect::<Vec<_>>().iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+        [] | ["--dry-run"] => (false, false),
+        ["--apply"] => (true, false),
+        ["--apply", "--delete-legacy"] | ["--delete-legacy", "--apply"] => (true, true),
+        _ => {
+            eprintln!("usage: migrate_schema [--dry-run|--apply [--delete-legacy]]");
+            std::process::exit(2)
+        }
+    };
+    let member_json: Value = serde_json::from_str(include_str!("../../src/members.json").nfkc().collect::<String>().as_str()).unwrap();
+    let expiry_policy: ExpiryPolicy = serde_json::from_str(include_str!("../../src/expiry_rules.json")).unwrap();
+    let firestore_db = FirestoreDb::with_options_service_account_key_file(FirestoreDbOptions::new("hello-radiko".to_owned()), PathBuf::from(env::var("FIRESTORE_CRED_JSON").unwrap())).await.unwrap();
+    let reviews = ReviewBook::load(&firestore_db, DATA_ROOT).await.unwrap();
+    let legacy_parent = firestore_db.parent_path(DATA_ROOT, LEGACY_PROGRAMS_DOC).unwrap();
+
+    let names: Vec<String> = firestore_db.fluent().list().collections().parent(&legacy_parent).stream_all_with_errors().await.unwrap().try_collect().await.unwrap();
+    let mut docs = vec![];
+    let mut unreadable = vec![];
+    for name in tqdm!(names.iter(),desc="Read legacy documents") {
+        // 読めない文書があっても止めずに、最後にまとめて報告する
+        let programs: Vec<_> = firestore_db.fluent().list().from(name).parent(&legacy_parent).obj::<MatchedProgram>().stream_all_with_errors().await.unwrap().collect().await;
+        for program in programs {
+            match program {
+                Ok(program) => docs.push((name.clone(), program)),
+                Err(err) => unreadable.push(format!("{name}: {err}")),
+            }
+        }
+    }
+    println!();
+    for err in &unreadable {
+        println!("unreadable legacy document: {err}");
+    }
+    let copies = docs.len();
+    let merged = merge_legacy(docs);
+    println!("{} names, {copies} legacy documents ({} unreadable) -> {} programs in {DATA_ROOT}/{CATALOG_DOC}", names.len(), unreadable.len(), merged.len());
+    if !apply {
+        println!("(dry run)");
+        return;
+    }
+    let mut program_writes_all = vec![];
+    let mut match_writes_all = vec![];
+    for (program, matched) in &merged {
+        match program_writes(&firestore_db, DATA_ROOT, program, matched, &member_json, &expiry_policy, &reviews) {
+            Ok(Some((program_write, match_writes))) => {
+                program_writes_all.push(program_write);
+                match_writes_all.extend(match_writes);
+            }
+            Ok(None) => {}
+            Err(err) => unreadable.push(format!("{}: {err:#}", program.id)),
+        }
+    }
+    let program_summary = write_documents(&firestore_db, &program_writes_all).await;
+    let match_summary = write_documents(&firestore_db, &match_writes_all).await;
+    println!("programs: {program_summary}");
+    println!("matches: {match_summary}");
+    // 移し損ねたもの・読めなかったものがあるうちは旧形式を消さない
+    if delete_legacy && (program_summary.failed > 0 || match_summary.failed > 0 || !unreadable.is_empty()) {
+        println!("some documents were not migrated; legacy documents are kept.");
+    } else if delete_legacy {
+        for (program, matched) in tqdm!(merged.iter(),desc="Delete legacy documents") {
+            for name in matched.keys() {
+                firestore_db.fluent().delete().from(name.as_str()).parent(&legacy_parent).document_id(program.id.to_string()).execute().await.unwrap();
+            }
+        }
+        println!();
+    }
+}
@@ -0,0 +1,79 @@
+use std::{env, io};
+use std::path::PathBuf;
+use chrono::{DateTime, Local, NaiveDate, TimeDelta, Utc};
+use firestore::{FirestoreDb, FirestoreDbOptions, FirestoreQueryDirection, FirestoreTimestamp};
+use futures::TryStreamExt;
+use reqwest::Client;
+use radiko_cacher::area::{area_station_ids, parse_area};
+use radiko_cacher::play_log::{first_plays, plays_per_group_week, program_breakdown, top_songs, write_rows, PlayLog, PLAY_LOG_COLLECTION};
+use radiko_cacher::radiko::jst;
+
+fn usage() -> ! {
+    eprintln!("usage: play_log <weekly|top-songs|first-plays|programs> [--from YYYY-MM-DD] [--to YYYY-MM-DD] [--group GROUP|MEMBER] [--area JP13] [--limit N] [--format csv|json]");
+    std::process::exit(2)
+}
+
+fn parse_date(s: &str) -> DateTime<Utc> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap_or_else(|_| usage())
+        .and_hms_opt(0, 0, 0).unwrap().and_local_timezone(jst()).unwrap().with_timezone(&Utc)
+}
+
+#[tokio::main]
+async fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let report = args.first().cloned().unwrap_or_else(|| usage());
+    let mut from = Local::now().with_timezone(&Utc) - TimeDelta::weeks(4);
+    let mut to = Local::now().with_timezone(&Utc);
+    let mut group = None;
+    let mut area = None;
+    let mut limit = 50;
+    let mut format = "csv".to_owned();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        let value = rest.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--from" => from = parse_date(value),
+            "--to" => to = parse_date(value) + TimeDelta::days(1),
+            "--group" => group = Some(value.clone()),
+            "--area" => area = Some(parse_area(value).unwrap_or_else(|_| usage())),
+            "--limit" => limit = value.parse().unwrap_or_else(|_| usage()),
+            "--format" => format = value.clone(),
+            _ => usage(),
+        }
+    }
+
+    let firestore_db = FirestoreDb::with_options_service_account_key_file(FirestoreDbOptions::new("hello-radiko".to_owned()), PathBuf::from(env::var("FIRESTORE_CRED_JSON").unwrap())).await.unwrap();
+    let parent = firestore_db.parent_path("hello-radiko-data", "play_logs").unwrap();
+    // 初回放送の判定には集計期間より前の履歴も必要
+    let query_from = if report == "first-plays" { DateTime::UNIX_EPOCH } else { from };
+    let logs: Vec<PlayLog> = firestore_db
+        .fluent()
+        .select()
+        .from(PLAY_LOG_COLLECTION)
+        .parent(parent)
+        .filter(|q| q.for_all([
+            q.field("played_at").greater_than_or_equal(FirestoreTimestamp(query_from)),
+            q.field("played_at").less_than(FirestoreTimestamp(to)),
+        ]))
+        .order_by([("played_at", FirestoreQueryDirection::Ascending)])
+        .obj()
+        .stream_query_with_errors().await.unwrap()
+        .try_collect().await.unwrap();
+    // そのエリアで受信できる局の記録だけにする
+    let logs = match area {
+        None => logs,
+        Some(area) => {
+            let ids = area_station_ids(&Client::new(), &area).await.unwrap();
+            logs.into_iter().filter(|log| ids.contains(&log.station_id)).collect()
+        }
+    };
+
+    let stdout = io::stdout();
+    match report.as_str() {
+        "weekly" => write_rows(&plays_per_group_week(&logs, group.as_deref()), &format, stdout),
+        "top-songs" => write_rows(&top_songs(&logs, group.as_deref(), limit), &format, stdout),
+        "first-plays" => write_rows(&first_plays(&logs, group.as_deref(), from), &format, stdout),
+        "programs" => write_rows(&program_breakdown(&logs, group.as_deref()), &format, stdout),
+        _ => usage(),
+    }.unwrap();
+}
@@ -0,0 +1,29 @@
+use std::env;
+use std::path::PathBuf;
+use chrono::Utc;
+use radiko_cacher::ledger::Ledger;
+use radiko_cacher::retention::{apply, deletion_candidates, report, RetentionPolicy};
+
+fn main() {
+    let apply_changes = match env::args().nth(1).as_deref() {
+        None | Some("--dry-run") => false,
+        Some("--apply") => true,
+        _ => {
+            eprintln!("usage: retention [--dry-run|--apply]");
+            std::process::exit(2)
+        }
+    };
+    let policy_path = PathBuf::from(env::var("RADIKO_RETENTION").unwrap_or("retention.json".to_owned()));
+    let Some(policy) = RetentionPolicy::load(&policy_path).unwrap() else {
+        println!("{} not found.", policy_path.display());
+        return;
+    };
+    let mut ledger = Ledger::open(env::var("RADIKO_LEDGER").unwrap_or("download_ledger.json".to_owned())).unwrap();
+    let candidates = deletion_candidates(&ledger, &policy, Utc::now());
+    println!("{}", report(&ledger, &candidates));
+    if apply_changes {
+        apply(&mut ledger, &candidates).unwrap();
+    } else {
+        println!("(dry run)");
+    }
+}
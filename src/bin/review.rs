@@ -0,0 +1,217 @@
+use std::env;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use axum::extract::{Query, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{TimeDelta, Utc};
+use firestore::{FirestoreDb, FirestoreDbOptions};
+use serde::Deserialize;
+use serde_json::Value;
+use unicode_normalization::UnicodeNormalization;
+use radiko_cacher::matcher::Confidence;
+use radiko_cacher::radiko::jst;
+use radiko_cacher::review::{stored_matches, submit, suggest_exclusions, suggestions_as_rules, Review, ReviewBook, Verdict};
+use radiko_cacher::schema::{MatchRecord, DATA_ROOT};
+use radiko_cacher::watchlist::load_watchlists;
+
+fn usage() -> ! {
+    eprintln!("usage: review [--collection ROOT] list [--days N] [--all] [--format text|json]");
+    eprintln!("       review [--collection ROOT] confirm PROGRAM_ID NAME [--reason TEXT]");
+    eprintln!("       review [--collection ROOT] reject PROGRAM_ID NAME --reason TEXT");
+    eprintln!("       review [--collection ROOT] suggest");
+    eprintln!("       review serve [--port N] [--bind ADDR]");
+    eprintln!("       ROOT defaults to {DATA_ROOT}; serve takes ?collection=ROOT on each request");
+    eprintln!("       serve requires RADIKO_REVIEW_TOKEN (sent as a Bearer token) to bind a non-loopback address");
+    std::process::exit(2)
+}
+
+struct Context {
+    db: FirestoreDb,
+    member_json: Value,
+    match_rules: Value,
+    // 書き込み先として指定できるコレクション(既定とウォッチリストのもの)
+    roots: Vec<String>,
+    token: Option<String>,
+}
+
+impl Context {
+    fn root<'a>(&'a self, collection: &'a Option<String>) -> Result<&'a str, (StatusCode, String)> {
+        match collection {
+            None => Ok(DATA_ROOT),
+            Some(root) if self.roots.contains(root) => Ok(root),
+            Some(root) => Err((StatusCode::BAD_REQUEST, format!("unknown collection {root}"))),
+        }
+    }
+}
+
+// まだ誰も見ていないもの。確度の低いものから
+async fn pending(db: &FirestoreDb, root: &str, days: i64, all: bool) -> anyhow::Result<Vec<MatchRecord>> {
+    let since = Utc::now() - TimeDelta::days(days);
+    let mut records = stored_matches(db, root).await?.into_iter().filter(|r| all || (r.verdict.is_none() && r.ft >= since)).collect::<Vec<_>>();
+    records.sort_by(|a, b| a.confidence.cmp(&b.confidence).then(b.ft.cmp(&a.ft)));
+    Ok(records)
+}
+
+#[derive(Deserialize)]
+struct ListParams {
+    days: Option<i64>,
+    #[serde(default)]
+    all: bool,
+    collection: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CollectionParams {
+    collection: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ReviewRequest {
+    program_id: u64,
+    name: String,
+    verdict: Verdict,
+    reason: Option<String>,
+}
+
+type HttpResult<T> = Result<Json<T>, (StatusCode, String)>;
+
+fn internal(err: anyhow::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}"))
+}
+
+// RADIKO_REVIEW_TOKEN があれば、すべてのリクエストに Authorization: Bearer <token> を求める
+async fn authorize(State(ctx): State<Arc<Context>>, request: Request, next: Next) -> Response {
+    let Some(token) = &ctx.token else { return next.run(request).await };
+    let given = request.headers().get(AUTHORIZATION).and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer "));
+    if given == Some(token.as_str()) {
+        next.run(request).await
+    } else {
+        (StatusCode::UNAUTHORIZED, "missing or invalid token").into_response()
+    }
+}
+
+async fn list_matches(State(ctx): State<Arc<Context>>, Query(params): Query<ListParams>) -> HttpResult<Vec<MatchRecord>> {
+    let root = ctx.root(&params.collection)?;
+    pending(&ctx.db, root, params.days.unwrap_or(7), params.all).await.map(Json).map_err(internal)
+}
+
+async fn list_reviews(State(ctx): State<Arc<Context>>, Query(params): Query<CollectionParams>) -> HttpResult<Vec<Review>> {
+    let book = ReviewBook::load(&ctx.db, ctx.root(&params.collection)?).await.map_err(internal)?;
+    Ok(Json(book.reviews.into_values().collect()))
+}
+
+async fn post_review(State(ctx): State<Arc<Context>>, Query(params): Query<CollectionParams>, Json(request): Json<ReviewRequest>) -> HttpResult<Review> {
+    if request.verdict == Verdict::Rejected && request.reason.as_deref().is_none_or(|r| r.trim().is_empty()) {
+        return Err((StatusCode::BAD_REQUEST, "a rejection needs a reason".to_owned()));
+    }
+    let root = ctx.root(&params.collection)?;
+    submit(&ctx.db, root, request.program_id, &request.name, request.verdict, request.reason, &ctx.member_json, &ctx.match_rules).await.map(Json).map_err(internal)
+}
+
+async fn suggestions(State(ctx): State<Arc<Context>>, Query(params): Query<CollectionParams>) -> HttpResult<Value> {
+    let book = ReviewBook::load(&ctx.db, ctx.root(&params.collection)?).await.map_err(internal)?;
+    Ok(Json(suggestions_as_rules(&suggest_exclusions(&book.reviews.into_values().collect::<Vec<_>>()))))
+}
+
+#[tokio::main]
+async fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let (root, args) = match args.as_slice() {
+        [flag, root, rest @ ..] if flag == "--collection" => (root.clone(), rest),
+        args => (DATA_ROOT.to_owned(), args),
+    };
+    let Some((command, rest)) = args.split_first() else { usage() };
+    let member_json: Value = serde_json::from_str(include_str!("../../src/members.json").nfkc().collect::<String>().as_str()).unwrap();
+    let match_rules: Value = serde_json::from_str(include_str!("../../src/match_rules.json").nfkc().collect::<String>().as_str()).unwrap();
+    let firestore_db = FirestoreDb::with_options_service_account_key_file(FirestoreDbOptions::new("hello-radiko".to_owned()), PathBuf::from(env::var("FIRESTORE_CRED_JSON").unwrap())).await.unwrap();
+
+    match command.as_str() {
+        "list" => {
+            let mut days = 7;
+            let mut all = false;
+            let mut format = "text".to_owned();
+            let mut rest = rest.iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--days" => days = rest.next().and_then(|d| d.parse().ok()).unwrap_or_else(|| usage()),
+                    "--all" => all = true,
+                    "--format" => format = rest.next().unwrap_or_else(|| usage()).clone(),
+                    _ => usage(),
+                }
+            }
+            let records = pending(&firestore_db, &root, days, all).await.unwrap();
+            match format.as_str() {
+                "json" => println!("{}", serde_json::to_string_pretty(&records).unwrap()),
+                "text" => for record in &records {
+                    println!("{}\t{}\t{}\t{}\t{}\t{}", record.program_id, record.name,
+                             if record.confidence == Confidence::Low { "low" } else { "high" },
+                             record.ft.with_timezone(&jst()).format("%Y-%m-%d %H:%M"), record.station_id, record.title);
+                },
+                _ => usage(),
+            }
+            eprintln!("{} matches", records.len());
+        }
+        "confirm" | "reject" => {
+            let (program_id, name, reason) = match rest.iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+                [program_id, name] => (*program_id, *name, None),
+                [program_id, name, "--reason", reason] => (*program_id, *name, Some(reason.to_string())),
+                _ => usage(),
+            };
+            let program_id = program_id.parse::<u64>().unwrap_or_else(|_| usage());
+            let verdict = if command == "reject" { Verdict::Rejected } else { Verdict::Confirmed };
+            let review = submit(&firestore_db, &root, program_id, name, verdict, reason, &member_json, &match_rules).await.unwrap();
+            println!("{:?}: {} {} ({})", review.verdict, review.program_id, review.name, review.title);
+            for evidence in &review.evidence {
+                println!("  {}", evidence.context);
+            }
+        }
+        "suggest" => {
+            let book = ReviewBook::load(&firestore_db, &root).await.unwrap();
+            let suggestions = suggest_exclusions(&book.reviews.into_values().collect::<Vec<_>>());
+            for suggestion in &suggestions {
+                eprintln!("{}\t{}\t{} rejections", suggestion.name, suggestion.exclude, suggestion.rejections);
+            }
+            println!("{}", serde_json::to_string_pretty(&suggestions_as_rules(&suggestions)).unwrap());
+        }
+        "serve" => {
+            let mut port = 8080;
+            let mut bind: IpAddr = Ipv4Addr::LOCALHOST.into();
+            let mut rest = rest.iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--port" => port = rest.next().and_then(|p| p.parse().ok()).unwrap_or_else(|| usage()),
+                    "--bind" => bind = rest.next().and_then(|a| a.parse().ok()).unwrap_or_else(|| usage()),
+                    _ => usage(),
+                }
+            }
+            // POST /reviews はFirestoreの文書を消したり書き換えたりするので、トークンなしで外には開けない
+            let token = env::var("RADIKO_REVIEW_TOKEN").ok().filter(|t| !t.is_empty());
+            if token.is_none() && !bind.is_loopback() {
+                eprintln!("refusing to listen on {bind} without RADIKO_REVIEW_TOKEN");
+                std::process::exit(2)
+            }
+            let watchlists = load_watchlists(include_str!("../../src/watchlists.json"), &PathBuf::from(env::var("RADIKO_WATCHLISTS").unwrap_or("watchlists.json".to_owned()))).unwrap();
+            let mut roots = vec![DATA_ROOT.to_owned()];
+            for watchlist in watchlists {
+                if !roots.contains(&watchlist.collection) { roots.push(watchlist.collection); }
+            }
+            let context = Arc::new(Context { db: firestore_db, member_json, match_rules, roots, token });
+            let app = Router::new()
+                .route("/matches", get(list_matches))
+                .route("/reviews", get(list_reviews).post(post_review))
+                .route("/suggestions", get(suggestions))
+                .layer(middleware::from_fn_with_state(context.clone(), authorize))
+                .with_state(context);
+            let listener = tokio::net::TcpListener::bind((bind, port)).await.unwrap();
+            println!("listening on {}", listener.local_addr().unwrap());
+            axum::serve(listener, app).await.unwrap();
+        }
+        _ => usage(),
+    }
+}
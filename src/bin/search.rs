@@ -0,0 +1,82 @@
+use std::env;
+use std::path::PathBuf;
+use chrono::NaiveDate;
+use firestore::{FirestoreDb, FirestoreDbOptions};
+use serde_json::Value;
+use unicode_normalization::UnicodeNormalization;
+use radiko_cacher::archive::{rematch, ProgramArchive, SearchHit, SearchQuery};
+use radiko_cacher::expiry::ExpiryPolicy;
+use radiko_cacher::firestore_write::write_documents;
+use radiko_cacher::radiko::jst;
+use radiko_cacher::review::ReviewBook;
+use radiko_cacher::schema::{program_writes, DATA_ROOT};
+use radiko_cacher::text::Readings;
+
+fn usage() -> ! {
+    eprintln!("usage: search [TEXT] [--station ID] [--from YYYY-MM-DD] [--to YYYY-MM-DD] [--format text|json] [--rematch [--save]]");
+    std::process::exit(2)
+}
+
+#[tokio::main]
+async fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let mut query = SearchQuery::default();
+    let mut format = "text".to_owned();
+    let mut rematch_programs = false;
+    let mut save = false;
+    let mut rest = args.iter();
+    let parse_date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap_or_else(|_| usage());
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--station" => query.station = Some(rest.next().unwrap_or_else(|| usage()).clone()),
+            "--from" => query.from = Some(parse_date(rest.next().unwrap_or_else(|| usage()))),
+            "--to" => query.to = Some(parse_date(rest.next().unwrap_or_else(|| usage()))),
+            "--format" => format = rest.next().unwrap_or_else(|| usage()).clone(),
+            "--rematch" => rematch_programs = true,
+            "--save" => save = true,
+            text if !text.starts_with("--") => query.text = Some(query.text.map(|t| format!("{t} {text}")).unwrap_or(text.to_owned())),
+            _ => usage(),
+        }
+    }
+
+    let readings = Readings::from_json(include_str!("../../src/readings.json")).unwrap();
+    let archive = ProgramArchive::open(env::var("RADIKO_PROGRAM_ARCHIVE").unwrap_or("program_archive".to_owned())).unwrap().with_readings(readings);
+    let hits = archive.search(&query).unwrap();
+    if !rematch_programs {
+        for SearchHit { program, score } in &hits {
+            match format.as_str() {
+                "json" => println!("{}", serde_json::json!({ "score": score, "program": program })),
+                _ => println!("{score:.1}\t{}\t{}\t{}\t{}\t{}", program.ft.with_timezone(&jst()).format("%Y-%m-%d %H:%M"), program.radio_channel.id,
+                              program.radio_channel.name, program.title, program.pfm.clone().unwrap_or_default()),
+            }
+        }
+        eprintln!("{} programs", hits.len());
+        return;
+    }
+    let programs = hits.into_iter().map(|hit| hit.program).collect::<Vec<_>>();
+
+    // 今のメンバー一覧で検索結果をマッチングし直す
+    let member_json: Value = serde_json::from_str(include_str!("../../src/members.json").nfkc().collect::<String>().as_str()).unwrap();
+    let match_rules: Value = serde_json::from_str(include_str!("../../src/match_rules.json").nfkc().collect::<String>().as_str()).unwrap();
+    let matched = rematch(programs, &member_json, &match_rules);
+    for (program, names) in &matched {
+        println!("{}\t{}\t{}\t{}", program.ft.with_timezone(&jst()).format("%Y-%m-%d %H:%M"), program.radio_channel.id, program.title,
+                 names.keys().cloned().collect::<Vec<_>>().join(","));
+    }
+    eprintln!("{} programs matched", matched.len());
+    if save {
+        let expiry_policy: ExpiryPolicy = serde_json::from_str(include_str!("../../src/expiry_rules.json")).unwrap();
+        let firestore_db = FirestoreDb::with_options_service_account_key_file(FirestoreDbOptions::new("hello-radiko".to_owned()), PathBuf::from(env::var("FIRESTORE_CRED_JSON").unwrap())).await.unwrap();
+        let reviews = ReviewBook::load(&firestore_db, DATA_ROOT).await.unwrap();
+        let mut program_writes_all = vec![];
+        let mut match_writes_all = vec![];
+        for (program, names) in &matched {
+            if let Some((program_write, match_writes)) = program_writes(&firestore_db, DATA_ROOT, program, names, &member_json, &expiry_policy, &reviews).unwrap() {
+                program_writes_all.push(program_write);
+                match_writes_all.extend(match_writes);
+            }
+        }
+        println!("programs: {}", write_documents(&firestore_db, &program_writes_all).await);
+        println!("matches: {}", write_documents(&firestore_db, &match_writes_all).await);
+    }
+}
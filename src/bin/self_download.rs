@@ -1,321 +1,195 @@
-use std::collections::HashMap;
-use std::ops::Deref;
-use markup5ever_rcdom::{Handle, NodeData, RcDom};
-use reqwest::{Client, Url};
-use xml5ever::driver::{parse_document, XmlParseOpts};
-use xml5ever::tendril::*;
-use anyhow::{Result, Context};
-use chrono::{Duration, NaiveDate, Local, DateTime, TimeDelta, Utc};
-use std::{env, fmt, process};
-use std::fmt::Formatter;
+use std::env;
 use std::path::PathBuf;
-use std::str::FromStr;
+use reqwest::Client;
+use chrono::{Duration, NaiveDate, Local, Utc};
 use kdam::tqdm;
 use unicode_normalization::UnicodeNormalization;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 use tokio::join;
-use tokio_stream::StreamExt;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct RadioChannel {
-    id: String,
-    name: String,
-    banner_url: String,
-    area_id: String,
-}
-impl RadioChannel {
-    fn from_hashmap(hash_map: HashMap<&str, String>) -> Result<Self> {
-        Ok(RadioChannel {
-            id: hash_map.get("id").context("id not found.")?.clone(),
-            name: hash_map.get("name").context("name not found.")?.clone().nfkc().collect::<_>(),
-            banner_url: hash_map.get("banner").context("banner not found.")?.clone(),
-            area_id: hash_map.get("area_id").context("area_id not found.")?.clone(),
-        })
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct RadioProgram {
-    radio_channel: RadioChannel,
-    id: u64,
-    // #[serde(with = "firestore::serialize_as_timestamp")]
-    ft: String,
-    // #[serde(with = "firestore::serialize_as_timestamp")]
-    // to: DateTime<Utc>,
-    #[serde(serialize_with = "serialize_td", deserialize_with = "deserialize_td")]
-    dur: TimeDelta,
-    title: String,
-    img: Option<String>,
-    info: Option<String>,
-    desc: Option<String>,
-    pfm: Option<String>,
-    // on_air_music: Vec<OnAirMusic>,
-    #[serde(with = "firestore::serialize_as_timestamp")]
-    expire_at: DateTime<Utc>,
-}
-
-// pub fn serialize_dt<S>(datetime: &DateTime<Local>, serializer: S) -> Result<S::Ok, S::Error>
-// where
-//     S: Serializer,
-// {
-//     let s = datetime.to_rfc3339(); // RFC 3339形式で文字列に変換
-//     serializer.serialize_str(&s) // 文字列としてシリアル化
-// }
-//
-// // デシリアライザ
-// pub fn deserialize_dt<'de, D>(deserializer: D) -> Result<DateTime<Local>, D::Error>
-// where
-//     D: Deserializer<'de>,
-// {
-//     DateTime::parse_from_rfc3339(&String::deserialize(deserializer)?)
-//         .map(|dt| dt.with_timezone(&Local))
-//         .map_err(serde::de::Error::custom)
-// }
-//
-pub fn serialize_td<S>(timedelta: &TimeDelta, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    serializer.serialize_i64(timedelta.num_seconds())
-}
-
-pub fn deserialize_td<'de, D>(deserializer: D) -> Result<TimeDelta, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    Ok(TimeDelta::seconds(i64::deserialize(deserializer)?))
-}
-
-impl RadioProgram {
-    fn from_hashmap(hash_map: HashMap<String, Option<String>>, radio_channel: RadioChannel) -> Result<Self> {
-        Ok(RadioProgram {
-            radio_channel,
-            id: hash_map.get("id").context("id not found.")?.clone().unwrap().parse::<u64>()?,
-            ft: hash_map.get("ft").context("ft not found.")?.clone().unwrap(),
-            dur: TimeDelta::seconds(hash_map.get("dur").context("dur not found.")?.clone().unwrap().parse::<i64>()?),
-            title: hash_map.get("title").context("title not found.")?.clone().unwrap().nfkc().collect::<_>(),
-            img: hash_map.get("img").context("img not found.")?.clone(),
-            info: hash_map.get("info").context("info not found.")?.clone().and_then(|s| {
-                let body = format!("<body>{s}</body>");
-                let dom = parse_document(RcDom::default(), Default::default()).from_utf8().read_from(&mut body.as_bytes()).unwrap();
-                let result = node_to_markdown(&dom.document);
-                Some(result.nfkc().collect::<_>())
-            }),
-            desc: hash_map.get("desc").context("desc not found.")?.clone().and_then(|s| {
-                let body = format!("<body>{s}</body>");
-                let dom = parse_document(RcDom::default(), Default::default()).from_utf8().read_from(&mut body.as_bytes()).unwrap();
-                let result = node_to_markdown(&dom.document);
-                Some(result.nfkc().collect::<_>())
-            }),
-            pfm: hash_map.get("pfm").context("pfm not found.")?.clone().and_then(|s| Some(s.nfkc().collect::<_>())),
-            expire_at: DateTime::from(DateTime::parse_from_str((hash_map.get("to").context("to not found.").unwrap().clone().unwrap() + " +0900").as_str(), "%Y%m%d%H%M%S %z")?) + TimeDelta::weeks(2),
-        })
-    }
-}
-impl fmt::Display for RadioProgram {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "RadioProgram(RadioChannel({}, {}, https://...., {}), {}, {}, {}, {},info: {}, desc: ..., {})", self.radio_channel.id, self.radio_channel.name, self.radio_channel.area_id
-               , self.id, self.ft, self.dur.num_minutes(),
-               self.info.clone().unwrap_or_else(|| "None".to_owned()), self.title,
-               self.pfm.clone().unwrap_or_else(|| "None".to_owned()))
-    }
-}
-
-
-fn dig_xml<T>(handle: Handle, path: Vec<&str>, call_func: fn(Handle) -> Option<T>) -> Vec<T> {
-    if path.is_empty() {
-        return match call_func(handle) {
-            None => { vec![] }
-            Some(v) => { vec![v] }
-        };
-    }
-    handle.children.borrow().iter().map(|child| {
-        match &child.data {
-            NodeData::Element { name, .. } => {
-                if path[0] == name.local.deref() {
-                    dig_xml(child.clone(), path[1..].to_owned(), call_func)
-                } else { vec![] }
-            }
-            _ => vec![]
-        }
-    }).flatten().collect::<Vec<_>>()
-}
-
-fn get_below_string(handle: Handle) -> Option<String> {
-    match &handle.children.borrow().clone().get(0) {
-        None => None,
-        Some(h) => {
-            match &h.data {
-                NodeData::Text { contents, .. } => { Some(contents.borrow().clone().to_string()) }
-                _ => None
-            }
-        }
-    }
-}
-
-
-fn node_to_markdown(handle: &Handle) -> String {
-    let dig = |handle: Handle| { handle.children.borrow().clone().into_iter().map(|child| node_to_markdown(&child)).collect::<Vec<_>>().join("") };
-    match &handle.data {
-        NodeData::Document => {
-            dig(handle.clone())
-        }
-        NodeData::Text { contents } => {
-            contents.borrow().to_string()
-        }
-        NodeData::Element { name, attrs, .. } => {
-            match name.local.to_lowercase().as_str() {
-                "a" => {
-                    let mut href = None;
-                    for attr in &attrs.borrow().clone() {
-                        if attr.name.local.deref() == "href" {
-                            href = Some(attr.value.clone());
-                            break;
-                        }
-                    }
-                    match href {
-                        None => { dig(handle.clone()) }
-                        Some(href) => { format!("[{}]({href})", dig(handle.clone())) }
-                    }
-                }
-                "b" | "strong" => format!("**{}**", dig(handle.clone())),
-                "p" => format!("{}\n", dig(handle.clone())),
-                "br" => format!("\n\n{}", dig(handle.clone())),
-                _ => dig(handle.clone()),
-            }
-        }
-        NodeData::Comment { .. } => String::new(),
-        elm => {
-            println!("err!:{:?}", elm);
-            String::new()
-        }
-    }
-}
+use radiko_cacher::area::{area_name, receivable_channels};
+use radiko_cacher::ledger::{DownloadStatus, Ledger};
+use radiko_cacher::loudness::measure;
+use radiko_cacher::watchlist::load_watchlists;
+use radiko_cacher::radiko::{jst, parse_channels, parse_programs, OnAirMusic, RadioProgram};
+use radiko_cacher::retention::{apply, deletion_candidates, report, RetentionPolicy};
+use radiko_cacher::recorder::{is_auth_error, record, RadikoAuth};
+use radiko_cacher::output_path::{output_paths, place_file, DEFAULT_TEMPLATE};
+use radiko_cacher::tagging::{write_tags, TagInfo};
+use radiko_cacher::transcode::{transcode, TranscodeConfig};
+use radiko_cacher::timefree::{format_remaining, is_available, is_expiring, plan, remaining, TIMEFREE_WINDOW};
 
 #[tokio::main]
 async fn main() {
     let client = Client::new();
 
-    let doc = parse_document(RcDom::default(), XmlParseOpts::default()).from_utf8().read_from(
-        &mut client.get("https://radiko.jp/v3/station/region/full.xml").send().await.unwrap().text().await.unwrap().as_bytes()
-    ).unwrap();
-    let channels_hashmap = dig_xml(doc.document, vec!["region", "stations", "station"], |handle| {
-        match &handle.data {
-            NodeData::Element { .. } => {
-                Some(handle.children.borrow().clone().into_iter().filter_map(|child| {
-                    match &child.data {
-                        NodeData::Element { name, .. } => {
-                            match name.local.deref() {
-                                "id" => { Some(("id", get_below_string(child).unwrap())) }
-                                "name" => { Some(("name", get_below_string(child).unwrap())) }
-                                "banner" => { Some(("banner", get_below_string(child).unwrap())) }
-                                "area_id" => { Some(("area_id", get_below_string(child).unwrap())) }
-                                _ => None
-                            }
-                        }
-                        _ => None
-                    }
-                }).collect::<HashMap<_, _>>())
-            }
-            _ => None
-        }
-    });
-    let channels = channels_hashmap.into_iter().map(|hash_map| RadioChannel::from_hashmap(hash_map).unwrap()).collect::<Vec<_>>();
+    let channels = parse_channels(client.get("https://radiko.jp/v3/station/region/full.xml").send().await.unwrap().text().await.unwrap().as_str());
     // for channel in &channels {
     //     println!("{:?}", channel)
     // }
+    // 認証で判定されたエリアで受信できる局しかタイムフリーで取れないので、それ以外の番組表は見ない
+    let auth = RadikoAuth::authorize(&client).await.unwrap();
+    let channels = receivable_channels(&client, &channels, &auth.area_id).await.unwrap();
+    println!("area: {} ({}), {} stations", auth.area_id, area_name(&auth.area_id).unwrap_or("?"), channels.len());
 
-    let program_joiner = channels.iter().map(|channel| NaiveDate::from((Local::now() - Duration::days(1)).naive_local()).iter_days().take(1).map(|date| {
+    // タイムフリーで聴ける期間(過去1週間)を全部見て、取りこぼしを拾う
+    let program_joiner = channels.iter().flat_map(|channel| NaiveDate::from((Local::now() - TIMEFREE_WINDOW - Duration::days(1)).naive_local()).iter_days().take(TIMEFREE_WINDOW.num_days() as usize + 2).map(|date| {
         (channel.clone(), client.get(format!("https://radiko.jp/v3/program/station/date/{}/{}.xml", date.format("%Y%m%d"), channel.id)).send())
-    })).flatten().collect::<Vec<_>>();
+    })).collect::<Vec<_>>();
 
-    let mut programs = vec![];
+    let mut fetched = vec![];
     for (channel, req) in tqdm!(program_joiner.into_iter(),desc="Parse XML") {
         // if channel.id != "JORF" { continue; }
-        let doc = parse_document(RcDom::default(), XmlParseOpts::default()).from_utf8().read_from(&mut req.await.unwrap().text().await.unwrap().as_bytes()).unwrap();
-        let programs_hashmaps = dig_xml(doc.document, vec!["radiko", "stations", "station", "progs", "prog"], |handle| match &handle.data {
-            NodeData::Element { attrs, .. } => {
-                let mut program_meta_hashmap = handle.children.borrow().clone().into_iter().filter_map(|child| {
-                    match &child.data {
-                        NodeData::Element { name, .. } => {
-                            match name.local.deref() {
-                                "title" => { Some(("title".to_owned(), get_below_string(child))) }
-                                "img" => { Some(("img".to_owned(), get_below_string(child))) }
-                                "info" => { Some(("info".to_owned(), get_below_string(child))) }
-                                "desc" => { Some(("desc".to_owned(), get_below_string(child))) }
-                                "pfm" => { Some(("pfm".to_owned(), get_below_string(child))) }
-                                _ => None
-                            }
-                        }
-                        _ => None
-                    }
-                }).collect::<HashMap<_, _>>();
-                let program_date_hashmap = attrs.borrow().clone().into_iter().map(|v| (v.name.local.to_string(), Some(v.value.to_string()))).collect::<HashMap<_, _>>();
-                program_meta_hashmap.extend(program_date_hashmap);
-                Some(program_meta_hashmap)
-            }
-            _ => None
-        });
-        programs.extend(programs_hashmaps.into_iter().filter_map(|hash_map: HashMap<_, _>| {
-            match RadioProgram::from_hashmap(hash_map, channel.clone()) {
-                Ok(v) => {
-                    Some(v)
-                }
-                Err(_) => { None }
-            }
-        }).collect::<Vec<_>>());
+        fetched.extend(parse_programs(req.await.unwrap().text().await.unwrap().as_str(), &channel));
     }
-
+    println!();
+    // 休止・振替で番組表から消えたものは、取りこぼしとして録り直さない
+    let mut ledger = Ledger::open(env::var("RADIKO_LEDGER").unwrap_or("download_ledger.json".to_owned())).unwrap();
+    for key in ledger.cancel_vanished(&fetched).unwrap() {
+        println!("cancelled: {key} is no longer in the schedule");
+    }
+    let programs = fetched.into_iter().filter(|program| is_available(program, Utc::now())).collect::<Vec<_>>();
+    let on_airs = programs.into_iter().map(|program| tokio::spawn({
+        let client = client.clone();
+        async move {
+            (OnAirMusic::get_on_air_music(program.clone(), client), program.clone())
+        }
+    })).collect::<Vec<_>>();
+    let mut programs = vec![];
+    for awaiter in tqdm!(on_airs.into_iter(),desc="Get On Air Music") {
+        let (on_air, program) = join!(awaiter).0.unwrap();
+        programs.push(RadioProgram { on_air_music: on_air.await, ..program })
+    };
 
     let member_json: Value = serde_json::from_str(include_str!("../../src/members.json").nfkc().collect::<String>().as_str()).unwrap();
+    let match_rules: Value = serde_json::from_str(include_str!("../../src/match_rules.json").nfkc().collect::<String>().as_str()).unwrap();
     // let firestore_db = FirestoreDb::with_options_service_account_key_file(FirestoreDbOptions::new("hello-radiko".to_owned()), PathBuf::from(env::var("FIRESTORE_CRED_JSON").unwrap())).await.unwrap();
 
+    let archive_dir = PathBuf::from(env::var("RADIKO_ARCHIVE_DIR").unwrap_or(".".to_owned()));
+    let output_template = env::var("RADIKO_OUTPUT_TEMPLATE").unwrap_or(DEFAULT_TEMPLATE.to_owned());
+    let transcode_config = TranscodeConfig::load(&PathBuf::from(env::var("RADIKO_TRANSCODE").unwrap_or("transcode.json".to_owned()))).unwrap();
+    let watchlists = load_watchlists(include_str!("../../src/watchlists.json"), &PathBuf::from(env::var("RADIKO_WATCHLISTS").unwrap_or("watchlists.json".to_owned()))).unwrap();
+    let mut queue = vec![];
     for program in programs {
-        let res = search_artist(program.clone(), member_json.clone());
+        // どのウォッチリストに当たっても録る
+        let mut res = vec![];
+        for watchlist in &watchlists {
+            res.extend(watchlist.match_program(&program, &member_json, &match_rules).unwrap());
+        }
         if !res.is_empty() {
-            let prog = program.clone();
-            println!("{},{}:{:?}", prog.title.clone(), prog.pfm.clone().unwrap_or("".to_owned()), res);
-            println!("{}", serde_json::to_string(&program.clone()).unwrap());
-            process::Command::new("yt-dlp").args(vec!["--no-progress".to_owned(), format!("https://radiko.jp/#!/ts/{}/{}", prog.radio_channel.id, prog.ft)]).spawn().unwrap().wait().unwrap();
+            println!("{},{}:{:?}", program.title.clone(), program.pfm.clone().unwrap_or("".to_owned()), res);
+            if !ledger.should_download(&program) {
+                println!("skip: {:?}", ledger.get(&program).map(|entry| &entry.status));
+                if ledger.get(&program).map(|entry| entry.status != DownloadStatus::Done).unwrap_or(false) && is_expiring(&program, Utc::now()) {
+                    println!("WARNING: {} ({}) expires in {} and retries are exhausted", program.title, program.radio_channel.id, format_remaining(remaining(&program, Utc::now())));
+                }
+                continue;
+            }
+            ledger.mark_queued(&program).unwrap();
+            let mut names = vec![];
+            for (name, _) in res {
+                if !names.contains(&name) { names.push(name); }
+            }
+            queue.push((program, names));
         }
     }
-}
 
-
-fn search_artist(radio_program: RadioProgram, member_json: Value) -> Vec<String> {
-    let mut found = vec![];
-    let _ = member_json.as_object().unwrap().into_iter().map(|(group_name, members)| {
-        // println!("{group_name}:{members}");
-        if group_name != "OG" {
-            if radio_program.title.contains(group_name)
-                || radio_program.desc.clone().map(|t| { t.contains(group_name) }).unwrap_or(false)
-                || radio_program.info.clone().map(|t| { t.contains(group_name) }).unwrap_or(false)
-                || radio_program.pfm.clone().map(|t| { t.contains(group_name) }).unwrap_or(false) {
-                found.push(group_name.to_owned())
+    let queue = plan(queue, Utc::now());
+    for (prog, _) in &queue {
+        println!("queued: {} {} ({}) remaining {}", prog.ft.with_timezone(&jst()).format("%m/%d %H:%M"), prog.title, prog.radio_channel.id, format_remaining(remaining(prog, Utc::now())));
+    }
+    // 番組表の取得に時間がかかるとトークンが切れるので取り直す
+    let mut auth = RadikoAuth::authorize(&client).await.unwrap();
+    let mut expiring = vec![];
+    for (prog, names) in queue {
+        println!("{}", serde_json::to_string(&prog.clone()).unwrap());
+        ledger.mark_in_progress(&prog).unwrap();
+        // キューが長いと途中でトークンが切れるので、401/403なら取り直して続きから録る
+        let recorded = match record(&client, &auth, &prog, &archive_dir.join(".partial")).await {
+            Err(err) if is_auth_error(&err) => {
+                println!("auth token rejected, re-authorizing: {err:#}");
+                match RadikoAuth::authorize(&client).await {
+                    Ok(renewed) => {
+                        auth = renewed;
+                        record(&client, &auth, &prog, &archive_dir.join(".partial")).await
+                    }
+                    Err(auth_err) => Err(auth_err.context("re-authorization failed")),
+                }
             }
-        }
-        let _ = members.as_object().unwrap().into_iter().map(|(member_name, literals)| {
-            for literal in literals.as_array().unwrap() {
-                let literal_string = literal.as_str().unwrap();
-                // println!("literal_string:{}", literal_string);
-                if radio_program.title.contains(literal_string)
-                    || radio_program.desc.clone().map(|t| { t.contains(literal_string) }).unwrap_or(false)
-                    || radio_program.info.clone().map(|t| { t.contains(literal_string) }).unwrap_or(false)
-                    || radio_program.pfm.clone().map(|t| { t.contains(literal_string) }).unwrap_or(false) {
-                    if member_name == "高橋愛" {
-                        if radio_program.title.contains("高橋愛子")
-                            || radio_program.desc.clone().map(|t| { t.contains("高橋愛子") }).unwrap_or(false)
-                            || radio_program.info.clone().map(|t| { t.contains("高橋愛子") }).unwrap_or(false)
-                            || radio_program.pfm.clone().map(|t| { t.contains("高橋愛子") }).unwrap_or(false) {
-                            break;
+            recorded => recorded,
+        };
+        let filepath = match recorded {
+            Ok(filepath) => filepath,
+            Err(err) => {
+                println!("recording failed: {err:#}");
+                ledger.mark_failed(&prog, format!("record: {err:#}")).unwrap();
+                if is_expiring(&prog, Utc::now()) { expiring.push(prog); }
+                continue;
+            }
+        };
+        // 局ごとの音量差をならすため、変換前の録音でラウドネスを測っておく
+        let loudness = match measure(&filepath) {
+            Ok(loudness) => {
+                println!("loudness: {:.1} LUFS, peak {:.3}", loudness.integrated_lufs, loudness.peak);
+                Some(loudness)
+            }
+            Err(err) => {
+                println!("loudness measurement failed: {err:#}");
+                None
+            }
+        };
+        // 設定があれば番組ごとのプロファイルでm4aへの詰め替えや再エンコードをする
+        let (filepath, gain) = match transcode_config.as_ref().and_then(|config| config.profile_for(&prog, &names)) {
+            Some((profile_name, profile)) => match transcode(&filepath, &prog, profile, loudness.as_ref()) {
+                Ok((transcoded, gain)) => {
+                    println!("transcoded ({profile_name}, gain {gain:+.2} dB): {}", transcoded.display());
+                    if transcoded != filepath {
+                        if let Err(err) = std::fs::remove_file(&filepath) {
+                            println!("failed to remove {}: {err}", filepath.display());
                         }
                     }
-                    found.push(member_name.to_owned());
-                    break;
+                    (transcoded, gain)
+                }
+                Err(err) => {
+                    println!("transcoding failed ({profile_name}): {err:#}");
+                    ledger.mark_failed(&prog, format!("transcode: {err:#}")).unwrap();
+                    if is_expiring(&prog, Utc::now()) { expiring.push(prog); }
+                    continue;
                 }
+            },
+            None => (filepath, 0.0),
+        };
+        // ハードリンクを張る前にタグを書いておく
+        if let Some(loudness) = loudness {
+            ledger.set_loudness(&prog, loudness, gain).unwrap();
+        }
+        let info = TagInfo { loudness: loudness.map(|l| l.with_gain(gain)), ..TagInfo::from_program(&prog, &client).await };
+        match write_tags(&filepath, &info) {
+            Ok(_) => println!("tagged: {}", filepath.display()),
+            Err(err) => println!("tagging failed: {}: {err}", filepath.display()),
+        }
+        let ext = filepath.extension().and_then(|e| e.to_str()).unwrap_or("aac").to_owned();
+        let placed = match output_paths(&output_template, &archive_dir, &prog, &names, &member_json, &ext).and_then(|targets| place_file(&filepath, &targets)) {
+            Ok(placed) => placed,
+            Err(err) => {
+                println!("failed to place {}: {err}", filepath.display());
+                ledger.mark_failed(&prog, format!("output: {err}")).unwrap();
+                if is_expiring(&prog, Utc::now()) { expiring.push(prog); }
+                continue;
             }
-        }).collect::<Vec<_>>();
-    }).collect::<Vec<_>>();
-    found
-}
\ No newline at end of file
+        };
+        placed.iter().for_each(|path| println!("saved: {}", path.display()));
+        ledger.mark_done(&prog, &placed, &names).unwrap();
+    }
+    for prog in expiring {
+        println!("WARNING: {} ({}) expires in {} without a successful download", prog.title, prog.radio_channel.id, format_remaining(remaining(&prog, Utc::now())));
+    }
+
+    // 容量・保存期間の上限を超えた分を消す
+    if let Some(policy) = RetentionPolicy::load(&PathBuf::from(env::var("RADIKO_RETENTION").unwrap_or("retention.json".to_owned()))).unwrap() {
+        let candidates = deletion_candidates(&ledger, &policy, Utc::now());
+        if !candidates.is_empty() {
+            println!("{}", report(&ledger, &candidates));
+            apply(&mut ledger, &candidates).unwrap();
+        }
+    }
+}
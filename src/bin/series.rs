@@ -0,0 +1,151 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use radiko_cacher::archive::ProgramArchive;
+use radiko_cacher::radiko::jst;
+use radiko_cacher::series::{detect, Gap, Series, SeriesHistory, MIN_EPISODES};
+use radiko_cacher::watchlist::{Target, Watchlist};
+
+fn usage() -> ! {
+    eprintln!("usage: series [--station ID] [--min-episodes N] [--missed] [--format text|json]");
+    eprintln!("       series show ID [--format text|json]");
+    eprintln!("       series subscribe ID [--watchlist NAME] [--collection ROOT]");
+    eprintln!("       series unsubscribe ID [--watchlist NAME]");
+    eprintln!("       ID includes the weekday and start time, so a series that moves to another slot gets a new ID");
+    std::process::exit(2)
+}
+
+fn weekdays(series: &Series) -> String {
+    series.weekdays.iter().map(|w| w.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn summary(series: &Series) -> String {
+    let preempted = series.missed.iter().filter(|m| matches!(m.gap, Gap::Preempted { .. })).count();
+    format!("{}\t{}\t{}\t{}\t{} episodes\t{} preempted, {} missing\t{}", series.id, series.station_id, weekdays(series), series.start.format("%H:%M"),
+            series.episodes.len(), preempted, series.missed.len() - preempted, series.title)
+}
+
+fn print_history(series: &Series) {
+    println!("{}", summary(series));
+    let mut rows = series.episodes.iter().map(|e| (e.ft, format!("aired\t{} ({})", e.title, e.program_id)))
+        .chain(series.missed.iter().map(|m| (m.expected, match &m.gap {
+            Gap::Missing => "MISSING".to_owned(),
+            Gap::Preempted { program_id, title } => format!("PREEMPTED\tby {title} ({program_id})"),
+        })))
+        .collect::<Vec<_>>();
+    rows.sort_by_key(|(ft, _)| *ft);
+    for (ft, row) in rows {
+        println!("  {}\t{row}", ft.with_timezone(&jst()).format("%Y-%m-%d %a %H:%M"));
+    }
+}
+
+// シリーズの購読は、RADIKO_WATCHLISTS のウォッチリストにシリーズのターゲットを足すことで行う
+fn edit_watchlists(edit: impl FnOnce(&mut Vec<Watchlist>)) {
+    let path = PathBuf::from(env::var("RADIKO_WATCHLISTS").unwrap_or("watchlists.json".to_owned()));
+    let mut watchlists: Vec<Watchlist> = match fs::read_to_string(&path) {
+        Ok(s) => serde_json::from_str(&s).unwrap(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(err) => panic!("{err}"),
+    };
+    edit(&mut watchlists);
+    watchlists.retain(|w| w.hello_project || !w.targets.is_empty());
+    fs::write(&path, serde_json::to_string_pretty(&watchlists).unwrap()).unwrap();
+    println!("updated {}", path.display());
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let archive = ProgramArchive::open(env::var("RADIKO_PROGRAM_ARCHIVE").unwrap_or("program_archive".to_owned())).unwrap();
+    let mut programs = vec![];
+    for date in archive.days().unwrap() {
+        programs.extend(archive.load_day(date).unwrap());
+    }
+
+    match args.iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+        ["show", id, rest @ ..] => {
+            // 購読中のシリーズは、アーカイブから消えた回も記録から出す
+            let mut history = SeriesHistory::open(env::var("RADIKO_SERIES_HISTORY").unwrap_or("series_history.json".to_owned())).unwrap();
+            if let Some(found) = detect(&programs, 1).into_iter().find(|s| s.id == *id) {
+                history.merge(found, programs.iter().map(|p| p.ft).min().unwrap());
+            }
+            let series = history.series.remove(*id).unwrap_or_else(|| {
+                eprintln!("no series {id} in the archive");
+                std::process::exit(1)
+            });
+            match rest {
+                [] | ["--format", "text"] => print_history(&series),
+                ["--format", "json"] => println!("{}", serde_json::to_string_pretty(&series).unwrap()),
+                _ => usage(),
+            }
+        }
+        [command @ ("subscribe" | "unsubscribe"), id, rest @ ..] => {
+            let mut watchlist_name = "series".to_owned();
+            let mut collection = "radiko-series".to_owned();
+            let mut rest = rest.iter();
+            while let Some(arg) = rest.next() {
+                match *arg {
+                    "--watchlist" => watchlist_name = rest.next().unwrap_or_else(|| usage()).to_string(),
+                    "--collection" if *command == "subscribe" => collection = rest.next().unwrap_or_else(|| usage()).to_string(),
+                    _ => usage(),
+                }
+            }
+            let id = id.to_string();
+            if *command == "unsubscribe" {
+                edit_watchlists(|watchlists| {
+                    for watchlist in watchlists.iter_mut().filter(|w| w.name == watchlist_name) {
+                        watchlist.targets.retain(|t| t.series != [id.clone()]);
+                    }
+                });
+                return;
+            }
+            let all = detect(&programs, 1);
+            let Some(series) = all.iter().find(|s| s.id == id) else {
+                eprintln!("no series {id} in the archive");
+                std::process::exit(1)
+            };
+            println!("{}", summary(series));
+            // 購読は枠ごと。同じ番組名のほかの枠は自動では追わない
+            println!("note: subscribed to the {} {} slot only; if the program moves, subscribe to the new ID", weekdays(series), series.start.format("%H:%M"));
+            for other in all.iter().filter(|s| s.id != id && s.station_id == series.station_id && s.title_key == series.title_key) {
+                println!("  same title in another slot: {}", summary(other));
+            }
+            edit_watchlists(|watchlists| {
+                if !watchlists.iter().any(|w| w.name == watchlist_name) {
+                    watchlists.push(Watchlist { name: watchlist_name.clone(), collection, hello_project: false, targets: vec![], notify: vec![] });
+                }
+                let watchlist = watchlists.iter_mut().find(|w| w.name == watchlist_name).unwrap();
+                if !watchlist.targets.iter().any(|t| t.series.contains(&id)) {
+                    watchlist.targets.push(Target::for_series(&series.title, &id));
+                }
+            });
+        }
+        rest => {
+            let mut station = None;
+            let mut min_episodes = MIN_EPISODES;
+            let mut missed_only = false;
+            let mut format = "text";
+            let mut rest = rest.iter();
+            while let Some(arg) = rest.next() {
+                match *arg {
+                    "--station" => station = Some(*rest.next().unwrap_or_else(|| usage())),
+                    "--min-episodes" => min_episodes = rest.next().and_then(|n| n.parse().ok()).unwrap_or_else(|| usage()),
+                    "--missed" => missed_only = true,
+                    "--format" => format = rest.next().unwrap_or_else(|| usage()),
+                    _ => usage(),
+                }
+            }
+            let found = detect(&programs, min_episodes).into_iter()
+                .filter(|s| station.is_none_or(|station| s.station_id == station))
+                .filter(|s| !missed_only || !s.missed.is_empty())
+                .collect::<Vec<_>>();
+            match format {
+                "json" => println!("{}", serde_json::to_string_pretty(&found).unwrap()),
+                "text" => for series in &found {
+                    println!("{}", summary(series));
+                },
+                _ => usage(),
+            }
+            eprintln!("{} series", found.len());
+        }
+    }
+}
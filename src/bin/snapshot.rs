@@ -0,0 +1,126 @@
+use std::env;
+use std::path::PathBuf;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde_json::Value;
+use unicode_normalization::UnicodeNormalization;
+use radiko_cacher::radiko::jst;
+use radiko_cacher::snapshot::{describe, diff, ProgramDiff, Snapshot, SnapshotStore};
+use radiko_cacher::watchlist::load_watchlists;
+
+fn usage() -> ! {
+    eprintln!("usage: snapshot list [--station ID] [--date YYYY-MM-DD]");
+    eprintln!("       snapshot diff (--station ID | --member NAME) [--date YYYY-MM-DD] [--from TIME] [--to TIME] [--format text|json]");
+    eprintln!("       TIME is JST \"YYYY-MM-DD HH:MM\"; by default the last two snapshots are compared");
+    std::process::exit(2)
+}
+
+fn parse_time(s: &str) -> DateTime<Utc> {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap_or_else(|_| usage()).and_local_timezone(jst()).unwrap().with_timezone(&Utc)
+}
+
+fn fetched(snapshot: &Snapshot) -> String {
+    snapshot.fetched_at.with_timezone(&jst()).format("%Y-%m-%d %H:%M").to_string()
+}
+
+// 比べる2つ。--from/--to がなければ最後の2つ
+fn pair(store: &SnapshotStore, station_id: &str, date: NaiveDate, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Option<(Snapshot, Snapshot)> {
+    let times = store.fetch_times(station_id, date).unwrap();
+    let after = match to {
+        Some(to) => store.at(station_id, date, to).unwrap()?,
+        None => store.load(station_id, date, *times.last()?).unwrap(),
+    };
+    let before = match from {
+        Some(from) => store.at(station_id, date, from).unwrap()?,
+        None => store.load(station_id, date, *times.iter().rev().find(|t| **t < after.fetched_at)?).unwrap(),
+    };
+    (before.fetched_at < after.fetched_at).then_some((before, after))
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let store = SnapshotStore::open(env::var("RADIKO_SNAPSHOT_DIR").unwrap_or("schedule_snapshots".to_owned())).unwrap();
+    let (command, rest) = args.split_first().unwrap_or_else(|| usage());
+
+    let mut station = None;
+    let mut member = None;
+    let mut date = None;
+    let mut from = None;
+    let mut to = None;
+    let mut format = "text".to_owned();
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().unwrap_or_else(|| usage()).clone();
+        match arg.as_str() {
+            "--station" => station = Some(value()),
+            "--date" => date = Some(NaiveDate::parse_from_str(&value(), "%Y-%m-%d").unwrap_or_else(|_| usage())),
+            "--member" if command == "diff" => member = Some(value()),
+            "--from" if command == "diff" => from = Some(parse_time(&value())),
+            "--to" if command == "diff" => to = Some(parse_time(&value())),
+            "--format" if command == "diff" => format = value(),
+            _ => usage(),
+        }
+    }
+    let stations = match &station {
+        Some(station) => vec![station.clone()],
+        None => store.stations().unwrap(),
+    };
+    let dates = |station_id: &str| match date {
+        Some(date) => vec![date],
+        None => store.dates(station_id).unwrap(),
+    };
+
+    match command.as_str() {
+        "list" => for station_id in &stations {
+            for date in dates(station_id) {
+                for fetched_at in store.fetch_times(station_id, date).unwrap() {
+                    let snapshot = store.load(station_id, date, fetched_at).unwrap();
+                    println!("{station_id}\t{}\t{}\t{} programs", date.format("%Y-%m-%d"), fetched(&snapshot), snapshot.programs.len());
+                }
+            }
+        },
+        "diff" => {
+            if station.is_none() == member.is_none() {
+                usage();
+            }
+            // メンバー・ターゲット名は、ウォッチリストのマッチで番組を絞る(オンエア曲は見ない)
+            let member_json: Value = serde_json::from_str(include_str!("../../src/members.json").nfkc().collect::<String>().as_str()).unwrap();
+            let match_rules: Value = serde_json::from_str(include_str!("../../src/match_rules.json").nfkc().collect::<String>().as_str()).unwrap();
+            let watchlists = load_watchlists(include_str!("../../src/watchlists.json"), &PathBuf::from(env::var("RADIKO_WATCHLISTS").unwrap_or("watchlists.json".to_owned()))).unwrap();
+            let concerns = |d: &ProgramDiff| member.as_ref().is_none_or(|member| d.programs().into_iter().any(|program| {
+                watchlists.iter().any(|w| w.match_program(program, &member_json, &match_rules).unwrap().contains_key(member))
+            }));
+
+            let mut found = vec![];
+            for station_id in &stations {
+                for date in dates(station_id) {
+                    let Some((before, after)) = pair(&store, station_id, date, from, to) else { continue };
+                    let diffs = diff(&before.programs, &after.programs).into_iter().filter(|d| concerns(d)).collect::<Vec<_>>();
+                    if !diffs.is_empty() {
+                        found.push((before, after, diffs));
+                    }
+                }
+            }
+            match format.as_str() {
+                "json" => {
+                    let json = found.iter().map(|(before, after, diffs)| serde_json::json!({
+                        "station_id": after.station_id,
+                        "date": after.date,
+                        "from": before.fetched_at,
+                        "to": after.fetched_at,
+                        "changes": diffs,
+                    })).collect::<Vec<_>>();
+                    println!("{}", serde_json::to_string_pretty(&json).unwrap());
+                }
+                "text" => for (before, after, diffs) in &found {
+                    println!("{} {} ({} -> {})", after.station_id, after.date.format("%Y-%m-%d"), fetched(before), fetched(after));
+                    for line in diffs.iter().flat_map(describe) {
+                        println!("  {line}");
+                    }
+                },
+                _ => usage(),
+            }
+            eprintln!("{} changes", found.iter().map(|(_, _, diffs)| diffs.len()).sum::<usize>());
+        }
+        _ => usage(),
+    }
+}
@@ -0,0 +1,55 @@
+use std::env;
+use reqwest::Client;
+use radiko_cacher::area::{area_name, area_station_ids, parse_area, parse_regions};
+use radiko_cacher::radiko::parse_channels;
+use radiko_cacher::recorder::RadikoAuth;
+
+fn usage() -> ! {
+    eprintln!("usage: stations [--area JP13|13|東京|here] [--region REGION_ID]");
+    std::process::exit(2)
+}
+
+#[tokio::main]
+async fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let mut area = None;
+    let mut region = None;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let value = rest.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--area" => area = Some(value.clone()),
+            "--region" => region = Some(value.clone()),
+            _ => usage(),
+        }
+    }
+
+    let client = Client::new();
+    let xml = client.get("https://radiko.jp/v3/station/region/full.xml").send().await.unwrap().text().await.unwrap();
+    let channels = parse_channels(&xml);
+    let regions = parse_regions(&xml, &channels);
+    // hereなら認証で判定されたエリアを使う
+    let area_id = match area.as_deref() {
+        None => None,
+        Some("here") => Some(RadikoAuth::authorize(&client).await.unwrap().area_id),
+        Some(area) => Some(parse_area(area).unwrap_or_else(|err| {
+            eprintln!("{err}");
+            usage()
+        })),
+    };
+    let receivable = match &area_id {
+        None => None,
+        Some(area_id) => {
+            println!("# {area_id} ({})", area_name(area_id).unwrap_or("?"));
+            Some(area_station_ids(&client, area_id).await.unwrap())
+        }
+    };
+    for r in regions.iter().filter(|r| region.as_ref().map(|id| &r.id == id).unwrap_or(true)) {
+        let stations = r.stations.iter().filter(|c| receivable.as_ref().map(|ids| ids.contains(&c.id)).unwrap_or(true)).collect::<Vec<_>>();
+        if stations.is_empty() { continue; }
+        println!("[{}] {} ({})", r.id, r.name, r.ascii_name);
+        for channel in stations {
+            println!("{}\t{}\t{}\t{}\t{}\t{}", channel.id, channel.name, channel.ascii_name.as_deref().unwrap_or(""), channel.area_id, area_name(&channel.area_id).unwrap_or("?"), if channel.timefree { "timefree" } else { "" });
+        }
+    }
+}
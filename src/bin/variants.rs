@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+use std::env;
+use serde_json::Value;
+use unicode_normalization::UnicodeNormalization;
+use radiko_cacher::text::fold;
+use radiko_cacher::variants::{expand, Variant, VariantKind};
+
+// members.jsonの各表記について、foldで同じとみなされる揺れを一覧にする(レビュー用)
+// マッチングはこの一覧を使わず、表記をfoldして比べる
+fn main() {
+    let json = match env::args().skip(1).collect::<Vec<_>>().iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+        [] | ["--format", "text"] => false,
+        ["--format", "json"] => true,
+        _ => {
+            eprintln!("usage: variants [--format text|json]");
+            std::process::exit(2)
+        }
+    };
+    let member_json: Value = serde_json::from_str(include_str!("../../src/members.json").nfkc().collect::<String>().as_str()).unwrap();
+    let mut report: BTreeMap<String, BTreeMap<String, Vec<Variant>>> = BTreeMap::new();
+    // 寄せた形 -> それを持つ名前。別人の表記と重なったら警告する
+    let mut owners: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (group_name, members) in member_json.as_object().unwrap() {
+        for (member_name, literals) in members.as_object().unwrap() {
+            let mut variants = vec![];
+            for literal in literals.as_array().unwrap().iter().filter_map(|l| l.as_str()) {
+                variants.extend(expand(literal).into_iter().filter(|v| !variants.contains(v)).collect::<Vec<_>>());
+            }
+            for variant in &variants {
+                let names = owners.entry(fold(&variant.text)).or_default();
+                if !names.contains(member_name) { names.push(member_name.clone()); }
+            }
+            report.entry(group_name.clone()).or_default().insert(member_name.clone(), variants);
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        for (group_name, members) in &report {
+            println!("[{group_name}]");
+            for (member_name, variants) in members {
+                println!("  {member_name}");
+                for variant in variants.iter().filter(|v| v.kind != VariantKind::Canonical) {
+                    println!("    {:?}\t{}", variant.kind, variant.text);
+                }
+            }
+        }
+    }
+    for (folded, names) in owners.iter().filter(|(_, names)| names.len() > 1) {
+        eprintln!("WARNING: {folded} is shared by {}", names.join(", "));
+    }
+}
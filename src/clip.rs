@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context, Result};
+use chrono::{NaiveTime, TimeDelta, Timelike};
+use regex::Regex;
+use crate::adts::scan_frames;
+use crate::radiko::{jst, RadioProgram};
+use crate::tagging::{chapters, ChapterMark, TagInfo};
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub start: TimeDelta,
+    pub end: TimeDelta,
+    pub title: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum ClipHint {
+    // 録音先頭からのオフセット
+    Manual { start: TimeDelta, end: TimeDelta, title: Option<String> },
+    // artist_name/music_titleにliteralを含む曲の区間
+    Music(String),
+    // desc/infoに書かれた「21:30頃〜」などの時刻
+    Desc,
+}
+
+fn parse_offset(s: &str) -> Result<TimeDelta> {
+    let parts = s.split(':').map(|p| p.parse::<i64>()).collect::<Result<Vec<_>, _>>().with_context(|| format!("invalid offset: {s}"))?;
+    match parts.as_slice() {
+        [m, s] => Ok(TimeDelta::minutes(*m) + TimeDelta::seconds(*s)),
+        [h, m, s] => Ok(TimeDelta::hours(*h) + TimeDelta::minutes(*m) + TimeDelta::seconds(*s)),
+        _ => bail!("invalid offset: {s}"),
+    }
+}
+
+impl ClipHint {
+    // "00:10:00-00:25:30=タイトル" 形式
+    pub fn parse_manual(s: &str) -> Result<Self> {
+        let (range, title) = match s.split_once('=') {
+            None => (s, None),
+            Some((range, title)) => (range, Some(title.to_owned())),
+        };
+        let (start, end) = range.split_once('-').with_context(|| format!("invalid range: {s}"))?;
+        Ok(ClipHint::Manual { start: parse_offset(start)?, end: parse_offset(end)?, title })
+    }
+}
+
+// 番組表の時刻表記は24時以降も「25:30」のように書かれることがある
+pub fn desc_segments(program: &RadioProgram) -> Vec<Segment> {
+    let re = Regex::new(r"(\d{1,2})[:時](\d{2})?分?頃?\s*[〜~-]").unwrap();
+    let ft = program.ft.with_timezone(&jst());
+    let start_of_day = ft.date_naive().and_time(NaiveTime::MIN);
+    let text = [program.desc.clone(), program.info.clone()].into_iter().flatten().collect::<Vec<_>>().join("\n");
+    let mut starts = vec![];
+    for line in text.lines() {
+        for cap in re.captures_iter(line) {
+            let hour = cap[1].parse::<i64>().unwrap();
+            let minute = cap.get(2).map(|m| m.as_str().parse::<i64>().unwrap()).unwrap_or(0);
+            let mut offset = start_of_day + TimeDelta::hours(hour) + TimeDelta::minutes(minute) - ft.naive_local();
+            if offset < TimeDelta::zero() && ft.hour() as i64 > hour {
+                offset += TimeDelta::days(1);
+            }
+            if offset >= TimeDelta::zero() && offset < program.dur {
+                let title = line[cap.get(0).unwrap().end()..].trim().to_owned();
+                starts.push((offset, if title.is_empty() { program.title.clone() } else { title }));
+            }
+        }
+    }
+    starts.sort_by_key(|(offset, _)| *offset);
+    starts.dedup_by_key(|(offset, _)| *offset);
+    let ends = starts.iter().skip(1).map(|(offset, _)| *offset).chain([program.dur]).collect::<Vec<_>>();
+    starts.into_iter().zip(ends).map(|((start, title), end)| Segment { start, end, title }).collect()
+}
+
+pub fn resolve_hints(program: &RadioProgram, hints: &[ClipHint]) -> Vec<Segment> {
+    let mut segments = vec![];
+    for hint in hints {
+        match hint {
+            ClipHint::Manual { start, end, title } => segments.push(Segment {
+                start: *start,
+                end: *end,
+                title: title.clone().unwrap_or_else(|| program.title.clone()),
+            }),
+            ClipHint::Music(literal) => segments.extend(chapters(program).into_iter()
+                .filter(|mark| mark.title.contains(literal.as_str()))
+                .map(|ChapterMark { start, end, title }| Segment { start, end, title })),
+            ClipHint::Desc => segments.extend(desc_segments(program)),
+        }
+    }
+    segments.sort_by_key(|s| s.start);
+    segments
+}
+
+// 再エンコードせず、ADTSフレーム境界で切り出す
+pub fn cut_adts(buf: &[u8], start: TimeDelta, end: TimeDelta) -> Result<Vec<u8>> {
+    let frames = scan_frames(buf)?;
+    let mut elapsed = TimeDelta::zero();
+    let mut out = vec![];
+    for frame in frames {
+        let next = elapsed + frame.duration();
+        if next > start && elapsed < end {
+            out.extend_from_slice(&buf[frame.offset..frame.offset + frame.len]);
+        }
+        elapsed = next;
+    }
+    if out.is_empty() { bail!("segment is out of the recording") }
+    Ok(out)
+}
+
+pub fn clip_recording(recording: &Path, out_dir: &Path, segments: &[Segment], info: &TagInfo) -> Result<Vec<(PathBuf, TagInfo)>> {
+    if recording.extension().and_then(|e| e.to_str()) != Some("aac") {
+        bail!("clip extraction only supports ADTS (.aac) recordings: {}", recording.display())
+    }
+    let buf = fs::read(recording)?;
+    let stem = recording.file_stem().and_then(|s| s.to_str()).unwrap_or("clip");
+    let mut clips = vec![];
+    for (i, segment) in segments.iter().enumerate() {
+        let path = out_dir.join(format!("{stem}_{:02}.aac", i + 1));
+        fs::write(&path, cut_adts(&buf, segment.start, segment.end)?)?;
+        let clip_info = TagInfo {
+            title: format!("{} - {}", info.title, segment.title),
+            chapters: info.chapters.iter().filter(|mark| mark.end > segment.start && mark.start < segment.end).map(|mark| ChapterMark {
+                start: (mark.start - segment.start).max(TimeDelta::zero()),
+                end: mark.end.min(segment.end) - segment.start,
+                title: mark.title.clone(),
+            }).collect(),
+            ..info.clone()
+        };
+        clips.push((path, clip_info));
+    }
+    Ok(clips)
+}
@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Months, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::radiko::RadioProgram;
+
+// Firestoreのexpire_atにTTLをかけているので、Noneなら消えない
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "keep", rename_all = "snake_case")]
+pub enum ExpiryRule {
+    Forever,
+    UntilAirtime,
+    For {
+        #[serde(default)]
+        days: i64,
+        #[serde(default)]
+        months: u32,
+    },
+}
+
+impl ExpiryRule {
+    pub fn expire_at(&self, program: &RadioProgram) -> Option<DateTime<Utc>> {
+        match self {
+            ExpiryRule::Forever => None,
+            ExpiryRule::UntilAirtime => Some(program.to),
+            ExpiryRule::For { days, months } => Some(program.to.checked_add_months(Months::new(*months)).unwrap_or(program.to) + TimeDelta::days(*days)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KindRules {
+    pub member: ExpiryRule,
+    pub group: ExpiryRule,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+// 該当者のいない番組はFirestoreに書かない(schema::program_writes)ので、そのルールはない
+// ローカルのアーカイブ(archive::ProgramArchive)は再マッチングとシリーズ検出に使うので、期限を付けずに全部残す
+pub struct ExpiryPolicy {
+    pub default: KindRules,
+    // メンバー・グループ名ごとの上書き。グループのルールは所属メンバーにも効く
+    #[serde(default)]
+    pub rules: HashMap<String, ExpiryRule>,
+}
+
+// 遅い方を採る(Noneは無期限)
+fn later(a: Option<DateTime<Utc>>, b: Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        _ => None,
+    }
+}
+
+impl ExpiryPolicy {
+    // 名前そのもののルール、なければ所属グループのルール(OGなど)、それもなければ既定
+    pub fn rule_for(&self, name: &str, member_json: &Value) -> &ExpiryRule {
+        let groups = member_json.as_object();
+        let is_group = groups.map(|groups| groups.contains_key(name)).unwrap_or(false);
+        let group_rule = || groups?.iter().filter(|(_, members)| members.get(name).is_some()).find_map(|(group, _)| self.rules.get(group));
+        self.rules.get(name).or_else(group_rule).unwrap_or(if is_group { &self.default.group } else { &self.default.member })
+    }
+    pub fn expire_at_for(&self, program: &RadioProgram, name: &str, member_json: &Value) -> Option<DateTime<Utc>> {
+        self.rule_for(name, member_json).expire_at(program)
+    }
+    // 番組そのものは、該当者の中で一番長く残すルールに合わせる。該当者がいなければ放送終了まで
+    pub fn program_expire_at(&self, program: &RadioProgram, names: &[String], member_json: &Value) -> Option<DateTime<Utc>> {
+        names.iter().map(|name| self.expire_at_for(program, name, member_json)).reduce(later).unwrap_or(Some(program.to))
+    }
+}
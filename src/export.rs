@@ -0,0 +1,154 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::{bail, Result};
+use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use serde::{Deserialize, Serialize};
+use crate::radiko::jst;
+use crate::schema::{MatchRecord, ProgramDocument};
+
+// TTLで消される前に書き出す、番組と該当情報のひとまとまり
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedProgram {
+    #[serde(flatten)]
+    pub document: ProgramDocument,
+    pub matches: Vec<MatchRecord>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    // 番組ごとに {局}/{開始時刻}_{番組ID}.json
+    Json,
+    // コレクションごとに1ファイル。番組1つが1行で、document列に上のJSONをそのまま入れる
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "json" => Some(ExportFormat::Json),
+            "parquet" => Some(ExportFormat::Parquet),
+            _ => None,
+        }
+    }
+}
+
+const PARQUET_SCHEMA: &str = "
+message archived_program {
+    required int64 program_id;
+    required binary station_id (UTF8);
+    required binary title (UTF8);
+    required int64 ft (TIMESTAMP(MILLIS,true));
+    required int64 to (TIMESTAMP(MILLIS,true));
+    optional binary pfm (UTF8);
+    required binary matched (UTF8);
+    optional int64 expire_at (TIMESTAMP(MILLIS,true));
+    required binary document (UTF8);
+}
+";
+
+// 途中で失敗しても書きかけのファイルが残らないよう、一時ファイルに書いてから置き換える
+fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+pub fn json_path(dir: &Path, archived: &ArchivedProgram) -> PathBuf {
+    let program = &archived.document.program;
+    dir.join(&program.radio_channel.id).join(format!("{}_{}.json", program.ft.with_timezone(&jst()).format("%Y%m%d%H%M%S"), program.id))
+}
+
+pub fn write_json(dir: &Path, archived: &ArchivedProgram) -> Result<PathBuf> {
+    let path = json_path(dir, archived);
+    write_atomic(&path, serde_json::to_string_pretty(archived)?)?;
+    Ok(path)
+}
+
+fn byte_arrays(values: impl Iterator<Item = String>) -> Vec<ByteArray> {
+    values.map(|s| ByteArray::from(s.as_str())).collect()
+}
+
+// 値のない行は定義レベル0にして、値は詰めて渡す
+fn optional<T>(values: impl Iterator<Item = Option<T>>) -> (Vec<T>, Vec<i16>) {
+    let mut present = vec![];
+    let mut levels = vec![];
+    for value in values {
+        levels.push(value.is_some() as i16);
+        present.extend(value);
+    }
+    (present, levels)
+}
+
+pub fn write_parquet(path: &Path, programs: &[ArchivedProgram]) -> Result<()> {
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+    let mut buf = vec![];
+    let mut writer = SerializedFileWriter::new(&mut buf, schema, Arc::new(WriterProperties::builder().build()))?;
+    let mut row_group = writer.next_row_group()?;
+    let documents = programs.iter().map(|a| &a.document);
+    let (pfm, pfm_levels) = optional(documents.clone().map(|d| d.program.pfm.as_deref().map(ByteArray::from)));
+    let (expire_at, expire_at_levels) = optional(documents.clone().map(|d| d.program.expire_at.map(|t| t.timestamp_millis())));
+    let mut column = 0;
+    while let Some(mut column_writer) = row_group.next_column()? {
+        match column {
+            0 => column_writer.typed::<Int64Type>().write_batch(&documents.clone().map(|d| d.program.id as i64).collect::<Vec<_>>(), None, None)?,
+            1 => column_writer.typed::<ByteArrayType>().write_batch(&byte_arrays(documents.clone().map(|d| d.program.radio_channel.id.clone())), None, None)?,
+            2 => column_writer.typed::<ByteArrayType>().write_batch(&byte_arrays(documents.clone().map(|d| d.program.title.clone())), None, None)?,
+            3 => column_writer.typed::<Int64Type>().write_batch(&documents.clone().map(|d| d.program.ft.timestamp_millis()).collect::<Vec<_>>(), None, None)?,
+            4 => column_writer.typed::<Int64Type>().write_batch(&documents.clone().map(|d| d.program.to.timestamp_millis()).collect::<Vec<_>>(), None, None)?,
+            5 => column_writer.typed::<ByteArrayType>().write_batch(&pfm, Some(&pfm_levels), None)?,
+            6 => column_writer.typed::<ByteArrayType>().write_batch(&byte_arrays(documents.clone().map(|d| serde_json::to_string(&d.matched)).collect::<Result<Vec<_>, _>>()?.into_iter()), None, None)?,
+            7 => column_writer.typed::<Int64Type>().write_batch(&expire_at, Some(&expire_at_levels), None)?,
+            8 => column_writer.typed::<ByteArrayType>().write_batch(&byte_arrays(programs.iter().map(serde_json::to_string).collect::<Result<Vec<_>, _>>()?.into_iter()), None, None)?,
+            _ => bail!("unexpected column {column}"),
+        };
+        column_writer.close()?;
+        column += 1;
+    }
+    row_group.close()?;
+    writer.close()?;
+    write_atomic(path, buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+    use super::*;
+
+    fn archived(id: u64, pfm: Option<&str>) -> ArchivedProgram {
+        serde_json::from_value(serde_json::json!({
+            "station_id": "TBS", "id": id, "ft": "2026-10-18T12:00:00Z", "to": "2026-10-18T13:00:00Z", "dur": 3600,
+            "title": format!("番組{id}"), "img": null, "info": null, "desc": null, "pfm": pfm, "on_air_music": [],
+            "expire_at": if pfm.is_some() { Some("2026-11-01T13:00:00Z") } else { None },
+            "matched": ["中澤裕子"], "matches": []
+        })).unwrap()
+    }
+
+    #[test]
+    fn parquet_has_one_row_per_program() {
+        let dir = std::env::temp_dir().join(format!("radiko_export_{}", std::process::id()));
+        let path = dir.join("expiring.parquet");
+        let programs = [archived(1, Some("中澤裕子")), archived(2, None)];
+        write_parquet(&path, &programs).unwrap();
+
+        let reader = SerializedFileReader::new(fs::File::open(&path).unwrap()).unwrap();
+        let rows = reader.get_row_iter(None).unwrap().map(|r| r.unwrap()).collect::<Vec<_>>();
+        assert_eq!(rows.len(), 2);
+        let column = |row: usize, name: &str| rows[row].get_column_iter().find(|(n, _)| *n == name).unwrap().1.clone();
+        assert_eq!(column(0, "program_id"), Field::Long(1));
+        assert_eq!(column(0, "pfm"), Field::Str("中澤裕子".to_owned()));
+        assert_eq!(column(1, "pfm"), Field::Null);
+        assert_eq!(column(1, "expire_at"), Field::Null);
+        assert_eq!(column(0, "matched"), Field::Str(r#"["中澤裕子"]"#.to_owned()));
+        let Field::Str(document) = column(1, "document") else { panic!() };
+        assert_eq!(serde_json::from_str::<ArchivedProgram>(&document).unwrap().document.program.id, 2);
+        assert!(!path.with_extension("tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use firestore::errors::FirestoreError;
+use firestore::{FirestoreDb, FirestoreResult};
+use futures::stream::{self, StreamExt};
+use futures::TryStreamExt;
+use serde::Serialize;
+
+// BatchWriteは1回500件まで
+pub const BATCH_SIZE: usize = 200;
+pub const CONCURRENCY: usize = 4;
+pub const MAX_ATTEMPTS: u32 = 3;
+// 再送の待ち時間。回ごとに倍にし、最大で同じだけ揺らす
+const RETRY_BASE: Duration = Duration::from_millis(500);
+// 毎回変わるので、変更の有無を見るときは無視する
+const VOLATILE_FIELDS: [&str; 1] = ["updated_at"];
+
+// ドキュメントIDは内容から決まるので、同じ書き込みを何度やっても結果は同じ
+pub struct DocWrite<T> {
+    pub parent: String,
+    pub collection: String,
+    pub document_id: String,
+    pub object: T,
+}
+
+// 該当しなくなったものなど、消すドキュメント
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DocDelete {
+    pub parent: String,
+    pub collection: String,
+    pub document_id: String,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct WriteSummary {
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub deleted: usize,
+    pub failed: usize,
+    pub errors: Vec<String>,
+}
+
+impl WriteSummary {
+    pub fn merge(&mut self, other: WriteSummary) {
+        self.created += other.created;
+        self.updated += other.updated;
+        self.unchanged += other.unchanged;
+        self.deleted += other.deleted;
+        self.failed += other.failed;
+        self.errors.extend(other.errors);
+    }
+}
+
+impl fmt::Display for WriteSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "created {}, updated {}, unchanged {}, ", self.created, self.updated, self.unchanged)?;
+        if self.deleted > 0 {
+            write!(f, "deleted {}, ", self.deleted)?;
+        }
+        write!(f, "failed {}", self.failed)?;
+        for error in self.errors.iter().take(10) {
+            write!(f, "\n  {error}")?;
+        }
+        if self.errors.len() > 10 {
+            write!(f, "\n  ... and {} more", self.errors.len() - 10)?;
+        }
+        Ok(())
+    }
+}
+
+// 一時的なもの(DEADLINE_EXCEEDED, RESOURCE_EXHAUSTED, ABORTED, UNAVAILABLE)だけ再送する
+fn is_retryable(code: i32) -> bool {
+    matches!(code, 4 | 8 | 10 | 14)
+}
+
+fn is_retryable_error(err: &FirestoreError) -> bool {
+    match err {
+        FirestoreError::DatabaseError(err) => err.retry_possible,
+        FirestoreError::NetworkError(_) => true,
+        _ => false,
+    }
+}
+
+fn backoff(attempt: u32) -> Duration {
+    let delay = RETRY_BASE * 2u32.pow(attempt - 1);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    delay + delay.mul_f64(nanos as f64 / 1e9)
+}
+
+enum Change {
+    Create,
+    Update,
+}
+
+fn stable_fields<V>(fields: &HashMap<String, V>) -> HashMap<&String, &V> {
+    fields.iter().filter(|(k, _)| !VOLATILE_FIELDS.contains(&k.as_str())).collect()
+}
+
+// 既存のドキュメントと比べて、変わったものだけを書く
+async fn classify<T: Serialize + Sync + Send>(db: &FirestoreDb, chunk: &[&DocWrite<T>]) -> FirestoreResult<Vec<Option<Change>>> {
+    let mut by_target: BTreeMap<(&str, &str), Vec<&str>> = BTreeMap::new();
+    for write in chunk {
+        by_target.entry((write.parent.as_str(), write.collection.as_str())).or_default().push(write.document_id.as_str());
+    }
+    let mut existing = HashMap::new();
+    for ((parent, collection), ids) in by_target {
+        let docs: Vec<(String, Option<firestore::FirestoreDocument>)> = db.fluent().select().by_id_in(collection).parent(parent)
+            .batch_with_errors(ids).await?.try_collect().await?;
+        existing.extend(docs.into_iter().filter_map(|(id, doc)| doc.map(|doc| ((parent, collection, id), doc))));
+    }
+    chunk.iter().map(|write| {
+        let new = FirestoreDb::serialize_to_doc("", &write.object)?;
+        Ok(match existing.get(&(write.parent.as_str(), write.collection.as_str(), write.document_id.clone())) {
+            None => Some(Change::Create),
+            Some(doc) if stable_fields(&doc.fields) == stable_fields(&new.fields) => None,
+            Some(_) => Some(Change::Update),
+        })
+    }).collect()
+}
+
+async fn write_chunk<T: Serialize + Sync + Send>(db: &FirestoreDb, chunk: &[&DocWrite<T>]) -> WriteSummary {
+    let mut summary = WriteSummary::default();
+    let changes = match classify(db, chunk).await {
+        Ok(changes) => changes,
+        // 比較できなければ全部書く(書き込み自体は冪等)
+        Err(err) => {
+            summary.errors.push(format!("read before write failed: {err}"));
+            chunk.iter().map(|_| Some(Change::Update)).collect()
+        }
+    };
+    summary.unchanged = changes.iter().filter(|c| c.is_none()).count();
+    let mut pending = chunk.iter().copied().zip(changes).filter_map(|(write, change)| change.map(|change| (write, change))).collect::<Vec<_>>();
+    let writer = match db.create_simple_batch_writer().await {
+        Ok(writer) => writer,
+        Err(err) => {
+            summary.failed += pending.len();
+            summary.errors.push(format!("batch writer: {err}"));
+            return summary;
+        }
+    };
+    let mut last_error = None;
+    for attempt in 1..=MAX_ATTEMPTS {
+        if pending.is_empty() { break; }
+        if attempt > 1 {
+            tokio::time::sleep(backoff(attempt - 1)).await;
+        }
+        let mut batch = writer.new_batch();
+        let mut added = vec![];
+        for (write, change) in pending {
+            match batch.update_object_at(&write.parent, &write.collection, &write.document_id, &write.object, None, None, vec![]) {
+                Ok(_) => added.push((write, change)),
+                Err(err) => {
+                    summary.failed += 1;
+                    summary.errors.push(format!("{}/{}: {err}", write.collection, write.document_id));
+                }
+            }
+        }
+        pending = added;
+        // バッチ全体の一時的なエラーはwriterがバックオフしながら再送する。ここでは書き込みごとの失敗を拾う
+        match batch.write().await {
+            Ok(response) => {
+                let mut retry = vec![];
+                for (i, (write, change)) in pending.into_iter().enumerate() {
+                    match response.statuses.get(i).filter(|status| status.code != 0) {
+                        None => match change {
+                            Change::Create => summary.created += 1,
+                            Change::Update => summary.updated += 1,
+                        },
+                        Some(status) if !is_retryable(status.code) || attempt == MAX_ATTEMPTS => {
+                            summary.failed += 1;
+                            summary.errors.push(format!("{}/{}: code {}: {}", write.collection, write.document_id, status.code, status.message));
+                        }
+                        Some(_) => retry.push((write, change)),
+                    }
+                }
+                pending = retry;
+            }
+            Err(err) => {
+                let retryable = is_retryable_error(&err);
+                last_error = Some(err);
+                if !retryable { break; }
+            }
+        }
+    }
+    if !pending.is_empty() {
+        summary.failed += pending.len();
+        summary.errors.push(format!("batch write failed: {}", last_error.map(|err| err.to_string()).unwrap_or_default()));
+    }
+    summary
+}
+
+async fn delete_chunk(db: &FirestoreDb, chunk: &[DocDelete]) -> WriteSummary {
+    let mut summary = WriteSummary::default();
+    let writer = match db.create_simple_batch_writer().await {
+        Ok(writer) => writer,
+        Err(err) => {
+            summary.failed += chunk.len();
+            summary.errors.push(format!("batch writer: {err}"));
+            return summary;
+        }
+    };
+    let mut pending = chunk.iter().collect::<Vec<_>>();
+    let mut last_error = None;
+    for attempt in 1..=MAX_ATTEMPTS {
+        if pending.is_empty() { break; }
+        if attempt > 1 {
+            tokio::time::sleep(backoff(attempt - 1)).await;
+        }
+        let mut batch = writer.new_batch();
+        let mut added = vec![];
+        for delete in pending {
+            match batch.delete_by_id_at(&delete.parent, &delete.collection, &delete.document_id, None) {
+                Ok(_) => added.push(delete),
+                Err(err) => {
+                    summary.failed += 1;
+                    summary.errors.push(format!("delete {}/{}: {err}", delete.collection, delete.document_id));
+                }
+            }
+        }
+        pending = added;
+        match batch.write().await {
+            Ok(response) => {
+                let mut retry = vec![];
+                for (i, delete) in pending.into_iter().enumerate() {
+                    match response.statuses.get(i).filter(|status| status.code != 0) {
+                        None => summary.deleted += 1,
+                        Some(status) if !is_retryable(status.code) || attempt == MAX_ATTEMPTS => {
+                            summary.failed += 1;
+                            summary.errors.push(format!("delete {}/{}: code {}: {}", delete.collection, delete.document_id, status.code, status.message));
+                        }
+                        Some(_) => retry.push(delete),
+                    }
+                }
+                pending = retry;
+            }
+            Err(err) => {
+                let retryable = is_retryable_error(&err);
+                last_error = Some(err);
+                if !retryable { break; }
+            }
+        }
+    }
+    if !pending.is_empty() {
+        summary.failed += pending.len();
+        summary.errors.push(format!("batch delete failed: {}", last_error.map(|err| err.to_string()).unwrap_or_default()));
+    }
+    summary
+}
+
+// 同じドキュメントが1つのコミットに2回入るとコミットごと弾かれるので、あとに積んだ方だけ残す
+fn dedup<T>(writes: &[DocWrite<T>]) -> Vec<&DocWrite<T>> {
+    let mut seen = HashSet::new();
+    let mut unique = writes.iter().rev().filter(|w| seen.insert((w.parent.as_str(), w.collection.as_str(), w.document_id.as_str()))).collect::<Vec<_>>();
+    unique.reverse();
+    unique
+}
+
+// BATCH_SIZEずつのバッチに分け、CONCURRENCY本まで並行して書く。失敗しても止めずに集計する
+pub async fn write_documents<T: Serialize + Sync + Send>(db: &FirestoreDb, writes: &[DocWrite<T>]) -> WriteSummary {
+    let writes = dedup(writes);
+    let summaries = stream::iter(writes.chunks(BATCH_SIZE))
+        .map(|chunk| write_chunk(db, chunk))
+        .buffer_unordered(CONCURRENCY)
+        .collect::<Vec<_>>().await;
+    let mut summary = WriteSummary::default();
+    summaries.into_iter().for_each(|s| summary.merge(s));
+    summary
+}
+
+pub async fn delete_documents(db: &FirestoreDb, deletes: &[DocDelete]) -> WriteSummary {
+    let mut seen = HashSet::new();
+    let deletes = deletes.iter().filter(|d| seen.insert(*d)).cloned().collect::<Vec<_>>();
+    let summaries = stream::iter(deletes.chunks(BATCH_SIZE))
+        .map(|chunk| delete_chunk(db, chunk))
+        .buffer_unordered(CONCURRENCY)
+        .collect::<Vec<_>>().await;
+    let mut summary = WriteSummary::default();
+    summaries.into_iter().for_each(|s| summary.merge(s));
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(document_id: &str, object: u32) -> DocWrite<u32> {
+        DocWrite { parent: "root/catalog".to_owned(), collection: "programs".to_owned(), document_id: document_id.to_owned(), object }
+    }
+
+    #[test]
+    fn duplicates_keep_the_last_write() {
+        let writes = [write("1", 1), write("2", 2), write("1", 3)];
+        assert_eq!(dedup(&writes).iter().map(|w| (w.document_id.as_str(), w.object)).collect::<Vec<_>>(), [("2", 2), ("1", 3)]);
+    }
+
+    #[test]
+    fn only_transient_codes_are_retried() {
+        assert!([4, 8, 10, 14].into_iter().all(is_retryable));
+        // INVALID_ARGUMENT, NOT_FOUND, PERMISSION_DENIED
+        assert!(![3, 5, 7].into_iter().any(is_retryable));
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter() {
+        for attempt in 1..=3 {
+            let base = RETRY_BASE * 2u32.pow(attempt - 1);
+            let delay = backoff(attempt);
+            assert!(base <= delay && delay < base * 2);
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use std::cmp::Reverse;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::text::{fold, Readings};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Script {
+    // 表記そのもの(誤字)
+    Literal,
+    Kana,
+    Romaji,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzyHit {
+    pub script: Script,
+    pub pattern: String,
+    pub found: String,
+    pub distance: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FuzzyRule {
+    pub enabled: bool,
+    pub max_distance: usize,
+}
+
+impl FuzzyRule {
+    pub fn for_name(match_rules: &Value, name: &str) -> Self {
+        Self::for_member(match_rules, name, name)
+    }
+    // メンバーに設定があればそれを、なければグループ、それもなければdefaultの設定を使う
+    pub fn for_member(match_rules: &Value, group: &str, member: &str) -> Self {
+        let lookup = |key: &str| {
+            [&match_rules["rules"][member][key], &match_rules["rules"][group][key], &match_rules["default"][key]]
+                .into_iter().find(|rule| !rule.is_null()).cloned().unwrap_or(Value::Null)
+        };
+        FuzzyRule {
+            enabled: lookup("fuzzy").as_bool().unwrap_or(false),
+            max_distance: lookup("max_distance").as_u64().unwrap_or(1) as usize,
+        }
+    }
+    // 短い名前ほど1文字違いで別人・別語になりやすいので、5文字につき1までに抑える
+    pub fn allowed(&self, pattern: &str) -> usize {
+        self.max_distance.min(pattern.chars().count() / 5)
+    }
+}
+
+const KANA: [(char, &str); 82] = [
+    ('あ', "a"), ('い', "i"), ('う', "u"), ('え', "e"), ('お', "o"),
+    ('か', "ka"), ('き', "ki"), ('く', "ku"), ('け', "ke"), ('こ', "ko"),
+    ('が', "ga"), ('ぎ', "gi"), ('ぐ', "gu"), ('げ', "ge"), ('ご', "go"),
+    ('さ', "sa"), ('し', "shi"), ('す', "su"), ('せ', "se"), ('そ', "so"),
+    ('ざ', "za"), ('じ', "ji"), ('ず', "zu"), ('ぜ', "ze"), ('ぞ', "zo"),
+    ('た', "ta"), ('ち', "chi"), ('つ', "tsu"), ('て', "te"), ('と', "to"),
+    ('だ', "da"), ('ぢ', "ji"), ('づ', "zu"), ('で', "de"), ('ど', "do"),
+    ('な', "na"), ('に', "ni"), ('ぬ', "nu"), ('ね', "ne"), ('の', "no"),
+    ('は', "ha"), ('ひ', "hi"), ('ふ', "fu"), ('へ', "he"), ('ほ', "ho"),
+    ('ば', "ba"), ('び', "bi"), ('ぶ', "bu"), ('べ', "be"), ('ぼ', "bo"),
+    ('ぱ', "pa"), ('ぴ', "pi"), ('ぷ', "pu"), ('ぺ', "pe"), ('ぽ', "po"),
+    ('ま', "ma"), ('み', "mi"), ('む', "mu"), ('め', "me"), ('も', "mo"),
+    ('や', "ya"), ('ゆ', "yu"), ('よ', "yo"),
+    ('ら', "ra"), ('り', "ri"), ('る', "ru"), ('れ', "re"), ('ろ', "ro"),
+    ('わ', "wa"), ('ゐ', "i"), ('ゑ', "e"), ('を', "o"), ('ん', "n"), ('ゔ', "vu"),
+    ('ぁ', "a"), ('ぃ', "i"), ('ぅ', "u"), ('ぇ', "e"), ('ぉ', "o"),
+    ('ゃ', "ya"), ('ゅ', "yu"), ('ょ', "yo"),
+];
+
+// foldしたテキストのひらがなをヘボン式に寄せる。かな以外はそのまま
+pub fn romaji(folded: &str) -> String {
+    let mut out = String::new();
+    let mut double_next = false;
+    // 直前のかなのローマ字。拗音などはこれにつなげる(かな以外のあとならつなげない)
+    let mut prev: Option<&str> = None;
+    for c in folded.chars() {
+        match c {
+            'っ' => { double_next = true; continue; }
+            // 長音は書かないことが多いので落とす
+            'ー' => continue,
+            _ => {}
+        }
+        let Some((_, roman)) = KANA.iter().find(|(kana, _)| *kana == c) else {
+            double_next = false;
+            prev = None;
+            out.push(c);
+            continue;
+        };
+        let roman = match c {
+            // 拗音: きゃ→kya, しゃ→sha, ちゃ→cha, じゃ→ja
+            'ゃ' | 'ゅ' | 'ょ' if prev.is_some_and(|p| p.len() >= 2 && p.ends_with('i')) => {
+                out.pop();
+                if out.ends_with("sh") || out.ends_with("ch") || out.ends_with('j') { &roman[1..] } else { *roman }
+            }
+            // ふぁ→fa などは母音を差し替える
+            'ぁ' | 'ぃ' | 'ぅ' | 'ぇ' | 'ぉ' if prev.is_some_and(|p| p.len() >= 2 && p.ends_with(['a', 'i', 'u', 'e', 'o'])) => {
+                out.pop();
+                *roman
+            }
+            _ => *roman,
+        };
+        prev = Some(roman);
+        if double_next {
+            out.push_str(if roman.starts_with("ch") { "t" } else { &roman[..1] });
+            double_next = false;
+        }
+        out.push_str(roman);
+    }
+    normalize_romaji(&out)
+}
+
+// 長音の書き方(ou/oo/o、uu/u)と撥音のm/nの揺れを寄せる
+fn normalize_romaji(roman: &str) -> String {
+    roman.replace("ou", "o").replace("oo", "o").replace("uu", "u").replace("mb", "nb").replace("mp", "np").replace("mm", "nm")
+}
+
+// 読みに空白があれば姓と名の区切りとみなし、名・姓の順のローマ字も作る
+pub fn romaji_patterns(reading: &str) -> Vec<String> {
+    let parts = reading.split_whitespace().map(|part| romaji(&fold(part))).collect::<Vec<_>>();
+    let mut patterns = vec![parts.concat()];
+    if parts.len() == 2 {
+        patterns.push(format!("{}{}", parts[1], parts[0]));
+    }
+    patterns
+}
+
+// textのどこかの部分文字列とpatternの編集距離がk以内なら、最小のものを返す
+pub fn find_within(text: &str, pattern: &str, k: usize) -> Option<(String, usize)> {
+    let text = text.chars().collect::<Vec<_>>();
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let m = pattern.len();
+    if m == 0 || m <= k {
+        return None;
+    }
+    // k文字違いならpatternをk+1個に割ったどれかはそのまま現れるので、その周りだけDPする
+    let piece_len = m / (k + 1);
+    let mut best: Option<(usize, usize, usize)> = None;
+    for piece in 0..=k {
+        let offset = piece * piece_len;
+        let end = if piece == k { m } else { offset + piece_len };
+        let needle = &pattern[offset..end];
+        for pos in (0..text.len().saturating_sub(needle.len() - 1)).filter(|&pos| text[pos..pos + needle.len()] == *needle) {
+            let from = pos.saturating_sub(offset + k);
+            let to = (pos + m + k - offset).min(text.len());
+            if let Some((start, stop, distance)) = align(&text[from..to], &pattern) {
+                if best.is_none_or(|(_, _, d)| distance < d) {
+                    best = Some((from + start, from + stop, distance));
+                }
+            }
+        }
+    }
+    best.filter(|(_, _, distance)| *distance <= k).map(|(start, stop, distance)| (text[start..stop].iter().collect(), distance))
+}
+
+// Sellersの近似部分一致。(開始, 終了, 距離)
+fn align(window: &[char], pattern: &[char]) -> Option<(usize, usize, usize)> {
+    // 各列の(距離, 一致の開始位置)
+    let mut prev = (0..=window.len()).map(|j| (0, j)).collect::<Vec<_>>();
+    for (i, p) in pattern.iter().enumerate() {
+        let mut cur = vec![(i + 1, 0)];
+        for (j, w) in window.iter().enumerate() {
+            let substitute = (prev[j].0 + usize::from(p != w), prev[j].1);
+            let delete = (prev[j + 1].0 + 1, prev[j + 1].1);
+            let insert = (cur[j].0 + 1, cur[j].1);
+            cur.push([substitute, delete, insert].into_iter().min_by_key(|(d, _)| *d).unwrap());
+        }
+        prev = cur;
+    }
+    // 同じ距離なら長く取れた方(置換で説明できる方)を選ぶ
+    prev.iter().enumerate().skip(1).min_by_key(|(stop, (d, start))| (*d, Reverse(stop - start))).map(|(stop, (d, start))| (*start, stop, *d))
+}
+
+// 名前の表記・読み・ローマ字のどれかが、許容距離内で本文に現れるか
+pub fn search(texts: &[String], literals: &[String], readings: &Readings, rule: &FuzzyRule) -> Option<FuzzyHit> {
+    let romaji_texts = texts.iter().map(|t| romaji(t)).collect::<Vec<_>>();
+    let mut candidates = vec![];
+    for literal in literals {
+        let folded = fold(literal);
+        // 完全一致は通常のマッチで拾っているので、表記は誤字のときだけ
+        if rule.allowed(&folded) > 0 {
+            candidates.push((Script::Literal, folded.clone()));
+        }
+        let reading = readings.reading_of(literal).map(|r| r.to_owned())
+            .or_else(|| folded.chars().all(|c| ('ぁ'..='ゖ').contains(&c) || c == 'ー').then(|| literal.clone()));
+        if let Some(reading) = reading {
+            let kana = fold(&reading);
+            if kana != folded {
+                candidates.push((Script::Kana, kana));
+            }
+            candidates.extend(romaji_patterns(&reading).into_iter().map(|r| (Script::Romaji, r)));
+        }
+    }
+    candidates.into_iter().filter_map(|(script, pattern)| {
+        let k = rule.allowed(&pattern);
+        let haystacks = if script == Script::Romaji { &romaji_texts } else { texts };
+        haystacks.iter().filter_map(|text| {
+            if text.contains(&pattern) {
+                return Some((pattern.clone(), 0));
+            }
+            find_within(text, &pattern, k)
+        }).min_by_key(|(_, distance)| *distance)
+            .map(|(found, distance)| FuzzyHit { script, pattern, found, distance })
+    }).min_by_key(|hit| hit.distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn romaji_contracted_sounds() {
+        assert_eq!(romaji("きょう"), "kyo");
+        assert_eq!(romaji("しゃしん"), "shashin");
+        assert_eq!(romaji("ちゃん"), "chan");
+        assert_eq!(romaji("じゅり"), "juri");
+        assert_eq!(romaji("ふぁん"), "fan");
+    }
+
+    #[test]
+    fn romaji_small_kana_after_non_kana_stay_apart() {
+        assert_eq!(romaji("miゃ"), "miya");
+        assert_eq!(romaji("いゃ"), "iya");
+        assert_eq!(romaji("uぁ"), "ua");
+    }
+
+    #[test]
+    fn romaji_double_consonants() {
+        assert_eq!(romaji("がっこう"), "gakko");
+        assert_eq!(romaji("まっちゃ"), "matcha");
+        assert_eq!(romaji("きっぷ"), "kippu");
+        // 末尾や、かな以外の前の促音は落とす
+        assert_eq!(romaji("あっ"), "a");
+        assert_eq!(romaji("あっa"), "aa");
+    }
+
+    #[test]
+    fn romaji_long_vowels() {
+        assert_eq!(romaji("らーめん"), "ramen");
+        assert_eq!(romaji("さとう"), romaji("さと"));
+        assert_eq!(romaji("おおの"), "ono");
+        assert_eq!(romaji("ゆうか"), "yuka");
+        assert_eq!(normalize_romaji("shimbun"), normalize_romaji("shinbun"));
+    }
+
+    #[test]
+    fn romaji_patterns_swap_name_order() {
+        assert_eq!(romaji_patterns("みちしげ さゆみ"), ["michishigesayumi", "sayumimichishige"]);
+        assert_eq!(romaji_patterns("さゆみん"), ["sayumin"]);
+    }
+
+    #[test]
+    fn allowed_distance_scales_with_length() {
+        let rule = FuzzyRule { enabled: true, max_distance: 2 };
+        assert_eq!(rule.allowed("abcd"), 0);
+        assert_eq!(rule.allowed("abcdefg"), 1);
+        assert_eq!(rule.allowed("abcdefghij"), 2);
+        assert_eq!(rule.allowed("abcdefghijklmnopqrst"), 2);
+    }
+
+    #[test]
+    fn rule_lookup_prefers_member_then_group_then_default() {
+        let rules = json!({"default": {"fuzzy": false}, "rules": {"group": {"fuzzy": true, "max_distance": 2}, "member": {"fuzzy": false}}});
+        assert!(!FuzzyRule::for_member(&rules, "group", "member").enabled);
+        assert!(FuzzyRule::for_member(&rules, "group", "other").enabled);
+        assert_eq!(FuzzyRule::for_member(&rules, "group", "member").max_distance, 2);
+        assert!(!FuzzyRule::for_name(&rules, "unknown").enabled);
+        assert_eq!(FuzzyRule::for_name(&rules, "unknown").max_distance, 1);
+    }
+
+    #[test]
+    fn find_within_exact() {
+        assert_eq!(find_within("xxabcdexx", "abcde", 0), Some(("abcde".to_owned(), 0)));
+        assert_eq!(find_within("abcde", "abcde", 0), Some(("abcde".to_owned(), 0)));
+        assert_eq!(find_within("xxabcdxxx", "abcde", 0), None);
+    }
+
+    #[test]
+    fn find_within_one_edit_at_the_edges() {
+        assert_eq!(find_within("abxdefgh", "abcde", 1), Some(("abxde".to_owned(), 1)));
+        assert_eq!(find_within("xbcdefgh", "abcde", 1), Some(("xbcde".to_owned(), 1)));
+        assert_eq!(find_within("zzzzabcdx", "abcde", 1), Some(("abcdx".to_owned(), 1)));
+        // 末尾が切れている
+        assert_eq!(find_within("zzzzabcd", "abcde", 1), Some(("abcd".to_owned(), 1)));
+        // 先頭が切れている
+        assert_eq!(find_within("bcdezzzz", "abcde", 1), Some(("bcde".to_owned(), 1)));
+        assert_eq!(find_within("zzabXYezz", "abcde", 1), None);
+    }
+
+    #[test]
+    fn find_within_two_edits() {
+        assert_eq!(find_within("qqabXdeYgqq", "abcdefg", 2), Some(("abXdeYg".to_owned(), 2)));
+        assert_eq!(find_within("abXdeYg", "abcdefg", 2), Some(("abXdeYg".to_owned(), 2)));
+        assert_eq!(find_within("Xbcdefg", "abcdefg", 2), Some(("Xbcdefg".to_owned(), 1)));
+        assert_eq!(find_within("abcdeXY", "abcdefg", 2), Some(("abcdeXY".to_owned(), 2)));
+        assert_eq!(find_within("XbcYeZg", "abcdefg", 2), None);
+    }
+
+    #[test]
+    fn find_within_needs_a_pattern_longer_than_k() {
+        assert_eq!(find_within("ab", "ab", 2), None);
+        assert_eq!(find_within("ab", "ab", 3), None);
+        assert_eq!(find_within("abc", "", 0), None);
+        assert_eq!(find_within("abc", "abc", 2), Some(("abc".to_owned(), 0)));
+    }
+}
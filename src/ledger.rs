@@ -0,0 +1,184 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::loudness::Loudness;
+use crate::radiko::{jst, RadioProgram};
+
+pub const MAX_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DownloadStatus {
+    Queued,
+    InProgress,
+    Done,
+    Failed { reason: String },
+    // 保存期間・容量の都合で消したもの。再ダウンロードはしない
+    Deleted { reason: String },
+    // 番組表から消えた(休止・振替)もの
+    Cancelled { reason: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub station_id: String,
+    pub ft: String,
+    pub program_id: u64,
+    pub title: String,
+    #[serde(flatten)]
+    pub status: DownloadStatus,
+    pub attempts: u32,
+    pub file_path: Option<PathBuf>,
+    #[serde(default)]
+    pub linked_paths: Vec<PathBuf>,
+    #[serde(default)]
+    pub matched: Vec<String>,
+    pub byte_size: Option<u64>,
+    pub checksum: Option<String>,
+    // 元の録音で測った値(変換時にかけたゲインは含まない)
+    #[serde(default)]
+    pub loudness: Option<Loudness>,
+    #[serde(default)]
+    pub applied_gain_db: Option<f64>,
+    pub updated_at: DateTime<Utc>,
+}
+
+pub struct Ledger {
+    path: PathBuf,
+    pub entries: BTreeMap<String, LedgerEntry>,
+}
+
+pub fn ledger_key(program: &RadioProgram) -> String {
+    format!("{}_{}_{}", program.radio_channel.id, program.ft.with_timezone(&jst()).format("%Y%m%d%H%M%S"), program.id)
+}
+
+pub fn file_checksum(path: &Path) -> Result<(u64, String)> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    let mut size = 0u64;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 { break; }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
+
+impl Ledger {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let entries = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Ledger { path, entries })
+    }
+    pub fn save(&self) -> Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.entries)?)?;
+        fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+    pub fn get(&self, program: &RadioProgram) -> Option<&LedgerEntry> {
+        self.entries.get(&ledger_key(program))
+    }
+    // 完了済みは飛ばし、失敗(中断されたin_progressも含む)はMAX_ATTEMPTS回まで再挑戦する
+    pub fn should_download(&self, program: &RadioProgram) -> bool {
+        match self.get(program) {
+            None => true,
+            Some(entry) => match entry.status {
+                DownloadStatus::Done | DownloadStatus::Deleted { .. } | DownloadStatus::Cancelled { .. } => false,
+                DownloadStatus::Queued => true,
+                DownloadStatus::InProgress | DownloadStatus::Failed { .. } => entry.attempts < MAX_ATTEMPTS,
+            }
+        }
+    }
+    fn update(&mut self, program: &RadioProgram, f: impl FnOnce(&mut LedgerEntry)) -> Result<()> {
+        let entry = self.entries.entry(ledger_key(program)).or_insert_with(|| LedgerEntry {
+            station_id: program.radio_channel.id.clone(),
+            ft: program.ft.with_timezone(&jst()).format("%Y%m%d%H%M%S").to_string(),
+            program_id: program.id,
+            title: program.title.clone(),
+            status: DownloadStatus::Queued,
+            attempts: 0,
+            file_path: None,
+            linked_paths: vec![],
+            matched: vec![],
+            byte_size: None,
+            checksum: None,
+            loudness: None,
+            applied_gain_db: None,
+            updated_at: Utc::now(),
+        });
+        f(entry);
+        entry.updated_at = Utc::now();
+        self.save()
+    }
+    pub fn mark_queued(&mut self, program: &RadioProgram) -> Result<()> {
+        self.update(program, |entry| if !matches!(entry.status, DownloadStatus::Done | DownloadStatus::Deleted { .. } | DownloadStatus::Cancelled { .. }) && entry.attempts == 0 {
+            entry.status = DownloadStatus::Queued
+        })
+    }
+    pub fn mark_in_progress(&mut self, program: &RadioProgram) -> Result<()> {
+        self.update(program, |entry| {
+            entry.status = DownloadStatus::InProgress;
+            entry.attempts += 1;
+        })
+    }
+    pub fn mark_failed(&mut self, program: &RadioProgram, reason: impl Into<String>) -> Result<()> {
+        let reason = reason.into();
+        self.update(program, |entry| entry.status = DownloadStatus::Failed { reason })
+    }
+    // placedの先頭が本体、残りは他メンバーのディレクトリに置いたリンク
+    pub fn mark_done(&mut self, program: &RadioProgram, placed: &[PathBuf], matched: &[String]) -> Result<()> {
+        let (byte_size, checksum) = file_checksum(&placed[0])?;
+        self.update(program, |entry| {
+            entry.status = DownloadStatus::Done;
+            entry.file_path = Some(placed[0].clone());
+            entry.linked_paths = placed[1..].to_vec();
+            entry.matched = matched.to_vec();
+            entry.byte_size = Some(byte_size);
+            entry.checksum = Some(checksum);
+        })
+    }
+    pub fn set_loudness(&mut self, program: &RadioProgram, loudness: Loudness, applied_gain_db: f64) -> Result<()> {
+        self.update(program, |entry| {
+            entry.loudness = Some(loudness);
+            entry.applied_gain_db = Some(applied_gain_db);
+        })
+    }
+    pub fn mark_deleted(&mut self, key: &str, reason: impl Into<String>) -> Result<()> {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.status = DownloadStatus::Deleted { reason: reason.into() };
+            entry.updated_at = Utc::now();
+        }
+        self.save()
+    }
+    // 番組表を取り直した局・日付にあるはずなのに見つからない未完了の番組は、録らずに打ち切る
+    pub fn cancel_vanished(&mut self, fetched: &[RadioProgram]) -> Result<Vec<String>> {
+        let keys = fetched.iter().map(ledger_key).collect::<HashSet<_>>();
+        let covered = fetched.iter().map(|p| (p.radio_channel.id.clone(), p.ft.with_timezone(&jst()).format("%Y%m%d").to_string())).collect::<HashSet<_>>();
+        let now = Utc::now();
+        let mut cancelled = vec![];
+        for (key, entry) in self.entries.iter_mut() {
+            let pending = matches!(entry.status, DownloadStatus::Queued | DownloadStatus::InProgress | DownloadStatus::Failed { .. });
+            if pending && !keys.contains(key) && covered.contains(&(entry.station_id.clone(), entry.ft[..8].to_owned())) {
+                entry.status = DownloadStatus::Cancelled { reason: "no longer in the schedule".to_owned() };
+                entry.updated_at = now;
+                cancelled.push(key.clone());
+            }
+        }
+        if !cancelled.is_empty() {
+            self.save()?;
+        }
+        Ok(cancelled)
+    }
+}
@@ -0,0 +1,29 @@
+pub mod radiko;
+pub mod matcher;
+pub mod play_log;
+pub mod tagging;
+pub mod adts;
+pub mod clip;
+pub mod ledger;
+pub mod output_path;
+pub mod timefree;
+pub mod recorder;
+pub mod retention;
+pub mod transcode;
+pub mod loudness;
+pub mod area;
+pub mod station;
+pub mod schema;
+pub mod firestore_write;
+pub mod expiry;
+pub mod archive;
+pub mod text;
+pub mod fuzzy;
+pub mod review;
+pub mod notify;
+pub mod watchlist;
+pub mod series;
+pub mod schedule;
+pub mod variants;
+pub mod snapshot;
+pub mod export;
@@ -0,0 +1,150 @@
+use std::f64::consts::PI;
+use std::fs::File;
+use std::path::Path;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+// ReplayGain 2.0の基準。R128_*_GAINは-23LUFS基準なので別に計算する
+pub const REPLAYGAIN_REFERENCE: f64 = -18.0;
+pub const R128_REFERENCE: f64 = -23.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    // EBU R128 (ITU-R BS.1770) のintegrated loudness
+    pub integrated_lufs: f64,
+    // サンプルピーク(1.0 = 0dBFS)
+    pub peak: f64,
+}
+
+impl Loudness {
+    pub fn replaygain_db(&self) -> f64 {
+        REPLAYGAIN_REFERENCE - self.integrated_lufs
+    }
+    // OpusのR128_TRACK_GAINはQ7.8の整数
+    pub fn r128_gain_q78(&self) -> i16 {
+        ((R128_REFERENCE - self.integrated_lufs) * 256.0).round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+    }
+    // 変換時にgain_dbだけ音量を変えた後の値
+    pub fn with_gain(&self, gain_db: f64) -> Self {
+        Loudness { integrated_lufs: self.integrated_lufs + gain_db, peak: self.peak * 10f64.powf(gain_db / 20.0) }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+// BS.1770のKウェイティング(高域シェルフ+ハイパス)を任意のサンプルレート向けに求める
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let (f0, g, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(g / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let highpass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+    [shelf, highpass]
+}
+
+fn block_loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+// 400msブロックを100msずつずらし、-70LUFSの絶対ゲートと-10LUの相対ゲートをかける
+fn gated_loudness(step_powers: &[f64]) -> f64 {
+    let blocks = step_powers.windows(4).map(|w| w.iter().sum::<f64>() / 4.0).collect::<Vec<_>>();
+    let absolute = blocks.iter().copied().filter(|p| block_loudness(*p) > -70.0).collect::<Vec<_>>();
+    if absolute.is_empty() { return f64::NEG_INFINITY; }
+    let threshold = block_loudness(absolute.iter().sum::<f64>() / absolute.len() as f64) - 10.0;
+    let relative = absolute.into_iter().filter(|p| block_loudness(*p) > threshold).collect::<Vec<_>>();
+    if relative.is_empty() { return f64::NEG_INFINITY; }
+    block_loudness(relative.iter().sum::<f64>() / relative.len() as f64)
+}
+
+pub fn measure(path: &Path) -> Result<Loudness> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+    let mut format = symphonia::default::get_probe().format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())?.format;
+    let track = format.default_track().context("no audio track")?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut filters: Vec<[Biquad; 2]> = vec![];
+    let mut step_len = 0usize;
+    let (mut step_sum, mut step_count) = (0.0, 0usize);
+    let mut step_powers = vec![];
+    let mut peak = 0f64;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        if packet.track_id() != track_id { continue; }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // 壊れたフレームは飛ばす
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let spec = *decoded.spec();
+        let channels = spec.channels.count();
+        if filters.len() != channels {
+            filters = vec![k_weighting(spec.rate as f64); channels];
+            step_len = (spec.rate / 10) as usize;
+        }
+        let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        samples.copy_interleaved_ref(decoded);
+        for frame in samples.samples().chunks(channels) {
+            // L/R/Cは重み1.0、サラウンドは1.41だが、radikoはステレオまでなので区別しない
+            for (sample, filter) in frame.iter().zip(filters.iter_mut()) {
+                peak = peak.max(sample.abs() as f64);
+                let shelved = filter[0].process(*sample as f64);
+                let y = filter[1].process(shelved);
+                step_sum += y * y;
+            }
+            step_count += 1;
+            if step_count == step_len {
+                step_powers.push(step_sum / step_len as f64);
+                step_sum = 0.0;
+                step_count = 0;
+            }
+        }
+    }
+    let integrated_lufs = gated_loudness(&step_powers);
+    if !integrated_lufs.is_finite() { anyhow::bail!("recording is silent: {}", path.display()) }
+    Ok(Loudness { integrated_lufs, peak })
+}
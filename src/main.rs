@@ -1,312 +1,55 @@
-use std::collections::HashMap;
-use std::ops::Deref;
-use markup5ever_rcdom::{Handle, NodeData, RcDom};
-use reqwest::{Client, Url};
-use xml5ever::driver::{parse_document, XmlParseOpts};
-use xml5ever::tendril::*;
-use anyhow::{Result, Context};
-use chrono::{Duration, NaiveDate, Local, DateTime, TimeDelta, Utc};
-use std::{env, fmt};
-use std::fmt::Formatter;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use reqwest::Client;
+use chrono::{Duration, NaiveDate, Local, TimeDelta, Utc};
+use std::env;
 use std::path::PathBuf;
-use std::str::FromStr;
 use firestore::{FirestoreDb, FirestoreDbOptions};
 use kdam::tqdm;
 use unicode_normalization::UnicodeNormalization;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 use tokio::join;
-use tokio_stream::StreamExt;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct RadioChannel {
-    id: String,
-    name: String,
-    banner_url: String,
-    area_id: String,
-}
-impl RadioChannel {
-    fn from_hashmap(hash_map: HashMap<&str, String>) -> Result<Self> {
-        Ok(RadioChannel {
-            id: hash_map.get("id").context("id not found.")?.clone(),
-            name: hash_map.get("name").context("name not found.")?.clone().nfkc().collect::<_>(),
-            banner_url: hash_map.get("banner").context("banner not found.")?.clone(),
-            area_id: hash_map.get("area_id").context("area_id not found.")?.clone(),
-        })
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct RadioProgram {
-    radio_channel: RadioChannel,
-    id: u64,
-    #[serde(with = "firestore::serialize_as_timestamp")]
-    ft: DateTime<Utc>,
-    #[serde(with = "firestore::serialize_as_timestamp")]
-    to: DateTime<Utc>,
-    #[serde(serialize_with = "serialize_td", deserialize_with = "deserialize_td")]
-    dur: TimeDelta,
-    title: String,
-    img: Option<String>,
-    info: Option<String>,
-    desc: Option<String>,
-    pfm: Option<String>,
-    on_air_music: Vec<OnAirMusic>,
-    #[serde(with = "firestore::serialize_as_timestamp")]
-    expire_at: DateTime<Utc>,
-}
-
-// pub fn serialize_dt<S>(datetime: &DateTime<Local>, serializer: S) -> Result<S::Ok, S::Error>
-// where
-//     S: Serializer,
-// {
-//     let s = datetime.to_rfc3339(); // RFC 3339形式で文字列に変換
-//     serializer.serialize_str(&s) // 文字列としてシリアル化
-// }
-//
-// // デシリアライザ
-// pub fn deserialize_dt<'de, D>(deserializer: D) -> Result<DateTime<Local>, D::Error>
-// where
-//     D: Deserializer<'de>,
-// {
-//     DateTime::parse_from_rfc3339(&String::deserialize(deserializer)?)
-//         .map(|dt| dt.with_timezone(&Local))
-//         .map_err(serde::de::Error::custom)
-// }
-//
-pub fn serialize_td<S>(timedelta: &TimeDelta, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    serializer.serialize_i64(timedelta.num_seconds())
-}
-
-pub fn deserialize_td<'de, D>(deserializer: D) -> Result<TimeDelta, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    Ok(TimeDelta::seconds(i64::deserialize(deserializer)?))
-}
-
-impl RadioProgram {
-    fn from_hashmap(hash_map: HashMap<String, Option<String>>, radio_channel: RadioChannel) -> Result<Self> {
-        Ok(RadioProgram {
-            radio_channel,
-            id: hash_map.get("id").context("id not found.")?.clone().unwrap().parse::<u64>()?,
-            ft: DateTime::from(DateTime::parse_from_str((hash_map.get("ft").context("ft not found.").unwrap().clone().unwrap() + " +0900").as_str(), "%Y%m%d%H%M%S %z")?),
-            to: DateTime::from(DateTime::parse_from_str((hash_map.get("to").context("to not found.").unwrap().clone().unwrap() + " +0900").as_str(), "%Y%m%d%H%M%S %z")?),
-            dur: TimeDelta::seconds(hash_map.get("dur").context("dur not found.")?.clone().unwrap().parse::<i64>()?),
-            title: hash_map.get("title").context("title not found.")?.clone().unwrap().nfkc().collect::<_>(),
-            img: hash_map.get("img").context("img not found.")?.clone(),
-            info: hash_map.get("info").context("info not found.")?.clone().and_then(|s| {
-                let body = format!("<body>{s}</body>");
-                let dom = parse_document(RcDom::default(), Default::default()).from_utf8().read_from(&mut body.as_bytes()).unwrap();
-                let result = node_to_markdown(&dom.document);
-                Some(result.nfkc().collect::<_>())
-            }),
-            desc: hash_map.get("desc").context("desc not found.")?.clone().and_then(|s| {
-                let body = format!("<body>{s}</body>");
-                let dom = parse_document(RcDom::default(), Default::default()).from_utf8().read_from(&mut body.as_bytes()).unwrap();
-                let result = node_to_markdown(&dom.document);
-                Some(result.nfkc().collect::<_>())
-            }),
-            pfm: hash_map.get("pfm").context("pfm not found.")?.clone().and_then(|s| Some(s.nfkc().collect::<_>())),
-            on_air_music: vec![],
-            expire_at: DateTime::from(DateTime::parse_from_str((hash_map.get("to").context("to not found.").unwrap().clone().unwrap() + " +0900").as_str(), "%Y%m%d%H%M%S %z")?) + TimeDelta::weeks(2),
-        })
-    }
-    fn app_url_scheme(&self) -> String {
-        format!("radiko://radiko.onelink.me/?deep_link_sub1={}&deep_link_sub2={}&deep_link_value={}", self.radio_channel.id, self.ft.format("%Y%m%d%H%M%S"), self.id)
-    }
-}
-impl fmt::Display for RadioProgram {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "RadioProgram(RadioChannel({}, {}, https://...., {}), {}, {}, {}, {}, {},info: {}, desc: ..., {}, {:?})", self.radio_channel.id, self.radio_channel.name, self.radio_channel.area_id
-               , self.id, self.ft.to_rfc3339(), self.to.to_rfc3339(), self.dur.num_minutes(),
-               self.info.clone().unwrap_or_else(|| "None".to_owned()), self.title,
-               self.pfm.clone().unwrap_or_else(|| "None".to_owned()), self.on_air_music)
-    }
-}
-
-#[derive(Clone, Serialize, Deserialize)]
-struct OnAirMusic {
-    artist_name: String,
-    artwork_url: String,
-    #[serde(serialize_with = "serialize_td", deserialize_with = "deserialize_td")]
-    start_time: TimeDelta,
-    music_title: String,
-}
-
-impl OnAirMusic {
-    async fn get_on_air_music(radio_program: RadioProgram, client: Client) -> Vec<Self> {
-        // let client = Client::new();
-        if radio_program.to > Local::now() { return vec![]; }
-        let url = Url::parse_with_params(format!("https://api.radiko.jp/music/api/v1/noas/{}", radio_program.radio_channel.id).as_str(),
-                                         &[("start_time_gte", radio_program.ft.to_rfc3339()), ("end_time_lt", radio_program.to.to_rfc3339())],
-        ).unwrap();
-        let json = client.get(url).send().await.unwrap().json::<Value>().await.unwrap();
-        json.get("data").unwrap_or(&Value::Array(vec![])).as_array().unwrap().iter().map(|v| {
-            OnAirMusic {
-                artist_name: v["artist_name"].as_str().unwrap().nfkc().collect::<_>(),
-                artwork_url: v["music"]["image"]["large"].as_str().unwrap().nfkc().collect::<_>(),
-                start_time: DateTime::from(DateTime::parse_from_rfc3339(v["displayed_start_time"].as_str().unwrap()).unwrap()) - radio_program.ft,
-                music_title: v["title"].as_str().unwrap().nfkc().collect::<_>(),
-            }
-        }).collect::<Vec<_>>()
-    }
-}
-
-impl fmt::Debug for OnAirMusic {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "OnAirMusic({}:{}  -  {}分後から)", self.music_title, self.artist_name, self.start_time.num_minutes())
-    }
-}
-
-fn dig_xml<T>(handle: Handle, path: Vec<&str>, call_func: fn(Handle) -> Option<T>) -> Vec<T> {
-    if path.is_empty() {
-        return match call_func(handle) {
-            None => { vec![] }
-            Some(v) => { vec![v] }
-        };
-    }
-    handle.children.borrow().iter().map(|child| {
-        match &child.data {
-            NodeData::Element { name, .. } => {
-                if path[0] == name.local.deref() {
-                    dig_xml(child.clone(), path[1..].to_owned(), call_func)
-                } else { vec![] }
-            }
-            _ => vec![]
-        }
-    }).flatten().collect::<Vec<_>>()
-}
-
-fn get_below_string(handle: Handle) -> Option<String> {
-    match &handle.children.borrow().clone().get(0) {
-        None => None,
-        Some(h) => {
-            match &h.data {
-                NodeData::Text { contents, .. } => { Some(contents.borrow().clone().to_string()) }
-                _ => None
-            }
-        }
-    }
-}
-
-
-fn node_to_markdown(handle: &Handle) -> String {
-    let dig = |handle: Handle| { handle.children.borrow().clone().into_iter().map(|child| node_to_markdown(&child)).collect::<Vec<_>>().join("") };
-    match &handle.data {
-        NodeData::Document => {
-            dig(handle.clone())
-        }
-        NodeData::Text { contents } => {
-            contents.borrow().to_string()
-        }
-        NodeData::Element { name, attrs, .. } => {
-            match name.local.to_lowercase().as_str() {
-                "a" => {
-                    let mut href = None;
-                    for attr in &attrs.borrow().clone() {
-                        if attr.name.local.deref() == "href" {
-                            href = Some(attr.value.clone());
-                            break;
-                        }
-                    }
-                    match href {
-                        None => { dig(handle.clone()) }
-                        Some(href) => { format!("[{}]({href})", dig(handle.clone())) }
-                    }
-                }
-                "b" | "strong" => format!("**{}**", dig(handle.clone())),
-                "p" => format!("{}\n", dig(handle.clone())),
-                "br" => format!("\n\n{}", dig(handle.clone())),
-                _ => dig(handle.clone()),
-            }
-        }
-        NodeData::Comment { .. } => String::new(),
-        elm => {
-            println!("err!:{:?}", elm);
-            String::new()
-        }
-    }
-}
+use futures::stream::{self, StreamExt};
+use radiko_cacher::archive::{members_sha256, rematch, ProgramArchive};
+use radiko_cacher::expiry::ExpiryPolicy;
+use radiko_cacher::ledger::ledger_key;
+use radiko_cacher::firestore_write::{delete_documents, write_documents, DocWrite, CONCURRENCY};
+use radiko_cacher::review::ReviewBook;
+use radiko_cacher::schema::{program_writes, stale_matches};
+use radiko_cacher::watchlist::load_watchlists;
+use radiko_cacher::notify::{Notification, NotifyLog};
+use radiko_cacher::text::Readings;
+use radiko_cacher::play_log::{PlayLog, PLAY_LOG_COLLECTION};
+use radiko_cacher::area::parse_regions;
+use radiko_cacher::station::{LogoCache, StationRecord, STATION_COLLECTION};
+use radiko_cacher::schedule::detect_changes;
+use radiko_cacher::snapshot::{Snapshot, SnapshotStore};
+use radiko_cacher::series::{detect, SeriesHistory, LOOKBACK_DAYS, MIN_EPISODES};
+use radiko_cacher::radiko::{jst, parse_channels, parse_programs, OnAirMusic, RadioProgram};
 
 #[tokio::main]
 async fn main() {
     let client = Client::new();
 
-    let doc = parse_document(RcDom::default(), XmlParseOpts::default()).from_utf8().read_from(
-        &mut client.get("https://radiko.jp/v3/station/region/full.xml").send().await.unwrap().text().await.unwrap().as_bytes()
-    ).unwrap();
-    let channels_hashmap = dig_xml(doc.document, vec!["region", "stations", "station"], |handle| {
-        match &handle.data {
-            NodeData::Element { .. } => {
-                Some(handle.children.borrow().clone().into_iter().filter_map(|child| {
-                    match &child.data {
-                        NodeData::Element { name, .. } => {
-                            match name.local.deref() {
-                                "id" => { Some(("id", get_below_string(child).unwrap())) }
-                                "name" => { Some(("name", get_below_string(child).unwrap())) }
-                                "banner" => { Some(("banner", get_below_string(child).unwrap())) }
-                                "area_id" => { Some(("area_id", get_below_string(child).unwrap())) }
-                                _ => None
-                            }
-                        }
-                        _ => None
-                    }
-                }).collect::<HashMap<_, _>>())
-            }
-            _ => None
-        }
-    });
-    let channels = channels_hashmap.into_iter().map(|hash_map| RadioChannel::from_hashmap(hash_map).unwrap()).collect::<Vec<_>>();
+    let station_xml = client.get("https://radiko.jp/v3/station/region/full.xml").send().await.unwrap().text().await.unwrap();
+    let channels = parse_channels(station_xml.as_str());
     // for channel in &channels {
     //     println!("{:?}", channel)
     // }
 
-    let program_joiner = channels.iter().map(|channel| NaiveDate::from((Local::now() - Duration::days(1)).naive_local()).iter_days().take(8).map(|date| {
-        (channel.clone(), client.get(format!("https://radiko.jp/v3/program/station/date/{}/{}.xml", date.format("%Y%m%d"), channel.id)).send())
-    })).flatten().collect::<Vec<_>>();
+    let program_joiner = channels.iter().flat_map(|channel| NaiveDate::from((Local::now() - Duration::days(1)).naive_local()).iter_days().take(8).map(|date| {
+        (channel.clone(), date, client.get(format!("https://radiko.jp/v3/program/station/date/{}/{}.xml", date.format("%Y%m%d"), channel.id)).send())
+    })).collect::<Vec<_>>();
 
+    // 取った番組表は局・放送日ごとにそのまま残す(snapshot コマンドで差分を見る)
+    let snapshots = SnapshotStore::open(env::var("RADIKO_SNAPSHOT_DIR").unwrap_or("schedule_snapshots".to_owned())).unwrap();
+    let fetched_at = Utc::now();
     let mut programs = vec![];
-    for (channel, req) in tqdm!(program_joiner.into_iter(),desc="Parse XML") {
+    for (channel, date, req) in tqdm!(program_joiner.into_iter(),desc="Parse XML") {
         // if channel.id != "JORF" { continue; }
-        let doc = parse_document(RcDom::default(), XmlParseOpts::default()).from_utf8().read_from(&mut req.await.unwrap().text().await.unwrap().as_bytes()).unwrap();
-        let programs_hashmaps = dig_xml(doc.document, vec!["radiko", "stations", "station", "progs", "prog"], |handle| match &handle.data {
-            NodeData::Element { attrs, .. } => {
-                let mut program_meta_hashmap = handle.children.borrow().clone().into_iter().filter_map(|child| {
-                    match &child.data {
-                        NodeData::Element { name, .. } => {
-                            match name.local.deref() {
-                                "title" => { Some(("title".to_owned(), get_below_string(child))) }
-                                "img" => { Some(("img".to_owned(), get_below_string(child))) }
-                                "info" => { Some(("info".to_owned(), get_below_string(child))) }
-                                "desc" => { Some(("desc".to_owned(), get_below_string(child))) }
-                                "pfm" => { Some(("pfm".to_owned(), get_below_string(child))) }
-                                _ => None
-                            }
-                        }
-                        _ => None
-                    }
-                }).collect::<HashMap<_, _>>();
-                let program_date_hashmap = attrs.borrow().clone().into_iter().map(|v| (v.name.local.to_string(), Some(v.value.to_string()))).collect::<HashMap<_, _>>();
-                program_meta_hashmap.extend(program_date_hashmap);
-                Some(program_meta_hashmap)
-            }
-            _ => None
-        });
-        programs.extend(programs_hashmaps.into_iter().filter_map(|hash_map: HashMap<_, _>| {
-            match RadioProgram::from_hashmap(hash_map, channel.clone()) {
-                Ok(v) => {
-                    if v.to < Local::now() - TimeDelta::hours(4) {
-                        None
-                    } else {
-                        Some(v)
-                    }
-                }
-                Err(_) => { None }
-            }
+        let fetched = parse_programs(req.await.unwrap().text().await.unwrap().as_str(), &channel);
+        snapshots.save(&Snapshot { station_id: channel.id.clone(), date, fetched_at, programs: fetched.clone() }).unwrap();
+        programs.extend(fetched.into_iter().filter(|v| {
+            v.to >= Local::now() - TimeDelta::hours(4)
         }).collect::<Vec<_>>());
     }
     println!();
@@ -321,66 +64,160 @@ async fn main() {
         let (on_air, program) = join!(awaiter).0.unwrap();
         programs.push(RadioProgram { on_air_music: on_air.await, ..program })
     };
+    // マッチしなかった番組も含めて、取得したものは全部ローカルに残しておく
+    let readings = Readings::from_json(include_str!("readings.json")).unwrap();
+    let archive = ProgramArchive::open(env::var("RADIKO_PROGRAM_ARCHIVE").unwrap_or("program_archive".to_owned())).unwrap().with_readings(readings);
+    // 前回取った番組表と比べて、休止・振替・番組名の変更を拾う
+    let mut previous = vec![];
+    for date in programs.iter().map(|p| p.ft.with_timezone(&jst()).date_naive()).collect::<BTreeSet<_>>() {
+        previous.extend(archive.load_day(date).unwrap());
+    }
+    let schedule_events = detect_changes(&previous, &programs, Utc::now());
+    for event in &schedule_events {
+        println!("schedule change: {} {} ({}) {:?}", event.program.ft.with_timezone(&jst()).format("%m/%d %H:%M"), event.program.title, event.program.radio_channel.id, event.change);
+    }
+    archive.apply_changes(&schedule_events).unwrap();
+    println!("archived: {} programs", archive.store(&programs).unwrap());
     let member_json: Value = serde_json::from_str(include_str!("members.json").nfkc().collect::<String>().as_str()).unwrap();
+    let expiry_policy: ExpiryPolicy = serde_json::from_str(include_str!("expiry_rules.json")).unwrap();
+    let match_rules: Value = serde_json::from_str(include_str!("match_rules.json").nfkc().collect::<String>().as_str()).unwrap();
     let firestore_db = FirestoreDb::with_options_service_account_key_file(FirestoreDbOptions::new("hello-radiko".to_owned()), PathBuf::from(env::var("FIRESTORE_CRED_JSON").unwrap())).await.unwrap();
 
-    for program in programs {
-        let res = search_artist(program.clone(), member_json.clone());
-        if !res.is_empty() {
-            let prog = program.clone();
-            println!("{},{}:{:?}", prog.title.clone(), prog.pfm.clone().unwrap_or("".to_owned()), res);
-            println!("{}", serde_json::to_string(&program.on_air_music.clone()).unwrap());
-            for re in res {
-                // println!("{}", serde_json::to_string(&program.clone()).unwrap());
-
-                let parent = firestore_db.parent_path("hello-radiko-data", "programs").unwrap();
-                firestore_db
-                    .fluent()
-                    .update()
-                    .in_col(re.as_str())
-                    .document_id(&prog.id.to_string().as_str())
-                    .parent(parent)
-                    .object(&prog.clone())
-                    .execute::<RadioProgram>().await.unwrap();
-            }
-        }
+    // 局の情報は番組ごとに埋め込まず、stationsコレクションに1局1ドキュメントで書く
+    let regions = parse_regions(station_xml.as_str(), &channels);
+    let mut logo_cache = LogoCache::open(env::var("RADIKO_LOGO_CACHE").unwrap_or("station_logos".to_owned())).unwrap();
+    let station_parent: String = firestore_db.parent_path("hello-radiko-data", "stations").unwrap().into();
+    let mut station_writes = vec![];
+    for channel in tqdm!(channels.iter(),desc="Fetch Station Logos") {
+        let region_id = regions.iter().find(|r| r.stations.iter().any(|c| c.id == channel.id)).map(|r| r.id.clone());
+        station_writes.push(DocWrite {
+            parent: station_parent.clone(),
+            collection: STATION_COLLECTION.to_owned(),
+            document_id: channel.id.clone(),
+            object: StationRecord::build(channel, region_id, &mut logo_cache, &client).await,
+        });
     }
-}
-
-
-fn search_artist(radio_program: RadioProgram, member_json: Value) -> Vec<String> {
-    let mut found = vec![];
-    let _ = member_json.as_object().unwrap().into_iter().map(|(group_name, members)| {
-        // println!("{group_name}:{members}");
-        if group_name != "OG" {
-            if radio_program.title.contains(group_name)
-                || radio_program.desc.clone().map(|t| { t.contains(group_name) }).unwrap_or(false)
-                || radio_program.info.clone().map(|t| { t.contains(group_name) }).unwrap_or(false)
-                || radio_program.pfm.clone().map(|t| { t.contains(group_name) }).unwrap_or(false) {
-                found.push(group_name.to_owned())
+    logo_cache.save().unwrap();
+    println!();
+    println!("stations: {}", write_documents(&firestore_db, &station_writes).await);
+
+    let play_log_parent: String = firestore_db.parent_path("hello-radiko-data", "play_logs").unwrap().into();
+    let play_log_writes = programs.iter().flat_map(|program| PlayLog::from_program(program, &member_json, &match_rules)).map(|play_log| DocWrite {
+        parent: play_log_parent.clone(),
+        collection: PLAY_LOG_COLLECTION.to_owned(),
+        document_id: play_log.document_id(),
+        object: play_log,
+    }).collect::<Vec<_>>();
+    println!("play logs: {}", write_documents(&firestore_db, &play_log_writes).await);
+
+    let mut program_writes_all = vec![];
+    let mut match_writes_all = vec![];
+    let programs_keys = programs.iter().map(ledger_key).collect::<HashSet<_>>();
+    // ハロプロのメンバー一覧もウォッチリストの1つとして扱う
+    let watchlists = load_watchlists(include_str!("watchlists.json"), &PathBuf::from(env::var("RADIKO_WATCHLISTS").unwrap_or("watchlists.json".to_owned()))).unwrap();
+    let no_members = Value::Object(Default::default());
+    let mut notifications = vec![];
+    // 変更後の番組が今回の番組表にあるものは、その番組のドキュメントに変更を書く
+    let changes_by_id = schedule_events.iter().filter_map(|e| e.change.current_program_id(&e.program).map(|id| (id, e.change.clone()))).collect::<HashMap<_, _>>();
+    let current_ids = programs.iter().map(|p| p.id).collect::<HashSet<_>>();
+    for watchlist in &watchlists {
+        let reviews = ReviewBook::load(&firestore_db, &watchlist.collection).await.unwrap();
+        // 今回の番組表から消えた番組は、公開済みのドキュメントに休止・振替を書いておく
+        for event in schedule_events.iter().filter(|e| !current_ids.contains(&e.program.id)) {
+            let matched = watchlist.match_program(&event.program, &member_json, &match_rules).unwrap();
+            if matched.is_empty() { continue; }
+            if let Some((mut program_write, match_writes)) = program_writes(&firestore_db, &watchlist.collection, &event.program, &matched, watchlist.member_json(&member_json, &no_members), &expiry_policy, &reviews).unwrap() {
+                program_write.object.change = Some(event.change.clone());
+                if !watchlist.notify.is_empty() {
+                    notifications.push((watchlist, Notification::new(&watchlist.name, &event.program, program_write.object.matched.clone()).with_change(event.change.clone())));
+                }
+                program_writes_all.push(program_write);
+                match_writes_all.extend(match_writes);
             }
         }
-        let _ = members.as_object().unwrap().into_iter().map(|(member_name, literals)| {
-            for literal in literals.as_array().unwrap() {
-                let literal_string = literal.as_str().unwrap();
-                // println!("literal_string:{}", literal_string);
-                if radio_program.title.contains(literal_string)
-                    || radio_program.desc.clone().map(|t| { t.contains(literal_string) }).unwrap_or(false)
-                    || radio_program.info.clone().map(|t| { t.contains(literal_string) }).unwrap_or(false)
-                    || radio_program.pfm.clone().map(|t| { t.contains(literal_string) }).unwrap_or(false) {
-                    if member_name == "高橋愛" {
-                        if radio_program.title.contains("高橋愛子")
-                            || radio_program.desc.clone().map(|t| { t.contains("高橋愛子") }).unwrap_or(false)
-                            || radio_program.info.clone().map(|t| { t.contains("高橋愛子") }).unwrap_or(false)
-                            || radio_program.pfm.clone().map(|t| { t.contains("高橋愛子") }).unwrap_or(false) {
-                            break;
-                        }
+        for program in &programs {
+            let matched = watchlist.match_program(program, &member_json, &match_rules).unwrap();
+            if matched.is_empty() { continue; }
+            println!("[{}] {},{}:{:?}", watchlist.name, program.title, program.pfm.clone().unwrap_or("".to_owned()), matched);
+            println!("{}", serde_json::to_string(&program.on_air_music).unwrap());
+            if let Some((mut program_write, match_writes)) = program_writes(&firestore_db, &watchlist.collection, program, &matched, watchlist.member_json(&member_json, &no_members), &expiry_policy, &reviews).unwrap() {
+                program_write.object.change = changes_by_id.get(&program.id).cloned();
+                // 放送が終わったものは知らせない
+                if !watchlist.notify.is_empty() && program.to > Utc::now() {
+                    let notification = Notification::new(&watchlist.name, program, program_write.object.matched.clone());
+                    if let Some(change) = &program_write.object.change {
+                        notifications.push((watchlist, notification.clone().with_change(change.clone())));
                     }
-                    found.push(member_name.to_owned());
-                    break;
+                    notifications.push((watchlist, notification));
                 }
+                program_writes_all.push(program_write);
+                match_writes_all.extend(match_writes);
             }
-        }).collect::<Vec<_>>();
-    }).collect::<Vec<_>>();
-    found
-}
\ No newline at end of file
+        }
+    }
+    // 購読中のシリーズの回を記録し、新しく休止・差し替えになった回は購読しているウォッチリストに知らせる
+    if watchlists.iter().any(|w| w.targets.iter().any(|t| !t.series.is_empty())) {
+        let mut series_history = SeriesHistory::open(env::var("RADIKO_SERIES_HISTORY").unwrap_or("series_history.json".to_owned())).unwrap();
+        let mut recent = vec![];
+        for date in archive.days().unwrap().into_iter().rev().take(LOOKBACK_DAYS) {
+            recent.extend(archive.load_day(date).unwrap());
+        }
+        let since = recent.iter().map(|p| p.ft).min().unwrap_or(Utc::now());
+        for series in detect(&recent, MIN_EPISODES) {
+            let subscribers = watchlists.iter().map(|w| (w, w.series_targets(&series))).filter(|(_, names)| !names.is_empty()).collect::<Vec<_>>();
+            if subscribers.is_empty() { continue; }
+            for missed in series_history.merge(series.clone(), since).iter().filter(|m| m.expected > Utc::now() - TimeDelta::days(7)) {
+                println!("series {} ({}): {} {:?}", series.title, series.id, missed.expected.with_timezone(&jst()).format("%m/%d %H:%M"), missed.gap);
+                for (watchlist, names) in subscribers.iter().filter(|(w, _)| !w.notify.is_empty()) {
+                    notifications.push((watchlist, Notification::missed_episode(&watchlist.name, &series, missed, names.clone())));
+                }
+            }
+        }
+        series_history.save().unwrap();
+    }
+    // メンバー一覧が変わっていたら、アーカイブ済みの過去の番組もマッチングし直す
+    let members_hash = members_sha256(include_str!("members.json"));
+    let mut meta = archive.meta().unwrap();
+    if let Some(hello_project) = watchlists.iter().find(|w| w.hello_project).filter(|_| meta.members_sha256.as_ref() != Some(&members_hash)) {
+        let reviews = ReviewBook::load(&firestore_db, &hello_project.collection).await.unwrap();
+        let mut past = vec![];
+        for date in archive.days().unwrap() {
+            past.extend(archive.load_day(date).unwrap().into_iter().filter(|p| !programs_keys.contains(&ledger_key(p))));
+        }
+        let rematched = rematch(past, &member_json, &match_rules);
+        println!("member list changed: {} archived programs matched", rematched.len());
+        for (prog, matched) in &rematched {
+            if let Some((program_write, match_writes)) = program_writes(&firestore_db, &hello_project.collection, prog, matched, &member_json, &expiry_policy, &reviews).unwrap() {
+                program_writes_all.push(program_write);
+                match_writes_all.extend(match_writes);
+            }
+        }
+        meta.members_sha256 = Some(members_hash);
+        meta.updated_at = Some(Utc::now());
+    }
+    println!("programs: {}", write_documents(&firestore_db, &program_writes_all).await);
+    let match_summary = write_documents(&firestore_db, &match_writes_all).await;
+    println!("matches: {match_summary}");
+    // 該当しなくなった名前のmatchesは、TTLを待たずに消す
+    let stale = stream::iter(&program_writes_all).map(|write| stale_matches(&firestore_db, write)).buffer_unordered(CONCURRENCY).collect::<Vec<_>>().await;
+    let mut stale_deletes = vec![];
+    for result in stale {
+        match result {
+            Ok(deletes) => stale_deletes.extend(deletes),
+            Err(err) => println!("failed to list matches: {err:#}"),
+        }
+    }
+    println!("stale matches: {}", delete_documents(&firestore_db, &stale_deletes).await);
+    // 書き込みに失敗したら次回もう一度マッチングし直す
+    if match_summary.failed == 0 {
+        archive.save_meta(&meta).unwrap();
+    }
+
+    let mut notify_log = NotifyLog::open(env::var("RADIKO_NOTIFY_LOG").unwrap_or("notified.json".to_owned())).unwrap();
+    for (watchlist, notification) in notifications {
+        if let Err(err) = notify_log.deliver(&client, &watchlist.notify, notification).await {
+            println!("notification failed ({}): {err:#}", watchlist.name);
+        }
+    }
+    notify_log.save().unwrap();
+}
@@ -0,0 +1,184 @@
+use std::sync::LazyLock;
+use chrono::TimeDelta;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::radiko::{deserialize_td, serialize_td, RadioProgram};
+use crate::fuzzy::{self, FuzzyHit, FuzzyRule, Script};
+use crate::text::{contains_folded, fold, fold_contains, fold_tokens, Readings};
+use crate::variants::match_literals;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MatchType {
+    Text,
+    SongPlayed {
+        #[serde(serialize_with = "serialize_td", deserialize_with = "deserialize_td")]
+        start_time: TimeDelta,
+        music_title: String,
+        artist_name: String,
+    },
+    // 局・曜日・時間帯の条件だけで選んだもの
+    Schedule,
+    // 購読している番組シリーズの回
+    Series {
+        series_id: String,
+    },
+    // かな・ローマ字・誤字を許して見つけたもの。確度は低い
+    Fuzzy {
+        script: Script,
+        pattern: String,
+        found: String,
+        distance: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Confidence {
+    Low,
+    High,
+}
+
+impl MatchType {
+    pub fn confidence(&self) -> Confidence {
+        match self {
+            MatchType::Fuzzy { .. } => Confidence::Low,
+            _ => Confidence::High,
+        }
+    }
+}
+
+// どれか1つでも確かな根拠があれば高
+pub fn confidence(match_types: &[MatchType]) -> Confidence {
+    match_types.iter().map(|m| m.confidence()).max().unwrap_or(Confidence::Low)
+}
+
+static READINGS: LazyLock<Readings> = LazyLock::new(|| Readings::from_json(include_str!("readings.json")).unwrap());
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchedProgram {
+    #[serde(flatten)]
+    pub program: RadioProgram,
+    // 旧形式の文書にはない
+    #[serde(default)]
+    pub match_types: Vec<MatchType>,
+}
+
+pub fn song_plays_enabled(match_rules: &Value, name: &str) -> bool {
+    match_rules["rules"][name]["song_plays"].as_bool()
+        .or(match_rules["default"]["song_plays"].as_bool())
+        .unwrap_or(false)
+}
+
+// メンバーに設定があればそれを、なければグループの設定を使う
+pub fn member_song_plays_enabled(match_rules: &Value, group: &str, member: &str) -> bool {
+    match_rules["rules"][member]["song_plays"].as_bool().unwrap_or_else(|| song_plays_enabled(match_rules, group))
+}
+
+// rules.{name}.exclude のどれかが含まれていれば、その名前の表記が出ていても別人とみなす
+pub fn excluded(match_rules: &Value, name: &str, text: &str) -> bool {
+    match_rules["rules"][name]["exclude"].as_array().into_iter().flatten().filter_map(|l| l.as_str()).any(|l| fold_contains(text, l))
+}
+
+// 名前を探すときに使う表記の一覧(グループ名はそれ自体)
+pub fn literals_of(name: &str, member_json: &Value, match_rules: &Value) -> Vec<String> {
+    let mut literals = member_json.as_object().unwrap().iter()
+        .find_map(|(group_name, members)| if group_name == name { Some(vec![name]) } else { members[name].as_array().map(|l| l.iter().filter_map(|l| l.as_str()).collect()) })
+        .unwrap_or_default();
+    literals.extend(match_rules["rules"][name]["artists"].as_array().into_iter().flatten().filter_map(|l| l.as_str()));
+    match_literals(literals)
+}
+
+pub fn search_artist(radio_program: RadioProgram, member_json: Value, match_rules: Value) -> Vec<(String, MatchType)> {
+    let mut found = vec![];
+    // 異体字・カナ・空白の揺れを寄せてから比べる
+    let texts = [Some(&radio_program.title), radio_program.desc.as_ref(), radio_program.info.as_ref(), radio_program.pfm.as_ref()]
+        .into_iter().flatten().map(|t| fold_tokens(t)).collect::<Vec<_>>();
+    let mentioned = |literal: &str| {
+        let literal = fold(literal);
+        texts.iter().any(|t| contains_folded(t, &literal))
+    };
+    // 誤字を許す探索は区切りを見ない
+    let fuzzy_texts = texts.iter().map(|t| t.replace(' ', "")).collect::<Vec<_>>();
+    let excluded_here = |name: &str| texts.iter().any(|t| excluded(&match_rules, name, t));
+    // ソロ・ユニット名義もartist_nameに含まれていれば拾う
+    let played = |name: &str, literals: Vec<&str>| {
+        let mut literals = literals;
+        literals.extend(match_rules["rules"][name]["artists"].as_array().into_iter().flatten().filter_map(|l| l.as_str()));
+        radio_program.on_air_music.iter().filter(|music| {
+            literals.iter().any(|literal| fold_contains(&music.artist_name, literal))
+                && !excluded(&match_rules, name, &music.artist_name)
+        }).map(|music| {
+            (name.to_owned(), MatchType::SongPlayed {
+                start_time: music.start_time,
+                music_title: music.music_title.clone(),
+                artist_name: music.artist_name.clone(),
+            })
+        }).collect::<Vec<_>>()
+    };
+    // 既定では使わない。名前(メンバーはなければグループ)ごとに有効にする
+    let fuzzy_match = |group: &str, name: &str, literals: &[String]| {
+        let rule = FuzzyRule::for_member(&match_rules, group, name);
+        if !rule.enabled || excluded_here(name) { return None; }
+        fuzzy::search(&fuzzy_texts, literals, &READINGS, &rule).map(|FuzzyHit { script, pattern, found, distance }| {
+            (name.to_owned(), MatchType::Fuzzy { script, pattern, found, distance })
+        })
+    };
+    let _ = member_json.as_object().unwrap().into_iter().map(|(group_name, members)| {
+        // println!("{group_name}:{members}");
+        if group_name != "OG" {
+            if mentioned(group_name) && !excluded_here(group_name) {
+                found.push((group_name.to_owned(), MatchType::Text))
+            } else {
+                found.extend(fuzzy_match(group_name, group_name, &[group_name.to_owned()]));
+            }
+            if song_plays_enabled(&match_rules, group_name) {
+                found.extend(played(group_name, vec![group_name]));
+            }
+        }
+        let _ = members.as_object().unwrap().into_iter().map(|(member_name, literals)| {
+            let literals = match_literals(literals.as_array().unwrap().iter().filter_map(|l| l.as_str()));
+            let mut text_matched = false;
+            for literal_string in &literals {
+                // println!("literal_string:{}", literal_string);
+                if mentioned(literal_string) {
+                    text_matched = true;
+                    if excluded_here(member_name) {
+                        break;
+                    }
+                    found.push((member_name.to_owned(), MatchType::Text));
+                    break;
+                }
+            }
+            if !text_matched {
+                found.extend(fuzzy_match(group_name, member_name, &literals));
+            }
+            if member_song_plays_enabled(&match_rules, group_name, member_name) {
+                found.extend(played(member_name, literals.iter().map(|l| l.as_str()).collect()));
+            }
+        }).collect::<Vec<_>>();
+    }).collect::<Vec<_>>();
+    found
+}
+
+pub fn search_artist_name(artist_name: &str, member_json: &Value, match_rules: &Value) -> (Vec<String>, Vec<String>) {
+    let mut groups = vec![];
+    let mut members = vec![];
+    for (group_name, group_members) in member_json.as_object().unwrap() {
+        if group_name != "OG" && fold_contains(artist_name, group_name) && !excluded(match_rules, group_name, artist_name) {
+            groups.push(group_name.to_owned());
+        }
+        for (member_name, literals) in group_members.as_object().unwrap() {
+            if excluded(match_rules, member_name, artist_name) { continue; }
+            let mut literals = literals.as_array().unwrap().iter().filter_map(|l| l.as_str()).collect::<Vec<_>>();
+            literals.extend(match_rules["rules"][member_name.as_str()]["artists"].as_array().into_iter().flatten().filter_map(|l| l.as_str()));
+            if match_literals(literals).iter().any(|literal| fold_contains(artist_name, literal)) {
+                members.push(member_name.to_owned());
+                if group_name != "OG" && !groups.contains(group_name) {
+                    groups.push(group_name.to_owned());
+                }
+            }
+        }
+    }
+    (groups, members)
+}
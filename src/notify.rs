@@ -0,0 +1,183 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use crate::radiko::{jst, RadioProgram};
+use crate::schedule::ScheduleChange;
+use crate::series::{Gap, MissedEpisode, Series};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotifyChannel {
+    // 番組と該当者をそのままJSONでPOSTする
+    Webhook { url: String },
+    Slack { url: String },
+    Discord { url: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub watchlist: String,
+    pub program_id: u64,
+    pub station_id: String,
+    pub title: String,
+    pub ft: String,
+    pub names: Vec<String>,
+    // 番組表の変更の知らせなら、その種類
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<ScheduleChange>,
+    // 購読中のシリーズの回が休止・差し替えになった知らせなら、そのシリーズ
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub series_id: Option<String>,
+}
+
+impl Notification {
+    pub fn new(watchlist: &str, program: &RadioProgram, names: Vec<String>) -> Self {
+        Notification {
+            watchlist: watchlist.to_owned(),
+            program_id: program.id,
+            station_id: program.radio_channel.id.clone(),
+            title: program.title.clone(),
+            ft: program.ft.with_timezone(&jst()).format("%Y-%m-%d %H:%M").to_string(),
+            names,
+            change: None,
+            series_id: None,
+        }
+    }
+    // 差し替えなら、枠に入った番組のID。枠が空なら0
+    pub fn missed_episode(watchlist: &str, series: &Series, missed: &MissedEpisode, names: Vec<String>) -> Self {
+        let (program_id, change) = match &missed.gap {
+            Gap::Missing => (0, ScheduleChange::Cancelled),
+            Gap::Preempted { program_id, title } => (*program_id, ScheduleChange::Preempted { program_id: *program_id, title: title.clone() }),
+        };
+        Notification {
+            watchlist: watchlist.to_owned(),
+            program_id,
+            station_id: series.station_id.clone(),
+            title: series.title.clone(),
+            ft: missed.expected.with_timezone(&jst()).format("%Y-%m-%d %H:%M").to_string(),
+            names,
+            change: Some(change),
+            series_id: Some(series.id.clone()),
+        }
+    }
+    pub fn with_change(self, change: ScheduleChange) -> Self {
+        Notification { change: Some(change), ..self }
+    }
+    pub fn text(&self) -> String {
+        let change = match &self.change {
+            None => String::new(),
+            Some(ScheduleChange::Cancelled) => " [休止]".to_owned(),
+            Some(ScheduleChange::Preempted { title, .. }) => format!(" [休止: {title}]"),
+            Some(ScheduleChange::Rescheduled { ft, .. }) => format!(" [{}に変更]", ft.with_timezone(&jst()).format("%Y-%m-%d %H:%M")),
+            Some(ScheduleChange::Retitled { title, .. }) => format!(" [番組名変更: {title}]"),
+        };
+        format!("[{}] {} {} ({}){change}: {}", self.watchlist, self.ft, self.title, self.station_id, self.names.join(", "))
+    }
+    // 同じ番組・同じ該当者で2回送らないためのキー
+    pub fn keys(&self) -> Vec<String> {
+        let suffix = self.change.as_ref().map(|c| format!("/{}", c.label())).unwrap_or_default();
+        let program = match &self.series_id {
+            Some(series_id) => format!("series/{series_id}/{}", self.ft),
+            None => self.program_id.to_string(),
+        };
+        self.names.iter().map(|name| format!("{}/{program}/{}{suffix}", self.watchlist, name)).collect()
+    }
+}
+
+impl NotifyChannel {
+    // 通知済みのキーに付ける、チャンネルごとの印。URLはそのまま残さない
+    pub fn id(&self) -> String {
+        let url = match self {
+            NotifyChannel::Webhook { url } | NotifyChannel::Slack { url } | NotifyChannel::Discord { url } => url,
+        };
+        format!("{:x}", Sha256::digest(url.as_bytes()))[..12].to_owned()
+    }
+    pub async fn send(&self, client: &Client, notification: &Notification) -> Result<()> {
+        let request = match self {
+            NotifyChannel::Webhook { url } => client.post(url).json(notification),
+            NotifyChannel::Slack { url } => client.post(url).json(&json!({ "text": notification.text() })),
+            NotifyChannel::Discord { url } => client.post(url).json(&json!({ "content": notification.text() })),
+        };
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+// 通知済みのキー。ローカルのJSONに持つ
+pub struct NotifyLog {
+    path: PathBuf,
+    pub sent: BTreeSet<String>,
+}
+
+impl NotifyLog {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let sent = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(NotifyLog { path, sent })
+    }
+    pub fn save(&self) -> Result<()> {
+        fs::write(&self.path, serde_json::to_string_pretty(&self.sent)?)?;
+        Ok(())
+    }
+    // そのチャンネルにまだ送っていない該当者だけに絞る。チャンネルの印のないキーは全チャンネルに送ったもの(以前の形式)
+    pub fn unsent(&self, notification: Notification, channel_id: &str) -> Option<Notification> {
+        let names = notification.names.iter().zip(notification.keys())
+            .filter(|(_, key)| !self.sent.contains(key) && !self.sent.contains(&format!("{key}@{channel_id}")))
+            .map(|(name, _)| name.clone()).collect::<Vec<_>>();
+        (!names.is_empty()).then_some(Notification { names, ..notification })
+    }
+    // チャンネルごとに、送れたものだけ通知済みにする。失敗したチャンネルには次回また送る
+    pub async fn deliver(&mut self, client: &Client, channels: &[NotifyChannel], notification: Notification) -> Result<()> {
+        let mut errors = vec![];
+        for channel in channels {
+            let channel_id = channel.id();
+            let Some(notification) = self.unsent(notification.clone(), &channel_id) else { continue };
+            match channel.send(client, &notification).await {
+                Ok(_) => self.sent.extend(notification.keys().into_iter().map(|key| format!("{key}@{channel_id}"))),
+                Err(err) => errors.push(format!("{err:#}")),
+            }
+        }
+        if errors.is_empty() { Ok(()) } else { Err(anyhow!("{} of {} channels failed: {}", errors.len(), channels.len(), errors.join("; "))) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification() -> Notification {
+        let program: RadioProgram = serde_json::from_value(json!({
+            "station_id": "TBS", "id": 1, "ft": "2026-10-18T12:00:00Z", "to": "2026-10-18T13:00:00Z", "dur": 3600,
+            "title": "番組", "img": null, "info": null, "desc": null, "pfm": null, "on_air_music": []
+        })).unwrap();
+        Notification::new("hello-project", &program, vec!["中澤裕子".to_owned(), "石田亜佑美".to_owned()])
+    }
+
+    fn log(sent: &[&str]) -> NotifyLog {
+        NotifyLog { path: PathBuf::new(), sent: sent.iter().map(|s| s.to_string()).collect() }
+    }
+
+    #[test]
+    fn sent_is_tracked_per_channel() {
+        let slack = NotifyChannel::Slack { url: "https://hooks.slack.com/a".to_owned() };
+        let discord = NotifyChannel::Discord { url: "https://discord.com/api/webhooks/b".to_owned() };
+        let log = log(&[&format!("hello-project/1/中澤裕子@{}", slack.id())]);
+        assert_eq!(log.unsent(notification(), &slack.id()).unwrap().names, ["石田亜佑美"]);
+        assert_eq!(log.unsent(notification(), &discord.id()).unwrap().names, ["中澤裕子", "石田亜佑美"]);
+    }
+
+    #[test]
+    fn keys_without_a_channel_count_for_every_channel() {
+        let log = log(&["hello-project/1/中澤裕子", "hello-project/1/石田亜佑美"]);
+        assert!(log.unsent(notification(), "any").is_none());
+    }
+}
@@ -0,0 +1,195 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Result};
+use serde_json::Value;
+use crate::radiko::{jst, RadioProgram};
+
+pub const DEFAULT_TEMPLATE: &str = "{station_id}_{ft:%Y%m%d%H%M}_{title}";
+
+// ファイル名に使えない文字は全角に置き換える
+pub fn sanitize(s: &str) -> String {
+    let replaced = s.chars().map(|c| match c {
+        '/' => '／',
+        '\\' => '＼',
+        ':' => '：',
+        '*' => '＊',
+        '?' => '？',
+        '"' => '”',
+        '<' => '＜',
+        '>' => '＞',
+        '|' => '｜',
+        '\n' | '\r' | '\t' => ' ',
+        c if c.is_control() => '_',
+        c => c,
+    }).collect::<String>();
+    let trimmed = replaced.trim().trim_end_matches('.').trim();
+    // 1コンポーネント255バイト制限に拡張子の分の余裕を残す
+    let mut end = trimmed.len().min(200);
+    while !trimmed.is_char_boundary(end) { end -= 1; }
+    let result = trimmed[..end].to_owned();
+    if result.is_empty() || result == "." || result == ".." { "_".to_owned() } else { result }
+}
+
+pub fn split_matches(names: &[String], member_json: &Value) -> (Vec<String>, Vec<String>) {
+    let groups = member_json.as_object().unwrap();
+    let (groups, members): (Vec<_>, Vec<_>) = names.iter().cloned().partition(|name| groups.contains_key(name.as_str()));
+    (groups, members)
+}
+
+pub struct TemplateContext<'a> {
+    pub program: &'a RadioProgram,
+    pub groups: &'a [String],
+    pub members: &'a [String],
+    pub group: Option<&'a str>,
+    pub member: Option<&'a str>,
+}
+
+pub fn render_template(template: &str, ctx: &TemplateContext) -> Result<PathBuf> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('}') else { bail!("unclosed placeholder in template: {template}") };
+        let placeholder = &rest[open + 1..open + close];
+        let (name, format) = match placeholder.split_once(':') {
+            None => (placeholder, None),
+            Some((name, format)) => (name, Some(format)),
+        };
+        let value = match name {
+            "station_id" => ctx.program.radio_channel.id.clone(),
+            "station_name" => ctx.program.radio_channel.name.clone(),
+            "ft" => ctx.program.ft.with_timezone(&jst()).format(format.unwrap_or("%Y%m%d%H%M%S")).to_string(),
+            "to" => ctx.program.to.with_timezone(&jst()).format(format.unwrap_or("%Y%m%d%H%M%S")).to_string(),
+            "title" => ctx.program.title.clone(),
+            "program_id" => ctx.program.id.to_string(),
+            "pfm" => ctx.program.pfm.clone().unwrap_or_default(),
+            "members" => ctx.members.join(format.unwrap_or(",")),
+            "groups" => ctx.groups.join(format.unwrap_or(",")),
+            "member" => ctx.member.or(ctx.members.first().map(|s| s.as_str())).unwrap_or("unknown").to_owned(),
+            "group" => ctx.group.or(ctx.groups.first().map(|s| s.as_str())).unwrap_or("unknown").to_owned(),
+            _ => bail!("unknown placeholder: {{{placeholder}}}"),
+        };
+        out.push_str(&sanitize(&value));
+        rest = &rest[open + close + 1..];
+    }
+    out.push_str(rest);
+    Ok(out.split('/').filter(|c| !c.is_empty()).collect())
+}
+
+// 同名のファイルがあれば「 (2)」「 (3)」…を付ける
+pub fn avoid_collision(path: &Path) -> PathBuf {
+    if !path.exists() { return path.to_path_buf(); }
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_owned();
+    let ext = path.extension().and_then(|s| s.to_str()).map(|e| format!(".{e}")).unwrap_or_default();
+    (2..).map(|i| path.with_file_name(format!("{stem} ({i}){ext}"))).find(|p| !p.exists()).unwrap()
+}
+
+// {member}/{group}を使ったテンプレートでは該当者ごとのパスを返す(先頭が本体、残りはリンク先)
+pub fn output_paths(template: &str, root: &Path, program: &RadioProgram, names: &[String], member_json: &Value, ext: &str) -> Result<Vec<PathBuf>> {
+    let (groups, members) = split_matches(names, member_json);
+    let mut contexts = vec![];
+    if template.contains("{member}") && !members.is_empty() {
+        contexts.extend(members.iter().map(|m| TemplateContext { program, groups: &groups, members: &members, group: None, member: Some(m) }));
+    } else if template.contains("{group}") && !groups.is_empty() {
+        contexts.extend(groups.iter().map(|g| TemplateContext { program, groups: &groups, members: &members, group: Some(g), member: None }));
+    } else {
+        contexts.push(TemplateContext { program, groups: &groups, members: &members, group: None, member: None });
+    }
+    let mut paths = vec![];
+    for ctx in contexts {
+        let path = root.join(render_template(template, &ctx)?);
+        let path = path.with_file_name(format!("{}.{ext}", path.file_name().and_then(|s| s.to_str()).unwrap_or_default()));
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+// ダウンロード済みファイルを本体の場所へ移し、他の該当者のディレクトリにはハードリンク(できなければコピー)を置く
+pub fn place_file(downloaded: &Path, targets: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut placed: Vec<PathBuf> = vec![];
+    for target in targets {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let target = avoid_collision(target);
+        match placed.first() {
+            None => if fs::rename(downloaded, &target).is_err() {
+                fs::copy(downloaded, &target)?;
+                fs::remove_file(downloaded)?;
+            },
+            Some(primary) => if fs::hard_link(primary, &target).is_err() {
+                fs::copy(primary, &target)?;
+            },
+        }
+        placed.push(target);
+    }
+    Ok(placed)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn program(title: &str, pfm: Option<&str>) -> RadioProgram {
+        serde_json::from_value(json!({
+            "station_id": "TBS", "id": 42, "ft": "2026-10-18T12:00:00Z", "to": "2026-10-18T13:00:00Z", "dur": 3600,
+            "title": title, "img": null, "info": null, "desc": null, "pfm": pfm, "on_air_music": []
+        })).unwrap()
+    }
+
+    fn render(template: &str, program: &RadioProgram, groups: &[String], members: &[String], member: Option<&str>) -> Result<PathBuf> {
+        render_template(template, &TemplateContext { program, groups, members, group: None, member })
+    }
+
+    #[test]
+    fn default_template_uses_jst_start_time() {
+        let program = program("ラジオA", None);
+        assert_eq!(render(DEFAULT_TEMPLATE, &program, &[], &[], None).unwrap(), PathBuf::from("TBS_202610182100_ラジオA"));
+        assert_eq!(render("{program_id}_{ft}-{to:%H%M}", &program, &[], &[], None).unwrap(), PathBuf::from("42_20261018210000-2200"));
+    }
+
+    #[test]
+    fn values_are_sanitized_but_template_slashes_make_directories() {
+        let program = program("A/B: 特番?", Some("中澤裕子"));
+        assert_eq!(render("{pfm}/{title}", &program, &[], &[], None).unwrap(), PathBuf::from("中澤裕子").join("A／B： 特番？"));
+        assert_eq!(render("/{title}//x", &program, &[], &[], None).unwrap(), PathBuf::from("A／B： 特番？").join("x"));
+    }
+
+    #[test]
+    fn member_and_group_placeholders() {
+        let program = program("ラジオA", None);
+        let groups = ["モーニング娘。".to_owned()];
+        let members = ["譜久村聖".to_owned(), "石田亜佑美".to_owned()];
+        assert_eq!(render("{member}/{title}", &program, &groups, &members, Some("石田亜佑美")).unwrap(), PathBuf::from("石田亜佑美/ラジオA"));
+        assert_eq!(render("{member}", &program, &groups, &members, None).unwrap(), PathBuf::from("譜久村聖"));
+        assert_eq!(render("{members: & }_{group}", &program, &groups, &members, None).unwrap(), PathBuf::from("譜久村聖 & 石田亜佑美_モーニング娘。"));
+        // 空の値は「_」になる
+        assert_eq!(render("{group}_{pfm}", &program, &[], &[], None).unwrap(), PathBuf::from("unknown__"));
+    }
+
+    #[test]
+    fn bad_templates_are_errors() {
+        let program = program("ラジオA", None);
+        assert!(render("{title", &program, &[], &[], None).is_err());
+        assert!(render("{artist}", &program, &[], &[], None).is_err());
+    }
+
+    #[test]
+    fn collisions_get_a_number() {
+        let dir = std::env::temp_dir().join(format!("radiko_output_path_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ラジオA.m4a");
+        assert_eq!(avoid_collision(&path), path);
+        fs::write(&path, "").unwrap();
+        assert_eq!(avoid_collision(&path), dir.join("ラジオA (2).m4a"));
+        fs::write(dir.join("ラジオA (2).m4a"), "").unwrap();
+        assert_eq!(avoid_collision(&path), dir.join("ラジオA (3).m4a"));
+        let bare = dir.join("ラジオA");
+        fs::write(&bare, "").unwrap();
+        assert_eq!(avoid_collision(&bare), dir.join("ラジオA (2)"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::matcher::search_artist_name;
+use crate::radiko::{jst, RadioProgram};
+
+pub const PLAY_LOG_COLLECTION: &str = "plays";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayLog {
+    pub station_id: String,
+    pub station_name: String,
+    pub program_id: u64,
+    pub program_title: String,
+    pub pfm: Option<String>,
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    pub played_at: DateTime<Utc>,
+    pub artist_name: String,
+    pub music_title: String,
+    pub artwork_url: String,
+    pub groups: Vec<String>,
+    pub members: Vec<String>,
+}
+
+impl PlayLog {
+    pub fn from_program(program: &RadioProgram, member_json: &Value, match_rules: &Value) -> Vec<Self> {
+        program.on_air_music.iter().map(|music| {
+            let (groups, members) = search_artist_name(&music.artist_name, member_json, match_rules);
+            PlayLog {
+                station_id: program.radio_channel.id.clone(),
+                station_name: program.radio_channel.name.clone(),
+                program_id: program.id,
+                program_title: program.title.clone(),
+                pfm: program.pfm.clone(),
+                played_at: program.ft + music.start_time,
+                artist_name: music.artist_name.clone(),
+                music_title: music.music_title.clone(),
+                artwork_url: music.artwork_url.clone(),
+                groups,
+                members,
+            }
+        }).collect::<Vec<_>>()
+    }
+    pub fn document_id(&self) -> String {
+        format!("{}_{}", self.station_id, self.played_at.with_timezone(&jst()).format("%Y%m%d%H%M%S"))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupWeekRow {
+    pub group: String,
+    pub station_id: String,
+    pub station_name: String,
+    pub week: String,
+    pub plays: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SongRow {
+    pub artist_name: String,
+    pub music_title: String,
+    pub plays: usize,
+    pub stations: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FirstPlayRow {
+    pub artist_name: String,
+    pub music_title: String,
+    pub station_id: String,
+    pub program_title: String,
+    pub played_at: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgramRow {
+    pub group: String,
+    pub station_id: String,
+    pub program_title: String,
+    pub pfm: String,
+    pub plays: usize,
+}
+
+fn group_filter<'a>(logs: &'a [PlayLog], group: Option<&'a str>) -> impl Iterator<Item=&'a PlayLog> {
+    logs.iter().filter(move |log| group.map(|g| log.groups.iter().any(|v| v == g) || log.members.iter().any(|v| v == g)).unwrap_or(!log.groups.is_empty()))
+}
+
+pub fn plays_per_group_week(logs: &[PlayLog], group: Option<&str>) -> Vec<GroupWeekRow> {
+    let mut counter = BTreeMap::<(String, String, String), (String, usize)>::new();
+    for log in group_filter(logs, group) {
+        let week = log.played_at.with_timezone(&jst()).iso_week();
+        let groups = match group {
+            None => log.groups.clone(),
+            Some(g) => vec![g.to_owned()],
+        };
+        for group in &groups {
+            let entry = counter.entry((group.clone(), format!("{}-W{:02}", week.year(), week.week()), log.station_id.clone()))
+                .or_insert((log.station_name.clone(), 0));
+            entry.1 += 1;
+        }
+    }
+    counter.into_iter().map(|((group, week, station_id), (station_name, plays))| {
+        GroupWeekRow { group, station_id, station_name, week, plays }
+    }).collect::<Vec<_>>()
+}
+
+pub fn top_songs(logs: &[PlayLog], group: Option<&str>, limit: usize) -> Vec<SongRow> {
+    let mut counter = HashMap::<(String, String), (usize, Vec<String>)>::new();
+    for log in group_filter(logs, group) {
+        let entry = counter.entry((log.artist_name.clone(), log.music_title.clone())).or_default();
+        entry.0 += 1;
+        if !entry.1.contains(&log.station_id) {
+            entry.1.push(log.station_id.clone());
+        }
+    }
+    let mut rows = counter.into_iter().map(|((artist_name, music_title), (plays, stations))| {
+        SongRow { artist_name, music_title, plays, stations: stations.len() }
+    }).collect::<Vec<_>>();
+    rows.sort_by(|a, b| b.plays.cmp(&a.plays).then(b.stations.cmp(&a.stations)).then(a.music_title.cmp(&b.music_title)));
+    rows.truncate(limit);
+    rows
+}
+
+// logsは集計開始より前の履歴も含めて渡すこと。sinceより前に一度でも流れた曲は新曲扱いしない
+pub fn first_plays(logs: &[PlayLog], group: Option<&str>, since: DateTime<Utc>) -> Vec<FirstPlayRow> {
+    let mut first = HashMap::<(String, String), &PlayLog>::new();
+    for log in group_filter(logs, group) {
+        let entry = first.entry((log.artist_name.clone(), log.music_title.clone())).or_insert(log);
+        if log.played_at < entry.played_at {
+            *entry = log;
+        }
+    }
+    let mut rows = first.into_values().filter(|log| log.played_at >= since).collect::<Vec<_>>();
+    rows.sort_by_key(|log| log.played_at);
+    rows.into_iter().map(|log| FirstPlayRow {
+        artist_name: log.artist_name.clone(),
+        music_title: log.music_title.clone(),
+        station_id: log.station_id.clone(),
+        program_title: log.program_title.clone(),
+        played_at: log.played_at.with_timezone(&jst()).to_rfc3339(),
+    }).collect::<Vec<_>>()
+}
+
+pub fn program_breakdown(logs: &[PlayLog], group: Option<&str>) -> Vec<ProgramRow> {
+    let mut counter = HashMap::<(String, String, String, String), usize>::new();
+    for log in group_filter(logs, group) {
+        let groups = match group {
+            None => log.groups.clone(),
+            Some(g) => vec![g.to_owned()],
+        };
+        for g in groups {
+            *counter.entry((g, log.station_id.clone(), log.program_title.clone(), log.pfm.clone().unwrap_or_default())).or_default() += 1;
+        }
+    }
+    let mut rows = counter.into_iter().map(|((group, station_id, program_title, pfm), plays)| {
+        ProgramRow { group, station_id, program_title, pfm, plays }
+    }).collect::<Vec<_>>();
+    rows.sort_by(|a, b| a.group.cmp(&b.group).then(b.plays.cmp(&a.plays)).then(a.program_title.cmp(&b.program_title)));
+    rows
+}
+
+pub fn write_rows<T: Serialize, W: Write>(rows: &[T], format: &str, writer: W) -> Result<()> {
+    match format {
+        "json" => serde_json::to_writer_pretty(writer, rows)?,
+        _ => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            for row in rows {
+                csv_writer.serialize(row)?;
+            }
+            csv_writer.flush()?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use markup5ever_rcdom::{Handle, NodeData, RcDom};
+use reqwest::{Client, Url};
+use xml5ever::driver::{parse_document, XmlParseOpts};
+use xml5ever::tendril::*;
+use anyhow::{Result, Context};
+use chrono::{Local, DateTime, FixedOffset, TimeDelta, Utc};
+use std::fmt;
+use std::fmt::Formatter;
+use unicode_normalization::UnicodeNormalization;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StationLogo {
+    pub url: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub logo_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RadioChannel {
+    pub id: String,
+    pub name: String,
+    pub banner_url: String,
+    pub area_id: String,
+    #[serde(default)]
+    pub ascii_name: Option<String>,
+    #[serde(default)]
+    pub ruby: Option<String>,
+    #[serde(default)]
+    pub href: Option<String>,
+    #[serde(default)]
+    pub timefree: bool,
+    #[serde(default)]
+    pub areafree: bool,
+    #[serde(default)]
+    pub logos: Vec<StationLogo>,
+}
+impl RadioChannel {
+    pub fn from_hashmap(hash_map: HashMap<&str, String>, logos: Vec<StationLogo>) -> Result<Self> {
+        Ok(RadioChannel {
+            id: hash_map.get("id").context("id not found.")?.clone(),
+            name: hash_map.get("name").context("name not found.")?.clone().nfkc().collect::<_>(),
+            banner_url: hash_map.get("banner").context("banner not found.")?.clone(),
+            area_id: hash_map.get("area_id").context("area_id not found.")?.clone(),
+            ascii_name: hash_map.get("ascii_name").cloned(),
+            ruby: hash_map.get("ruby").cloned(),
+            href: hash_map.get("href").cloned(),
+            timefree: hash_map.get("timefree").map(|v| v == "1").unwrap_or(false),
+            areafree: hash_map.get("areafree").map(|v| v == "1").unwrap_or(false),
+            logos,
+        })
+    }
+    // 一番大きいロゴ。なければバナー
+    pub fn logo_url(&self) -> &str {
+        self.logos.iter().max_by_key(|logo| logo.width.unwrap_or(0) * logo.height.unwrap_or(0)).map(|logo| logo.url.as_str()).unwrap_or(&self.banner_url)
+    }
+    // 局の情報はstationsコレクションに1回だけ書き、番組側にはIDだけ持たせる
+    pub fn station_ref(id: &str) -> Self {
+        RadioChannel {
+            id: id.to_owned(),
+            name: String::new(),
+            banner_url: String::new(),
+            area_id: String::new(),
+            ascii_name: None,
+            ruby: None,
+            href: None,
+            timefree: false,
+            areafree: false,
+            logos: vec![],
+        }
+    }
+}
+
+fn serialize_station_id<S>(channel: &RadioChannel, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&channel.id)
+}
+
+// 以前の番組ドキュメントは局の情報をまるごと埋め込んでいたので、そちらも読めるようにする
+fn deserialize_station_id<'de, D>(deserializer: D) -> Result<RadioChannel, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StationField {
+        Id(String),
+        Embedded(RadioChannel),
+    }
+    Ok(match StationField::deserialize(deserializer)? {
+        StationField::Id(id) => RadioChannel::station_ref(&id),
+        StationField::Embedded(channel) => channel,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RadioProgram {
+    #[serde(rename = "station_id", alias = "radio_channel", serialize_with = "serialize_station_id", deserialize_with = "deserialize_station_id")]
+    pub radio_channel: RadioChannel,
+    pub id: u64,
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    pub ft: DateTime<Utc>,
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    pub to: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_td", deserialize_with = "deserialize_td")]
+    pub dur: TimeDelta,
+    pub title: String,
+    pub img: Option<String>,
+    pub info: Option<String>,
+    pub desc: Option<String>,
+    pub pfm: Option<String>,
+    pub on_air_music: Vec<OnAirMusic>,
+    // 既定では放送終了まで。保存期間はexpiry::ExpiryPolicyで決める
+    #[serde(default, with = "firestore::serialize_as_optional_timestamp")]
+    pub expire_at: Option<DateTime<Utc>>,
+}
+
+// pub fn serialize_dt<S>(datetime: &DateTime<Local>, serializer: S) -> Result<S::Ok, S::Error>
+// where
+//     S: Serializer,
+// {
+//     let s = datetime.to_rfc3339(); // RFC 3339形式で文字列に変換
+//     serializer.serialize_str(&s) // 文字列としてシリアル化
+// }
+//
+// // デシリアライザ
+// pub fn deserialize_dt<'de, D>(deserializer: D) -> Result<DateTime<Local>, D::Error>
+// where
+//     D: Deserializer<'de>,
+// {
+//     DateTime::parse_from_rfc3339(&String::deserialize(deserializer)?)
+//         .map(|dt| dt.with_timezone(&Local))
+//         .map_err(serde::de::Error::custom)
+// }
+//
+pub fn jst() -> FixedOffset {
+    FixedOffset::east_opt(9 * 3600).unwrap()
+}
+
+pub fn serialize_td<S>(timedelta: &TimeDelta, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_i64(timedelta.num_seconds())
+}
+
+pub fn deserialize_td<'de, D>(deserializer: D) -> Result<TimeDelta, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(TimeDelta::seconds(i64::deserialize(deserializer)?))
+}
+
+impl RadioProgram {
+    pub fn from_hashmap(hash_map: HashMap<String, Option<String>>, radio_channel: RadioChannel) -> Result<Self> {
+        Ok(RadioProgram {
+            radio_channel,
+            id: hash_map.get("id").context("id not found.")?.clone().unwrap().parse::<u64>()?,
+            ft: DateTime::from(DateTime::parse_from_str((hash_map.get("ft").context("ft not found.").unwrap().clone().unwrap() + " +0900").as_str(), "%Y%m%d%H%M%S %z")?),
+            to: DateTime::from(DateTime::parse_from_str((hash_map.get("to").context("to not found.").unwrap().clone().unwrap() + " +0900").as_str(), "%Y%m%d%H%M%S %z")?),
+            dur: TimeDelta::seconds(hash_map.get("dur").context("dur not found.")?.clone().unwrap().parse::<i64>()?),
+            title: hash_map.get("title").context("title not found.")?.clone().unwrap().nfkc().collect::<_>(),
+            img: hash_map.get("img").context("img not found.")?.clone(),
+            info: hash_map.get("info").context("info not found.")?.clone().map(|s| {
+                let body = format!("<body>{s}</body>");
+                let dom = parse_document(RcDom::default(), Default::default()).from_utf8().read_from(&mut body.as_bytes()).unwrap();
+                let result = node_to_markdown(&dom.document);
+                result.nfkc().collect::<_>()
+            }),
+            desc: hash_map.get("desc").context("desc not found.")?.clone().map(|s| {
+                let body = format!("<body>{s}</body>");
+                let dom = parse_document(RcDom::default(), Default::default()).from_utf8().read_from(&mut body.as_bytes()).unwrap();
+                let result = node_to_markdown(&dom.document);
+                result.nfkc().collect::<_>()
+            }),
+            pfm: hash_map.get("pfm").context("pfm not found.")?.clone().map(|s| s.nfkc().collect::<_>()),
+            on_air_music: vec![],
+            expire_at: Some(DateTime::from(DateTime::parse_from_str((hash_map.get("to").context("to not found.").unwrap().clone().unwrap() + " +0900").as_str(), "%Y%m%d%H%M%S %z")?)),
+        })
+    }
+    pub fn app_url_scheme(&self) -> String {
+        format!("radiko://radiko.onelink.me/?deep_link_sub1={}&deep_link_sub2={}&deep_link_value={}", self.radio_channel.id, self.ft.format("%Y%m%d%H%M%S"), self.id)
+    }
+}
+impl fmt::Display for RadioProgram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "RadioProgram(RadioChannel({}, {}, https://...., {}), {}, {}, {}, {}, {},info: {}, desc: ..., {}, {:?})", self.radio_channel.id, self.radio_channel.name, self.radio_channel.area_id
+               , self.id, self.ft.to_rfc3339(), self.to.to_rfc3339(), self.dur.num_minutes(),
+               self.info.clone().unwrap_or_else(|| "None".to_owned()), self.title,
+               self.pfm.clone().unwrap_or_else(|| "None".to_owned()), self.on_air_music)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OnAirMusic {
+    pub artist_name: String,
+    pub artwork_url: String,
+    #[serde(serialize_with = "serialize_td", deserialize_with = "deserialize_td")]
+    pub start_time: TimeDelta,
+    pub music_title: String,
+}
+
+impl OnAirMusic {
+    pub async fn get_on_air_music(radio_program: RadioProgram, client: Client) -> Vec<Self> {
+        // let client = Client::new();
+        if radio_program.to > Local::now() { return vec![]; }
+        let url = Url::parse_with_params(format!("https://api.radiko.jp/music/api/v1/noas/{}", radio_program.radio_channel.id).as_str(),
+                                         &[("start_time_gte", radio_program.ft.to_rfc3339()), ("end_time_lt", radio_program.to.to_rfc3339())],
+        ).unwrap();
+        let json = client.get(url).send().await.unwrap().json::<Value>().await.unwrap();
+        json.get("data").unwrap_or(&Value::Array(vec![])).as_array().unwrap().iter().map(|v| {
+            OnAirMusic {
+                artist_name: v["artist_name"].as_str().unwrap().nfkc().collect::<_>(),
+                artwork_url: v["music"]["image"]["large"].as_str().unwrap().nfkc().collect::<_>(),
+                start_time: DateTime::from(DateTime::parse_from_rfc3339(v["displayed_start_time"].as_str().unwrap()).unwrap()) - radio_program.ft,
+                music_title: v["title"].as_str().unwrap().nfkc().collect::<_>(),
+            }
+        }).collect::<Vec<_>>()
+    }
+}
+
+impl fmt::Debug for OnAirMusic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "OnAirMusic({}:{}  -  {}分後から)", self.music_title, self.artist_name, self.start_time.num_minutes())
+    }
+}
+
+pub fn dig_xml<T>(handle: Handle, path: Vec<&str>, call_func: fn(Handle) -> Option<T>) -> Vec<T> {
+    if path.is_empty() {
+        return match call_func(handle) {
+            None => { vec![] }
+            Some(v) => { vec![v] }
+        };
+    }
+    handle.children.borrow().iter().flat_map(|child| {
+        match &child.data {
+            NodeData::Element { name, .. } => {
+                if path[0] == name.local.deref() {
+                    dig_xml(child.clone(), path[1..].to_owned(), call_func)
+                } else { vec![] }
+            }
+            _ => vec![]
+        }
+    }).collect::<Vec<_>>()
+}
+
+pub fn get_below_string(handle: Handle) -> Option<String> {
+    match &handle.children.borrow().clone().first() {
+        None => None,
+        Some(h) => {
+            match &h.data {
+                NodeData::Text { contents, .. } => { Some(contents.borrow().clone().to_string()) }
+                _ => None
+            }
+        }
+    }
+}
+
+
+pub fn node_to_markdown(handle: &Handle) -> String {
+    let dig = |handle: Handle| { handle.children.borrow().clone().into_iter().map(|child| node_to_markdown(&child)).collect::<Vec<_>>().join("") };
+    match &handle.data {
+        NodeData::Document => {
+            dig(handle.clone())
+        }
+        NodeData::Text { contents } => {
+            contents.borrow().to_string()
+        }
+        NodeData::Element { name, attrs, .. } => {
+            match name.local.to_lowercase().as_str() {
+                "a" => {
+                    let mut href = None;
+                    for attr in &attrs.borrow().clone() {
+                        if attr.name.local.deref() == "href" {
+                            href = Some(attr.value.clone());
+                            break;
+                        }
+                    }
+                    match href {
+                        None => { dig(handle.clone()) }
+                        Some(href) => { format!("[{}]({href})", dig(handle.clone())) }
+                    }
+                }
+                "b" | "strong" => format!("**{}**", dig(handle.clone())),
+                "p" => format!("{}\n", dig(handle.clone())),
+                "br" => format!("\n\n{}", dig(handle.clone())),
+                _ => dig(handle.clone()),
+            }
+        }
+        NodeData::Comment { .. } => String::new(),
+        elm => {
+            println!("err!:{:?}", elm);
+            String::new()
+        }
+    }
+}
+
+pub fn parse_channels(xml: &str) -> Vec<RadioChannel> {
+    let doc = parse_document(RcDom::default(), XmlParseOpts::default()).from_utf8().read_from(&mut xml.as_bytes()).unwrap();
+    let channels_hashmap = dig_xml(doc.document, vec!["region", "stations", "station"], |handle| {
+        match &handle.data {
+            NodeData::Element { .. } => {
+                let mut logos = vec![];
+                let hash_map = handle.children.borrow().clone().into_iter().filter_map(|child| {
+                    match &child.data {
+                        NodeData::Element { name, attrs, .. } => {
+                            match name.local.deref() {
+                                "id" => { Some(("id", get_below_string(child).unwrap())) }
+                                "name" => { Some(("name", get_below_string(child).unwrap())) }
+                                "banner" => { Some(("banner", get_below_string(child).unwrap())) }
+                                "area_id" => { Some(("area_id", get_below_string(child).unwrap())) }
+                                "ascii_name" => { Some(("ascii_name", get_below_string(child)?)) }
+                                "ruby" => { Some(("ruby", get_below_string(child)?)) }
+                                "href" => { Some(("href", get_below_string(child)?)) }
+                                "timefree" => { Some(("timefree", get_below_string(child)?)) }
+                                "areafree" => { Some(("areafree", get_below_string(child)?)) }
+                                "logo" => {
+                                    let attr = |key: &str| attrs.borrow().iter().find(|a| a.name.local.deref() == key).map(|a| a.value.to_string());
+                                    if let Some(url) = get_below_string(child.clone()) {
+                                        logos.push(StationLogo {
+                                            url,
+                                            width: attr("width").and_then(|v| v.parse().ok()),
+                                            height: attr("height").and_then(|v| v.parse().ok()),
+                                            logo_type: attr("logo_type"),
+                                        });
+                                    }
+                                    None
+                                }
+                                _ => None
+                            }
+                        }
+                        _ => None
+                    }
+                }).collect::<HashMap<_, _>>();
+                Some((hash_map, logos))
+            }
+            _ => None
+        }
+    });
+    channels_hashmap.into_iter().map(|(hash_map, logos)| RadioChannel::from_hashmap(hash_map, logos).unwrap()).collect::<Vec<_>>()
+}
+
+pub fn parse_programs(xml: &str, channel: &RadioChannel) -> Vec<RadioProgram> {
+    let doc = parse_document(RcDom::default(), XmlParseOpts::default()).from_utf8().read_from(&mut xml.as_bytes()).unwrap();
+    let programs_hashmaps = dig_xml(doc.document, vec!["radiko", "stations", "station", "progs", "prog"], |handle| match &handle.data {
+        NodeData::Element { attrs, .. } => {
+            let mut program_meta_hashmap = handle.children.borrow().clone().into_iter().filter_map(|child| {
+                match &child.data {
+                    NodeData::Element { name, .. } => {
+                        match name.local.deref() {
+                            "title" => { Some(("title".to_owned(), get_below_string(child))) }
+                            "img" => { Some(("img".to_owned(), get_below_string(child))) }
+                            "info" => { Some(("info".to_owned(), get_below_string(child))) }
+                            "desc" => { Some(("desc".to_owned(), get_below_string(child))) }
+                            "pfm" => { Some(("pfm".to_owned(), get_below_string(child))) }
+                            _ => None
+                        }
+                    }
+                    _ => None
+                }
+            }).collect::<HashMap<_, _>>();
+            let program_date_hashmap = attrs.borrow().clone().into_iter().map(|v| (v.name.local.to_string(), Some(v.value.to_string()))).collect::<HashMap<_, _>>();
+            program_meta_hashmap.extend(program_date_hashmap);
+            Some(program_meta_hashmap)
+        }
+        _ => None
+    });
+    programs_hashmaps.into_iter().filter_map(|hash_map: HashMap<_, _>| {
+        RadioProgram::from_hashmap(hash_map, channel.clone()).ok()
+    }).collect::<Vec<_>>()
+}
@@ -0,0 +1,191 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{TimeDelta, Utc};
+use kdam::tqdm;
+use reqwest::{Client, StatusCode, Url};
+use sha2::{Digest, Sha256};
+use crate::adts::{scan_frames, skip_id3, total_duration};
+use crate::ledger::ledger_key;
+use crate::radiko::{jst, RadioProgram};
+
+const AUTH_KEY: &str = "bcd151073c03b352e1ef2fd66c32209da9ca0afa";
+const PLAYLIST_URL: &str = "https://tf-f-rpaa-radiko.smartstream.ne.jp/tf/playlist.m3u8";
+// radikoのセグメントは5秒なので、それより明らかに小さいものは壊れているとみなす
+const MIN_SEGMENT_SIZE: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct RadikoAuth {
+    pub token: String,
+    pub area_id: String,
+}
+
+impl RadikoAuth {
+    pub async fn authorize(client: &Client) -> Result<Self> {
+        let res = client.get("https://radiko.jp/v2/api/auth1")
+            .header("X-Radiko-App", "pc_html5")
+            .header("X-Radiko-App-Version", "0.0.1")
+            .header("X-Radiko-Device", "pc")
+            .header("X-Radiko-User", "dummy_user")
+            .send().await?.error_for_status()?;
+        let header = |name: &str| res.headers().get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_owned()).with_context(|| format!("{name} not found."));
+        let token = header("X-Radiko-AuthToken")?;
+        let offset = header("X-Radiko-KeyOffset")?.parse::<usize>()?;
+        let length = header("X-Radiko-KeyLength")?.parse::<usize>()?;
+        let partial_key = STANDARD.encode(AUTH_KEY.as_bytes().get(offset..offset + length).context("invalid key range.")?);
+        let body = client.get("https://radiko.jp/v2/api/auth2")
+            .header("X-Radiko-AuthToken", &token)
+            .header("X-Radiko-PartialKey", partial_key)
+            .header("X-Radiko-Device", "pc")
+            .header("X-Radiko-User", "dummy_user")
+            .send().await?.error_for_status()?.text().await?;
+        let area_id = body.trim().split(',').next().context("area not found.")?.to_owned();
+        Ok(RadikoAuth { token, area_id })
+    }
+}
+
+// トークン切れ(401/403)で失敗したかどうか。切れていたら取り直してやり直す
+pub fn is_auth_error(err: &anyhow::Error) -> bool {
+    err.chain().filter_map(|e| e.downcast_ref::<reqwest::Error>()).filter_map(|e| e.status())
+        .any(|status| status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN)
+}
+
+fn m3u8_entries(base: &Url, body: &str) -> Vec<(Url, TimeDelta)> {
+    let mut duration = TimeDelta::zero();
+    let mut entries = vec![];
+    for line in body.lines().map(|l| l.trim()) {
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            let secs = extinf.split(',').next().and_then(|s| s.parse::<f64>().ok()).unwrap_or(0.0);
+            duration = TimeDelta::milliseconds((secs * 1000.0) as i64);
+        } else if !line.is_empty() && !line.starts_with('#') {
+            if let Ok(url) = base.join(line) {
+                entries.push((url, duration));
+            }
+            duration = TimeDelta::zero();
+        }
+    }
+    entries
+}
+
+// タイムフリーのプレイリストは一度に数分ぶんしか返さないので、seekをずらしながら番組の最後まで集める
+pub async fn segment_urls(client: &Client, auth: &RadikoAuth, program: &RadioProgram) -> Result<Vec<Url>> {
+    let fmt = |t: chrono::DateTime<Utc>| t.with_timezone(&jst()).format("%Y%m%d%H%M%S").to_string();
+    let lsid = format!("{:x}", Sha256::digest(format!("{}{}", ledger_key(program), Utc::now().timestamp()).as_bytes()))[..32].to_owned();
+    let mut seek = program.ft;
+    let mut urls: Vec<Url> = vec![];
+    while seek < program.to {
+        let playlist = Url::parse_with_params(PLAYLIST_URL, &[
+            ("station_id", program.radio_channel.id.clone()),
+            ("start_at", fmt(program.ft)), ("ft", fmt(program.ft)),
+            ("end_at", fmt(program.to)), ("to", fmt(program.to)),
+            ("seek", fmt(seek)), ("l", "15".to_owned()), ("lsid", lsid.clone()), ("type", "b".to_owned()),
+        ])?;
+        let master = client.get(playlist.clone()).header("X-Radiko-AuthToken", &auth.token).header("X-Radiko-AreaId", &auth.area_id)
+            .send().await?.error_for_status()?.text().await?;
+        let Some((chunklist, _)) = m3u8_entries(&playlist, &master).into_iter().next() else { bail!("chunklist not found in playlist") };
+        let body = client.get(chunklist.clone()).header("X-Radiko-AuthToken", &auth.token).send().await?.error_for_status()?.text().await?;
+        let entries = m3u8_entries(&chunklist, &body).into_iter().filter(|(url, _)| !urls.contains(url)).collect::<Vec<_>>();
+        if entries.is_empty() { break; }
+        seek += entries.iter().map(|(_, d)| *d).sum::<TimeDelta>().max(TimeDelta::seconds(5));
+        urls.extend(entries.into_iter().map(|(url, _)| url));
+    }
+    if urls.is_empty() { bail!("no segment found for {}", ledger_key(program)) }
+    Ok(urls)
+}
+
+pub fn validate_segment(buf: &[u8]) -> Result<()> {
+    if buf.len() < MIN_SEGMENT_SIZE { bail!("segment too small: {} bytes", buf.len()) }
+    let frames = scan_frames(buf)?;
+    if frames.is_empty() { bail!("no ADTS frame in segment") }
+    Ok(())
+}
+
+// lsidは毎回変わり、トークンを取り直すとセグメントの並びもずれることがあるので、順番ではなくURLのパスで名前を決める
+fn segment_path(dir: &Path, url: &Url) -> PathBuf {
+    dir.join(format!("{}.aac", &format!("{:x}", Sha256::digest(url.path().as_bytes()))[..32]))
+}
+
+// 録音全体の長さが番組の長さとずれていないか(先頭・末尾の欠け程度は許容する)
+pub fn check_duration(path: &Path, program: &RadioProgram) -> Result<TimeDelta> {
+    let duration = total_duration(&scan_frames(&fs::read(path)?)?);
+    let tolerance = TimeDelta::seconds(30).max(program.dur / 100);
+    if (duration - program.dur).abs() > tolerance {
+        bail!("duration mismatch: recorded {}s, expected {}s", duration.num_seconds(), program.dur.num_seconds())
+    }
+    Ok(duration)
+}
+
+// セグメントは<work_dir>/<key>/に1つずつ保存するので、中断しても次回は続きから取得できる
+pub async fn record(client: &Client, auth: &RadikoAuth, program: &RadioProgram, work_dir: &Path) -> Result<PathBuf> {
+    let key = ledger_key(program);
+    let segment_dir = work_dir.join(&key);
+    fs::create_dir_all(&segment_dir)?;
+    let urls = segment_urls(client, auth, program).await?;
+    for (index, url) in tqdm!(urls.iter().enumerate(), desc = key.as_str(), total = urls.len()) {
+        let path = segment_path(&segment_dir, url);
+        if let Ok(buf) = fs::read(&path) {
+            if validate_segment(&buf).is_ok() { continue; }
+        }
+        let mut last_err = None;
+        for _ in 0..3 {
+            let result = async {
+                let buf = client.get(url.clone()).header("X-Radiko-AuthToken", &auth.token).send().await?.error_for_status()?.bytes().await?;
+                validate_segment(&buf)?;
+                let tmp = path.with_extension("part");
+                fs::write(&tmp, &buf)?;
+                fs::rename(&tmp, &path)?;
+                anyhow::Ok(())
+            }.await;
+            match result {
+                Ok(_) => { last_err = None; break; }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        if let Some(err) = last_err {
+            return Err(err.context(format!("segment {index} failed")));
+        }
+    }
+    println!();
+
+    let output = work_dir.join(format!("{key}.aac"));
+    let mut assembled = vec![];
+    for url in &urls {
+        let buf = fs::read(segment_path(&segment_dir, url))?;
+        assembled.extend_from_slice(&buf[skip_id3(&buf)..]);
+    }
+    fs::write(&output, assembled)?;
+    // 長さが合わなければ、取ったセグメントごと捨てて次は最初から取り直す
+    let checked = check_duration(&output, program);
+    fs::remove_dir_all(&segment_dir)?;
+    if let Err(err) = checked {
+        fs::remove_file(&output)?;
+        return Err(err);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_are_named_by_path_not_query() {
+        let dir = Path::new("work");
+        let a = Url::parse("https://example.com/tf/segments/20261018120000_1.aac?lsid=aaaa").unwrap();
+        let b = Url::parse("https://example.com/tf/segments/20261018120000_1.aac?lsid=bbbb").unwrap();
+        let c = Url::parse("https://example.com/tf/segments/20261018120005_2.aac?lsid=aaaa").unwrap();
+        assert_eq!(segment_path(dir, &a), segment_path(dir, &b));
+        assert_ne!(segment_path(dir, &a), segment_path(dir, &c));
+    }
+
+    #[test]
+    fn playlist_entries_keep_their_durations() {
+        let base = Url::parse("https://example.com/tf/chunklist.m3u8?lsid=aaaa").unwrap();
+        let body = "#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:1\n#EXTINF:5,\nsegments/1.aac\n#EXTINF:4.5,\nsegments/2.aac\n";
+        let entries = m3u8_entries(&base, body);
+        assert_eq!(entries.iter().map(|(url, _)| url.path()).collect::<Vec<_>>(), ["/tf/segments/1.aac", "/tf/segments/2.aac"]);
+        assert_eq!(entries[1].1, TimeDelta::milliseconds(4500));
+    }
+}
@@ -0,0 +1,147 @@
+use std::fs;
+use std::path::Path;
+use anyhow::Result;
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use crate::ledger::{DownloadStatus, Ledger, LedgerEntry};
+use crate::radiko::jst;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    pub max_gb: Option<f64>,
+    pub max_age_days: Option<i64>,
+    // ここに書いたメンバー・グループが含まれる録音は消さない
+    #[serde(default)]
+    pub pinned: Vec<String>,
+}
+
+impl RetentionPolicy {
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(s) => Ok(Some(serde_json::from_str(&s)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+    pub fn is_pinned(&self, entry: &LedgerEntry) -> bool {
+        entry.matched.iter().any(|name| self.pinned.contains(name))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeletionCandidate {
+    pub key: String,
+    pub title: String,
+    pub aired_at: DateTime<Utc>,
+    pub byte_size: u64,
+    pub reason: String,
+}
+
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+// ハードリンクかどうか分からないので、コピーとして数える
+#[cfg(not(unix))]
+fn same_file(_: &fs::Metadata, _: &fs::Metadata) -> bool {
+    false
+}
+
+// 本体に加えて、ハードリンクを張れずにコピーしたもの(place_file)の分も数える
+pub fn disk_usage(entry: &LedgerEntry) -> u64 {
+    let primary = entry.file_path.as_ref().and_then(|path| fs::metadata(path).ok());
+    let copies = entry.linked_paths.iter().filter_map(|path| fs::metadata(path).ok())
+        .filter(|linked| primary.as_ref().is_none_or(|primary| !same_file(primary, linked)))
+        .map(|linked| linked.len())
+        .sum::<u64>();
+    entry.byte_size.unwrap_or(0) + copies
+}
+
+fn aired_at(entry: &LedgerEntry) -> DateTime<Utc> {
+    NaiveDateTime::parse_from_str(&entry.ft, "%Y%m%d%H%M%S").ok()
+        .and_then(|ft| ft.and_local_timezone(jst()).single())
+        .map(|ft| ft.with_timezone(&Utc))
+        .unwrap_or(entry.updated_at)
+}
+
+// 古い順(同時刻ならキー順)に、まず期限切れを、次に容量超過分を候補にする
+pub fn deletion_candidates(ledger: &Ledger, policy: &RetentionPolicy, now: DateTime<Utc>) -> Vec<DeletionCandidate> {
+    let mut entries = ledger.entries.iter()
+        .filter(|(_, entry)| entry.status == DownloadStatus::Done)
+        .map(|(key, entry)| (key, entry, aired_at(entry), disk_usage(entry)))
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.2.cmp(&b.2).then(a.0.cmp(b.0)));
+    let mut total = entries.iter().map(|(_, _, _, size)| size).sum::<u64>();
+    let max_bytes = policy.max_gb.map(|gb| (gb * 1024.0 * 1024.0 * 1024.0) as u64);
+    let max_age = policy.max_age_days.map(TimeDelta::days);
+
+    let mut candidates = vec![];
+    for (key, entry, aired_at, size) in entries {
+        if policy.is_pinned(entry) { continue; }
+        let reason = if max_age.map(|age| now - aired_at > age).unwrap_or(false) {
+            format!("older than {} days", policy.max_age_days.unwrap())
+        } else if max_bytes.map(|max| total > max).unwrap_or(false) {
+            format!("archive exceeds {} GB", policy.max_gb.unwrap())
+        } else {
+            continue;
+        };
+        total -= size;
+        candidates.push(DeletionCandidate {
+            key: key.clone(),
+            title: entry.title.clone(),
+            aired_at,
+            byte_size: size,
+            reason,
+        });
+    }
+    candidates
+}
+
+pub fn apply(ledger: &mut Ledger, candidates: &[DeletionCandidate]) -> Result<()> {
+    for candidate in candidates {
+        let Some(entry) = ledger.entries.get(&candidate.key) else { continue };
+        for path in entry.file_path.iter().chain(entry.linked_paths.iter()) {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        ledger.mark_deleted(&candidate.key, candidate.reason.clone())?;
+    }
+    Ok(())
+}
+
+pub fn report(ledger: &Ledger, candidates: &[DeletionCandidate]) -> String {
+    let gb = |bytes: u64| bytes as f64 / 1024.0 / 1024.0 / 1024.0;
+    let total = ledger.entries.values().filter(|e| e.status == DownloadStatus::Done).map(disk_usage).sum::<u64>();
+    let freed = candidates.iter().map(|c| c.byte_size).sum::<u64>();
+    let mut lines = candidates.iter().map(|c| {
+        format!("{}\t{}\t{:.2}MB\t{}\t{}", c.key, c.aired_at.with_timezone(&jst()).format("%Y-%m-%d %H:%M"), c.byte_size as f64 / 1024.0 / 1024.0, c.reason, c.title)
+    }).collect::<Vec<_>>();
+    lines.push(format!("{} files, {:.2} GB freed ({:.2} GB -> {:.2} GB)", candidates.len(), gb(freed), gb(total), gb(total - freed)));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_count_but_hard_links_do_not() {
+        let dir = std::env::temp_dir().join(format!("radiko_retention_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (primary, link, copy) = (dir.join("primary.m4a"), dir.join("link.m4a"), dir.join("copy.m4a"));
+        fs::write(&primary, [0u8; 1000]).unwrap();
+        fs::hard_link(&primary, &link).unwrap();
+        fs::copy(&primary, &copy).unwrap();
+        let entry: LedgerEntry = serde_json::from_value(serde_json::json!({
+            "station_id": "TBS", "ft": "20261018210000", "program_id": 1, "title": "番組", "status": "done", "attempts": 1,
+            "file_path": primary, "linked_paths": [link, copy, dir.join("gone.m4a")], "byte_size": 1000, "checksum": null,
+            "updated_at": "2026-10-18T12:00:00Z"
+        })).unwrap();
+        assert_eq!(disk_usage(&entry), if cfg!(unix) { 2000 } else { 3000 });
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, HashMap};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use firestore::FirestoreDb;
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::matcher::{literals_of, MatchType};
+use crate::radiko::RadioProgram;
+use crate::schema::{catalog_parent_in, match_parent_in, MatchRecord, ProgramDocument, LEGACY_PROGRAMS_DOC, MATCH_COLLECTION, PROGRAM_COLLECTION};
+use crate::text::fold;
+
+// hello-radiko-data/catalog/reviews/{program_id}_{name}
+pub const REVIEW_COLLECTION: &str = "reviews";
+// 根拠の前後に残す文字数
+const CONTEXT_CHARS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Confirmed,
+    Rejected,
+}
+
+// マッチした表記と、その前後の文(foldしたもの)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Evidence {
+    pub literal: String,
+    pub context: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Review {
+    pub program_id: u64,
+    pub name: String,
+    pub verdict: Verdict,
+    pub reason: Option<String>,
+    pub station_id: String,
+    pub title: String,
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    pub ft: DateTime<Utc>,
+    pub evidence: Vec<Evidence>,
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    pub reviewed_at: DateTime<Utc>,
+}
+
+pub fn review_id(program_id: u64, name: &str) -> String {
+    format!("{program_id}_{name}")
+}
+
+impl Review {
+    pub fn new(program: &RadioProgram, name: &str, verdict: Verdict, reason: Option<String>, member_json: &Value, match_rules: &Value) -> Self {
+        Review {
+            program_id: program.id,
+            name: name.to_owned(),
+            verdict,
+            reason,
+            station_id: program.radio_channel.id.clone(),
+            title: program.title.clone(),
+            ft: program.ft,
+            evidence: evidence(program, name, member_json, match_rules),
+            reviewed_at: Utc::now(),
+        }
+    }
+    pub fn id(&self) -> String {
+        review_id(self.program_id, &self.name)
+    }
+}
+
+// 番組のどこに名前が出ていたか。除外ルールの候補を作るのに使う
+pub fn evidence(program: &RadioProgram, name: &str, member_json: &Value, match_rules: &Value) -> Vec<Evidence> {
+    let literals = literals_of(name, member_json, match_rules).iter().map(|l| fold(l)).filter(|l| !l.is_empty()).collect::<Vec<_>>();
+    let texts = [Some(&program.title), program.desc.as_ref(), program.info.as_ref(), program.pfm.as_ref()].into_iter().flatten()
+        .chain(program.on_air_music.iter().map(|music| &music.artist_name));
+    let mut found = vec![];
+    for text in texts {
+        let text = fold(text);
+        for literal in &literals {
+            for (at, _) in text.match_indices(literal.as_str()) {
+                let before = text[..at].chars().rev().take(CONTEXT_CHARS).collect::<Vec<_>>().into_iter().rev().collect::<String>();
+                let after = text[at + literal.len()..].chars().take(CONTEXT_CHARS).collect::<String>();
+                let evidence = Evidence { literal: literal.clone(), context: format!("{before}{literal}{after}") };
+                if !found.contains(&evidence) {
+                    found.push(evidence);
+                }
+            }
+        }
+    }
+    found
+}
+
+// 確認済みの判定。取り消された(program, name)は何度マッチしても公開しない
+#[derive(Debug, Clone, Default)]
+pub struct ReviewBook {
+    pub reviews: HashMap<String, Review>,
+}
+
+impl ReviewBook {
+    // rootはウォッチリストごとの書き込み先(既定はschema::DATA_ROOT)
+    pub async fn load(db: &FirestoreDb, root: &str) -> Result<Self> {
+        let reviews: Vec<Review> = db.fluent().list().from(REVIEW_COLLECTION).parent(catalog_parent_in(db, root)?).obj().stream_all_with_errors().await?.try_collect().await?;
+        Ok(ReviewBook { reviews: reviews.into_iter().map(|review| (review.id(), review)).collect() })
+    }
+    pub fn verdict(&self, program_id: u64, name: &str) -> Option<Verdict> {
+        self.reviews.get(&review_id(program_id, name)).map(|review| review.verdict)
+    }
+    pub fn is_rejected(&self, program_id: u64, name: &str) -> bool {
+        self.verdict(program_id, name) == Some(Verdict::Rejected)
+    }
+    pub fn retain(&self, program_id: u64, matched: &mut BTreeMap<String, Vec<MatchType>>) {
+        matched.retain(|name, _| !self.is_rejected(program_id, name));
+    }
+}
+
+// 判定を書き、公開済みのmatchesにも反映する(取り消しなら外す)
+pub async fn record(db: &FirestoreDb, root: &str, review: &Review) -> Result<()> {
+    let catalog = catalog_parent_in(db, root)?;
+    db.fluent().update().in_col(REVIEW_COLLECTION).document_id(review.id()).parent(&catalog).object(review).execute::<()>().await?;
+    if review.verdict == Verdict::Confirmed {
+        let parent = match_parent_in(db, root, review.program_id)?;
+        let record: Option<MatchRecord> = db.fluent().select().by_id_in(MATCH_COLLECTION).parent(&parent).obj().one(&review.name).await?;
+        if let Some(record) = record {
+            let record = MatchRecord { verdict: Some(Verdict::Confirmed), ..record };
+            db.fluent().update().in_col(MATCH_COLLECTION).document_id(&review.name).parent(&parent).object(&record).execute::<()>().await?;
+        }
+    } else {
+        db.fluent().delete().from(MATCH_COLLECTION).parent(match_parent_in(db, root, review.program_id)?).document_id(&review.name).execute().await?;
+        // 移行前の形式のコピー(root/programs/{name}/{program_id})が残っていればそれも消す
+        db.fluent().delete().from(review.name.as_str()).parent(db.parent_path(root, LEGACY_PROGRAMS_DOC)?).document_id(review.program_id.to_string()).execute().await?;
+        let document: Option<ProgramDocument> = db.fluent().select().by_id_in(PROGRAM_COLLECTION).parent(&catalog).obj().one(review.program_id.to_string()).await?;
+        if let Some(mut document) = document.filter(|d| d.matched.contains(&review.name)) {
+            document.matched.retain(|name| name != &review.name);
+            // 誰も残らなければ、該当者のいない番組と同じく番組ごと消す
+            if document.matched.is_empty() {
+                db.fluent().delete().from(PROGRAM_COLLECTION).parent(&catalog).document_id(review.program_id.to_string()).execute().await?;
+            } else {
+                db.fluent().update().in_col(PROGRAM_COLLECTION).document_id(review.program_id.to_string()).parent(&catalog).object(&document).execute::<()>().await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// 番組を引いて根拠を添え、判定を記録する
+#[allow(clippy::too_many_arguments)]
+pub async fn submit(db: &FirestoreDb, root: &str, program_id: u64, name: &str, verdict: Verdict, reason: Option<String>, member_json: &Value, match_rules: &Value) -> Result<Review> {
+    if verdict == Verdict::Rejected && reason.as_deref().is_none_or(|r| r.trim().is_empty()) {
+        return Err(anyhow!("a rejection needs a reason"));
+    }
+    let document = load_program(db, root, program_id).await?;
+    let review = Review::new(&document.program, name, verdict, reason, member_json, match_rules);
+    record(db, root, &review).await?;
+    Ok(review)
+}
+
+// 公開中のmatchesを全番組分
+pub async fn stored_matches(db: &FirestoreDb, root: &str) -> Result<Vec<MatchRecord>> {
+    Ok(db.fluent().select().from(MATCH_COLLECTION).parent(catalog_parent_in(db, root)?).all_descendants().obj().query().await?)
+}
+
+pub async fn load_program(db: &FirestoreDb, root: &str, program_id: u64) -> Result<ProgramDocument> {
+    let document: Option<ProgramDocument> = db.fluent().select().by_id_in(PROGRAM_COLLECTION).parent(catalog_parent_in(db, root)?).obj().one(program_id.to_string()).await?;
+    document.ok_or_else(|| anyhow!("program {program_id} is not in the catalog"))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Suggestion {
+    pub name: String,
+    pub exclude: String,
+    pub rejections: usize,
+}
+
+// 取り消された根拠の、表記を1〜2文字伸ばした形のうち確認済みの根拠に出てこないものを除外ルールの候補にする
+pub fn suggest_exclusions(reviews: &[Review]) -> Vec<Suggestion> {
+    let mut counts = BTreeMap::<(String, String), usize>::new();
+    for review in reviews.iter().filter(|r| r.verdict == Verdict::Rejected) {
+        let confirmed = reviews.iter().filter(|r| r.verdict == Verdict::Confirmed && r.name == review.name).flat_map(|r| &r.evidence).collect::<Vec<_>>();
+        let mut candidates = vec![];
+        for Evidence { literal, context } in &review.evidence {
+            let Some(at) = context.find(literal.as_str()) else { continue };
+            let before = context[..at].chars().collect::<Vec<_>>();
+            let after = context[at + literal.len()..].chars().collect::<Vec<_>>();
+            let extensions = [
+                after.first().map(|c| format!("{literal}{c}")),
+                before.last().map(|c| format!("{c}{literal}")),
+                (after.len() >= 2).then(|| format!("{literal}{}{}", after[0], after[1])),
+            ];
+            // 短いものから、確認済みの番組を巻き込まない最初の1つ
+            if let Some(candidate) = extensions.into_iter().flatten().find(|c| !confirmed.iter().any(|e| e.context.contains(c.as_str()))) {
+                if !candidates.contains(&candidate) {
+                    candidates.push(candidate);
+                }
+            }
+        }
+        for candidate in candidates {
+            *counts.entry((review.name.clone(), candidate)).or_default() += 1;
+        }
+    }
+    let mut suggestions = counts.into_iter().map(|((name, exclude), rejections)| Suggestion { name, exclude, rejections }).collect::<Vec<_>>();
+    suggestions.sort_by(|a, b| b.rejections.cmp(&a.rejections).then(a.name.cmp(&b.name)));
+    suggestions
+}
+
+// match_rules.json にそのまま足せる形
+pub fn suggestions_as_rules(suggestions: &[Suggestion]) -> Value {
+    let mut rules = serde_json::Map::new();
+    for suggestion in suggestions {
+        let rule = rules.entry(suggestion.name.clone()).or_insert_with(|| serde_json::json!({"exclude": []}));
+        rule["exclude"].as_array_mut().unwrap().push(Value::String(suggestion.exclude.clone()));
+    }
+    serde_json::json!({ "rules": rules })
+}
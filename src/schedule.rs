@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use crate::archive::broadcast_date;
+use crate::ledger::ledger_key;
+use crate::radiko::RadioProgram;
+use crate::series::title_key;
+
+// 同じ番組名の回を振替先とみなす範囲
+const RESCHEDULE_WINDOW: TimeDelta = TimeDelta::days(7);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScheduleChange {
+    // 枠ごと番組表から消えた
+    Cancelled,
+    // 枠に別の番組(スポーツ中継など)が入った
+    Preempted { program_id: u64, title: String },
+    // 別の時間に移った(延長による繰り下げを含む)
+    Rescheduled {
+        program_id: u64,
+        #[serde(with = "firestore::serialize_as_timestamp")]
+        ft: DateTime<Utc>,
+        #[serde(with = "firestore::serialize_as_timestamp")]
+        to: DateTime<Utc>,
+    },
+    Retitled { from: String, title: String },
+}
+
+impl ScheduleChange {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ScheduleChange::Cancelled => "cancelled",
+            ScheduleChange::Preempted { .. } => "preempted",
+            ScheduleChange::Rescheduled { .. } => "rescheduled",
+            ScheduleChange::Retitled { .. } => "retitled",
+        }
+    }
+    // 変更後の番組が今の番組表にあれば、そのID
+    pub fn current_program_id(&self, program: &RadioProgram) -> Option<u64> {
+        match self {
+            ScheduleChange::Rescheduled { program_id, .. } => Some(*program_id),
+            ScheduleChange::Retitled { .. } => Some(program.id),
+            ScheduleChange::Cancelled | ScheduleChange::Preempted { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleEvent {
+    // 前回取得したときの番組
+    pub program: RadioProgram,
+    pub change: ScheduleChange,
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    pub detected_at: DateTime<Utc>,
+}
+
+impl ScheduleEvent {
+    pub fn key(&self) -> String {
+        format!("{}_{}", ledger_key(&self.program), self.change.label())
+    }
+}
+
+// 前回の番組表と今回の番組表を比べる。今回取れていない局・日付の分は比べない
+pub fn detect_changes(previous: &[RadioProgram], current: &[RadioProgram], now: DateTime<Utc>) -> Vec<ScheduleEvent> {
+    let covered = current.iter().map(|p| (p.radio_channel.id.as_str(), broadcast_date(p))).collect::<HashSet<_>>();
+    let current_by_key = current.iter().map(|p| (ledger_key(p), p)).collect::<HashMap<_, _>>();
+    let previous_keys = previous.iter().map(ledger_key).collect::<HashSet<_>>();
+    let mut by_station = BTreeMap::<&str, Vec<&RadioProgram>>::new();
+    for program in current {
+        by_station.entry(program.radio_channel.id.as_str()).or_default().push(program);
+    }
+    let mut events = vec![];
+    for old in previous.iter().filter(|p| p.ft > now && covered.contains(&(p.radio_channel.id.as_str(), broadcast_date(p)))) {
+        let station = by_station.get(old.radio_channel.id.as_str()).map(|v| v.as_slice()).unwrap_or_default();
+        let change = if let Some(new) = current_by_key.get(&ledger_key(old)) {
+            if new.title == old.title { continue; }
+            ScheduleChange::Retitled { from: old.title.clone(), title: new.title.clone() }
+        } else if let Some(new) = station.iter().find(|p| p.id == old.id)
+            .or_else(|| station.iter().filter(|p| !previous_keys.contains(&ledger_key(p)) && title_key(&p.title) == title_key(&old.title) && (p.ft - old.ft).abs() <= RESCHEDULE_WINDOW)
+                .min_by_key(|p| (p.ft - old.ft).abs())) {
+            ScheduleChange::Rescheduled { program_id: new.id, ft: new.ft, to: new.to }
+        } else if let Some(occupant) = station.iter().find(|p| p.ft <= old.ft && old.ft < p.to) {
+            ScheduleChange::Preempted { program_id: occupant.id, title: occupant.title.clone() }
+        } else {
+            ScheduleChange::Cancelled
+        };
+        events.push(ScheduleEvent { program: old.clone(), change, detected_at: now });
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    // 時刻はJSTの時・分で、日付は2026-10-20(now より後)
+    fn program(id: u64, title: &str, day: u32, hhmm: (u32, u32), minutes: i64) -> RadioProgram {
+        let ft = DateTime::parse_from_rfc3339(&format!("2026-10-{day:02}T{:02}:{:02}:00+09:00", hhmm.0, hhmm.1)).unwrap().with_timezone(&Utc);
+        serde_json::from_value(json!({
+            "station_id": "TBS", "id": id, "ft": ft, "to": ft + TimeDelta::minutes(minutes), "dur": minutes * 60,
+            "title": title, "img": null, "info": null, "desc": null, "pfm": null, "on_air_music": []
+        })).unwrap()
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2026-10-18T09:00:00+09:00").unwrap().with_timezone(&Utc)
+    }
+
+    fn changes(previous: &[RadioProgram], current: &[RadioProgram]) -> Vec<(u64, ScheduleChange)> {
+        detect_changes(previous, current, now()).into_iter().map(|e| (e.program.id, e.change)).collect()
+    }
+
+    #[test]
+    fn unchanged_schedule_has_no_events() {
+        let schedule = [program(1, "ラジオA", 20, (12, 0), 60), program(2, "ラジオB", 20, (13, 0), 60)];
+        assert!(changes(&schedule, &schedule).is_empty());
+    }
+
+    #[test]
+    fn removed_slot_is_cancelled() {
+        let previous = [program(1, "ラジオA", 20, (12, 0), 60), program(2, "ラジオB", 20, (13, 0), 60)];
+        assert_eq!(changes(&previous, &previous[1..]), [(1, ScheduleChange::Cancelled)]);
+    }
+
+    #[test]
+    fn slot_taken_by_another_program_is_preempted() {
+        let previous = [program(1, "ラジオA", 20, (12, 0), 60)];
+        let current = [program(9, "プロ野球中継", 20, (11, 30), 180)];
+        assert_eq!(changes(&previous, &current), [(1, ScheduleChange::Preempted { program_id: 9, title: "プロ野球中継".to_owned() })]);
+    }
+
+    #[test]
+    fn moved_program_is_rescheduled() {
+        // 同じIDのまま繰り下げ
+        let previous = [program(1, "ラジオA", 20, (12, 0), 60)];
+        let current = [program(9, "プロ野球中継", 20, (11, 0), 90), program(1, "ラジオA", 20, (12, 30), 60)];
+        let moved = &current[1];
+        assert_eq!(changes(&previous, &current), [(1, ScheduleChange::Rescheduled { program_id: 1, ft: moved.ft, to: moved.to })]);
+
+        // IDが変わって別の日に移った。元の日も取れている
+        let current = [program(5, "ニュース", 20, (15, 0), 30), program(2, "ラジオA", 22, (12, 0), 60)];
+        let moved = &current[1];
+        assert_eq!(changes(&previous, &current), [(1, ScheduleChange::Rescheduled { program_id: 2, ft: moved.ft, to: moved.to })]);
+    }
+
+    #[test]
+    fn reschedule_target_must_be_new_and_nearby() {
+        let previous = [program(1, "ラジオA", 20, (12, 0), 60), program(2, "ラジオA", 27, (12, 0), 60)];
+        // 翌週の回は前回からあったので振替先ではない
+        let current = [program(5, "ニュース", 20, (15, 0), 30), program(2, "ラジオA", 27, (12, 0), 60)];
+        assert_eq!(changes(&previous, &current), [(1, ScheduleChange::Cancelled)]);
+    }
+
+    #[test]
+    fn new_title_in_the_same_slot_is_retitled() {
+        let previous = [program(1, "ラジオA", 20, (12, 0), 60)];
+        let current = [program(1, "ラジオA 特別編", 20, (12, 0), 60)];
+        assert_eq!(changes(&previous, &current), [(1, ScheduleChange::Retitled { from: "ラジオA".to_owned(), title: "ラジオA 特別編".to_owned() })]);
+    }
+
+    #[test]
+    fn past_and_uncovered_programs_are_skipped() {
+        // 放送済み
+        let previous = [program(1, "ラジオA", 17, (12, 0), 60), program(2, "ラジオB", 21, (12, 0), 60)];
+        // 21日は今回取れていない
+        let current = [program(5, "ニュース", 17, (15, 0), 30), program(6, "ニュース", 20, (15, 0), 30)];
+        assert!(changes(&previous, &current).is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use firestore::{FirestoreDb, FirestoreDocument, ParentPathBuilder};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::expiry::ExpiryPolicy;
+use crate::firestore_write::{DocDelete, DocWrite};
+use crate::matcher::{confidence, Confidence, MatchType, MatchedProgram};
+use crate::output_path::split_matches;
+use crate::radiko::RadioProgram;
+use crate::review::{ReviewBook, Verdict};
+use crate::schedule::ScheduleChange;
+
+pub const DATA_ROOT: &str = "hello-radiko-data";
+// 旧形式: hello-radiko-data/programs/{member}/{program_id} に番組を丸ごとコピーしていた
+pub const LEGACY_PROGRAMS_DOC: &str = "programs";
+// 新形式: hello-radiko-data/catalog/programs/{program_id} と、その下の matches/{name}
+pub const CATALOG_DOC: &str = "catalog";
+pub const PROGRAM_COLLECTION: &str = "programs";
+pub const MATCH_COLLECTION: &str = "matches";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgramDocument {
+    #[serde(flatten)]
+    pub program: RadioProgram,
+    // array-containsで名前から番組を引くための索引
+    pub matched: Vec<String>,
+    // 前回の番組表から休止・振替などがあったとき
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change: Option<ScheduleChange>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchRecord {
+    pub name: String,
+    pub kind: String,
+    pub program_id: u64,
+    pub station_id: String,
+    pub title: String,
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    pub ft: DateTime<Utc>,
+    pub match_types: Vec<MatchType>,
+    // あいまい一致だけの場合はlow
+    #[serde(default = "high_confidence")]
+    pub confidence: Confidence,
+    // 人が確認したもの。取り消したものはそもそも書かない
+    #[serde(default)]
+    pub verdict: Option<Verdict>,
+    #[serde(default, with = "firestore::serialize_as_optional_timestamp")]
+    pub expire_at: Option<DateTime<Utc>>,
+}
+
+fn high_confidence() -> Confidence {
+    Confidence::High
+}
+
+pub fn catalog_parent(db: &FirestoreDb) -> Result<ParentPathBuilder> {
+    catalog_parent_in(db, DATA_ROOT)
+}
+
+// ウォッチリストごとに別のルートコレクションを使える
+pub fn catalog_parent_in(db: &FirestoreDb, root: &str) -> Result<ParentPathBuilder> {
+    Ok(db.parent_path(root, CATALOG_DOC)?)
+}
+
+pub fn match_parent(db: &FirestoreDb, program_id: u64) -> Result<ParentPathBuilder> {
+    match_parent_in(db, DATA_ROOT, program_id)
+}
+
+pub fn match_parent_in(db: &FirestoreDb, root: &str, program_id: u64) -> Result<ParentPathBuilder> {
+    Ok(catalog_parent_in(db, root)?.at(PROGRAM_COLLECTION, program_id.to_string())?)
+}
+
+// members.jsonにない名前はウォッチリストのターゲット
+fn kind_of(name: &str, groups: &[String], member_json: &Value) -> &'static str {
+    if groups.iter().any(|g| g == name) {
+        "group"
+    } else if member_json.as_object().unwrap().values().any(|members| members.get(name).is_some()) {
+        "member"
+    } else {
+        "target"
+    }
+}
+
+pub fn match_records(program: &RadioProgram, matched: &BTreeMap<String, Vec<MatchType>>, member_json: &Value, policy: &ExpiryPolicy, reviews: &ReviewBook) -> Vec<MatchRecord> {
+    let (groups, _) = split_matches(&matched.keys().cloned().collect::<Vec<_>>(), member_json);
+    matched.iter().map(|(name, match_types)| MatchRecord {
+        name: name.clone(),
+        kind: kind_of(name, &groups, member_json).to_owned(),
+        program_id: program.id,
+        station_id: program.radio_channel.id.clone(),
+        title: program.title.clone(),
+        ft: program.ft,
+        match_types: match_types.clone(),
+        confidence: confidence(match_types),
+        verdict: reviews.verdict(program.id, name),
+        expire_at: policy.expire_at_for(program, name, member_json),
+    }).collect()
+}
+
+pub type ProgramWrites = (DocWrite<ProgramDocument>, Vec<DocWrite<MatchRecord>>);
+
+// 番組は1回だけ書き、該当者ごとの情報はmatchesサブコレクションに置く
+// レビューで取り消された該当者はここで落とし、誰も残らなければ何も書かない
+pub fn program_writes(db: &FirestoreDb, root: &str, program: &RadioProgram, matched: &BTreeMap<String, Vec<MatchType>>, member_json: &Value, policy: &ExpiryPolicy, reviews: &ReviewBook) -> Result<Option<ProgramWrites>> {
+    let mut matched = matched.clone();
+    reviews.retain(program.id, &mut matched);
+    if matched.is_empty() {
+        return Ok(None);
+    }
+    let matched = &matched;
+    let names = matched.keys().cloned().collect::<Vec<_>>();
+    let program = &RadioProgram { expire_at: policy.program_expire_at(program, &names, member_json), ..program.clone() };
+    let program_write = DocWrite {
+        parent: catalog_parent_in(db, root)?.into(),
+        collection: PROGRAM_COLLECTION.to_owned(),
+        document_id: program.id.to_string(),
+        object: ProgramDocument { program: program.clone(), matched: names, change: None },
+    };
+    let match_parent: String = match_parent_in(db, root, program.id)?.into();
+    let match_writes = match_records(program, matched, member_json, policy, reviews).into_iter().map(|record| DocWrite {
+        parent: match_parent.clone(),
+        collection: MATCH_COLLECTION.to_owned(),
+        document_id: record.name.clone(),
+        object: record,
+    }).collect();
+    Ok(Some((program_write, match_writes)))
+}
+
+// 今ある matches/{name} のうち、今回の該当者にないもの(ルールの変更や除外、レビューで外れたもの)
+pub async fn stale_matches(db: &FirestoreDb, program_write: &DocWrite<ProgramDocument>) -> Result<Vec<DocDelete>> {
+    let parent = format!("{}/{}/{}", program_write.parent, program_write.collection, program_write.document_id);
+    let documents: Vec<FirestoreDocument> = db.fluent().list().from(MATCH_COLLECTION).parent(parent.as_str()).stream_all_with_errors().await?.try_collect().await?;
+    Ok(documents.iter().filter_map(|doc| doc.name.rsplit('/').next())
+        .filter(|name| !program_write.object.matched.iter().any(|matched| matched == name))
+        .map(|name| DocDelete { parent: parent.clone(), collection: MATCH_COLLECTION.to_owned(), document_id: name.to_owned() })
+        .collect())
+}
+
+// 旧形式の(該当者, コピー)を番組ごとにまとめる。コピー間で食い違っていれば一番あとに取得したもの(on_air_musicが多い方)を採る
+pub fn merge_legacy(docs: Vec<(String, MatchedProgram)>) -> Vec<(RadioProgram, BTreeMap<String, Vec<MatchType>>)> {
+    let mut merged: BTreeMap<u64, (RadioProgram, BTreeMap<String, Vec<MatchType>>)> = BTreeMap::new();
+    for (name, MatchedProgram { program, match_types }) in docs {
+        let entry = merged.entry(program.id).or_insert_with(|| (program.clone(), BTreeMap::new()));
+        if program.on_air_music.len() > entry.0.on_air_music.len() {
+            entry.0 = program;
+        }
+        // match_typesを持たない頃はテキストでしか探していなかった
+        entry.1.insert(name, if match_types.is_empty() { vec![MatchType::Text] } else { match_types });
+    }
+    merged.into_values().collect()
+}
@@ -0,0 +1,267 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use anyhow::Result;
+use chrono::{DateTime, Datelike, NaiveTime, Timelike, Utc, Weekday};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use unicode_normalization::UnicodeNormalization;
+use crate::archive::broadcast_date;
+use crate::radiko::{jst, RadioProgram};
+use crate::text::fold;
+
+// 2回以上、2週以上にわたって同じ曜日・時刻の枠で見つかったものを番組シリーズとみなす
+pub const MIN_EPISODES: usize = 2;
+// 毎回の実行で見直すのは直近の分だけ(番組表の先の分を含む)
+pub const LOOKBACK_DAYS: usize = 42;
+
+// 回数・再放送などの注記。foldしたあとの番組名から消す
+static EPISODE_MARKS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"【[^】]*】|\[[^\]]*\]|\([^)]*\)|<[^>]*>|第\d+(回|話|夜)|#\d+|(vol|ep)\.?\d+").unwrap()
+});
+
+pub fn title_key(title: &str) -> String {
+    let folded = fold(title);
+    let key = EPISODE_MARKS.replace_all(&folded, "").trim_matches(['-', '~', '〜', ':', '/']).to_owned();
+    if key.is_empty() { folded } else { key }
+}
+
+// 表示用。注記だけ落として表記はそのまま
+pub fn series_title(title: &str) -> String {
+    let title = title.nfkc().collect::<String>();
+    let stripped = EPISODE_MARKS.replace_all(&title, "").trim().to_owned();
+    if stripped.is_empty() { title } else { stripped }
+}
+
+// 局・曜日と開始時刻(JST)・番組名で決まる週1の枠。帯番組は曜日ごとに別シリーズになる
+// 再放送は注記を落とすと本放送と同じ名前になるので、時刻で分けている。そのかわり枠が移ると別シリーズになる
+pub fn series_id(program: &RadioProgram) -> String {
+    let start = program.ft.with_timezone(&jst());
+    let weekday = start.weekday().to_string().to_lowercase();
+    format!("{}_{weekday}{:02}{:02}_{}", program.radio_channel.id, start.hour(), start.minute(), title_hash(&title_key(&program.title)))
+}
+
+// 曜日を含めていなかったころのID。購読済みのものは全曜日の枠に当てはめる
+pub fn legacy_series_id(program: &RadioProgram) -> String {
+    let start = program.ft.with_timezone(&jst());
+    format!("{}_{:02}{:02}_{}", program.radio_channel.id, start.hour(), start.minute(), title_hash(&title_key(&program.title)))
+}
+
+fn title_hash(title_key: &str) -> String {
+    format!("{:x}", Sha256::digest(title_key.as_bytes()))[..8].to_owned()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Episode {
+    pub program_id: u64,
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    pub ft: DateTime<Utc>,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Gap {
+    // その日の番組表はあるのに、枠に何も入っていない
+    Missing,
+    // 別の番組(スポーツ中継など)が枠に入っていた
+    Preempted { program_id: u64, title: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MissedEpisode {
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    pub expected: DateTime<Utc>,
+    #[serde(flatten)]
+    pub gap: Gap,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Series {
+    pub id: String,
+    pub station_id: String,
+    // 最新回の番組名から回数などを落としたもの
+    pub title: String,
+    pub title_key: String,
+    pub weekdays: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub episodes: Vec<Episode>,
+    pub missed: Vec<MissedEpisode>,
+}
+
+impl Series {
+    pub fn latest(&self) -> Option<&Episode> {
+        self.episodes.last()
+    }
+    pub fn legacy_id(&self) -> String {
+        format!("{}_{}_{}", self.station_id, self.start.format("%H%M"), title_hash(&self.title_key))
+    }
+}
+
+// 購読中のシリーズの放送・休止の記録。番組表は直近の分しか見直さないので、それより前の回はここに残す
+pub struct SeriesHistory {
+    path: PathBuf,
+    pub series: BTreeMap<String, Series>,
+}
+
+impl SeriesHistory {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let series = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(SeriesHistory { path, series })
+    }
+    pub fn save(&self) -> Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.series)?)?;
+        fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+    // since 以降は今回見直した結果で置き換え、それより前の記録は残す。初めて記録した休止を返す
+    pub fn merge(&mut self, found: Series, since: DateTime<Utc>) -> Vec<MissedEpisode> {
+        let entry = self.series.entry(found.id.clone()).or_insert_with(|| Series { episodes: vec![], missed: vec![], ..found.clone() });
+        let new = found.missed.iter().filter(|m| !entry.missed.iter().any(|known| known.expected == m.expected)).cloned().collect();
+        entry.episodes.retain(|e| e.ft < since && !found.episodes.iter().any(|f| f.program_id == e.program_id));
+        entry.episodes.extend(found.episodes);
+        entry.episodes.sort_by_key(|e| e.ft);
+        entry.missed.retain(|m| m.expected < since);
+        entry.missed.extend(found.missed);
+        entry.missed.sort_by_key(|m| m.expected);
+        entry.title = found.title;
+        entry.weekdays = found.weekdays;
+        new
+    }
+}
+
+// 番組表から番組シリーズを拾い、枠があるはずなのに放送のなかった回を洗い出す
+pub fn detect(programs: &[RadioProgram], min_episodes: usize) -> Vec<Series> {
+    let mut groups = BTreeMap::<String, Vec<&RadioProgram>>::new();
+    let mut by_station = BTreeMap::<&str, Vec<&RadioProgram>>::new();
+    let mut covered = HashSet::new();
+    for program in programs {
+        groups.entry(series_id(program)).or_default().push(program);
+        by_station.entry(program.radio_channel.id.as_str()).or_default().push(program);
+        covered.insert((program.radio_channel.id.as_str(), broadcast_date(program)));
+    }
+    let mut found = vec![];
+    for (id, mut episodes) in groups {
+        episodes.sort_by_key(|p| p.ft);
+        episodes.dedup_by_key(|p| p.ft);
+        let weeks = episodes.iter().map(|p| broadcast_date(p).iso_week()).collect::<BTreeSet<_>>();
+        if episodes.len() < min_episodes || weeks.len() < 2 {
+            continue;
+        }
+        let first = episodes[0];
+        let station_id = first.radio_channel.id.as_str();
+        let start = first.ft.with_timezone(&jst()).time();
+        let mut weekdays = episodes.iter().map(|p| broadcast_date(p).weekday()).collect::<Vec<_>>();
+        weekdays.sort_by_key(|w| w.num_days_from_monday());
+        weekdays.dedup();
+        let aired = episodes.iter().map(|p| broadcast_date(p)).collect::<BTreeSet<_>>();
+        let last_day = covered.iter().filter(|(s, _)| *s == station_id).map(|(_, d)| *d).max().unwrap_or(broadcast_date(first));
+        let mut missed = vec![];
+        for date in broadcast_date(first).iter_days().take_while(|d| *d <= last_day) {
+            if !weekdays.contains(&date.weekday()) || aired.contains(&date) || !covered.contains(&(station_id, date)) {
+                continue;
+            }
+            let Some(expected) = date.and_time(start).and_local_timezone(jst()).single().map(|t| t.with_timezone(&Utc)) else { continue };
+            let occupant = by_station[station_id].iter().find(|p| p.ft <= expected && expected < p.to && series_id(p) != id);
+            missed.push(MissedEpisode {
+                expected,
+                gap: match occupant {
+                    Some(p) => Gap::Preempted { program_id: p.id, title: p.title.clone() },
+                    None => Gap::Missing,
+                },
+            });
+        }
+        found.push(Series {
+            id,
+            station_id: station_id.to_owned(),
+            title: series_title(&episodes.last().unwrap().title),
+            title_key: title_key(&first.title),
+            weekdays,
+            start,
+            episodes: episodes.iter().map(|p| Episode { program_id: p.id, ft: p.ft, title: p.title.clone() }).collect(),
+            missed,
+        });
+    }
+    found
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(id: u64, ft: &str, title: &str) -> RadioProgram {
+        let ft = DateTime::parse_from_rfc3339(ft).unwrap().with_timezone(&Utc);
+        serde_json::from_value(serde_json::json!({
+            "station_id": "TBS", "id": id, "ft": ft, "to": ft + chrono::TimeDelta::minutes(30), "dur": 1800,
+            "title": title, "img": null, "info": null, "desc": null, "pfm": null, "on_air_music": []
+        })).unwrap()
+    }
+
+    #[test]
+    fn id_includes_the_weekday() {
+        let monday = program(1, "2026-10-19T21:00:00+09:00", "番組A");
+        let tuesday = program(2, "2026-10-20T21:00:00+09:00", "番組A 第2回");
+        assert_eq!(series_id(&monday), format!("TBS_mon2100_{}", title_hash(&title_key("番組A"))));
+        assert_ne!(series_id(&monday), series_id(&tuesday));
+        assert_eq!(legacy_series_id(&monday), legacy_series_id(&tuesday));
+    }
+
+    #[test]
+    fn daily_show_is_split_by_weekday() {
+        // 2週分の月〜金の帯番組
+        let programs = (0..14).filter(|d| d % 7 < 5).map(|d| {
+            program(d, &format!("2026-10-{:02}T21:00:00+09:00", 19 + d), "帯番組")
+        }).collect::<Vec<_>>();
+        let found = detect(&programs, MIN_EPISODES);
+        assert_eq!(found.len(), 5);
+        assert!(found.iter().all(|s| s.weekdays.len() == 1 && s.episodes.len() == 2 && s.missed.is_empty()));
+        assert!(found.iter().all(|s| s.legacy_id() == legacy_series_id(&programs[0])));
+    }
+
+    #[test]
+    fn different_shows_in_the_same_slot_stay_apart() {
+        let programs = [
+            program(1, "2026-10-19T21:00:00+09:00", "月曜の番組"), program(2, "2026-10-26T21:00:00+09:00", "月曜の番組"),
+            program(3, "2026-10-20T21:00:00+09:00", "火曜の番組"), program(4, "2026-10-27T21:00:00+09:00", "火曜の番組"),
+        ];
+        let found = detect(&programs, MIN_EPISODES);
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|s| s.missed.is_empty()));
+    }
+
+    #[test]
+    fn history_keeps_episodes_older_than_the_lookback() {
+        let path = std::env::temp_dir().join(format!("radiko_series_history_{}.json", std::process::id()));
+        let mut history = SeriesHistory::open(&path).unwrap();
+        let weeks = |from: u32, to: u32| (from..to).map(|w| program(w as u64, &format!("2026-10-{:02}T21:00:00+09:00", 5 + 7 * w), "週1番組")).collect::<Vec<_>>();
+        let first = detect(&weeks(0, 3), MIN_EPISODES).remove(0);
+        assert!(history.merge(first, weeks(0, 3)[0].ft).is_empty());
+        history.save().unwrap();
+
+        // 次の実行では最初の週がもう見えず、3週目の枠にほかの番組が入っていた
+        let mut history = SeriesHistory::open(&path).unwrap();
+        let mut recent = weeks(1, 4);
+        let preempting = program(99, "2026-10-19T20:00:00+09:00", "野球中継");
+        recent[1] = RadioProgram { to: preempting.ft + chrono::TimeDelta::hours(3), ..preempting };
+        let since = recent.iter().map(|p| p.ft).min().unwrap();
+        let second = detect(&recent, MIN_EPISODES).remove(0);
+        let new = history.merge(second.clone(), since);
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].gap, Gap::Preempted { program_id: 99, title: "野球中継".to_owned() });
+        let stored = &history.series[&second.id];
+        assert_eq!(stored.episodes.iter().map(|e| e.program_id).collect::<Vec<_>>(), [0, 1, 3]);
+        assert_eq!(stored.missed, second.missed);
+        // 同じ休止は2回返さない
+        assert!(history.merge(second, since).is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use crate::radiko::{jst, RadioProgram};
+
+const FETCHED_AT_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+// ある時点で radiko の番組表(局・放送日ごと)がどうなっていたか。オンエア曲は含めない
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub station_id: String,
+    pub date: NaiveDate,
+    pub fetched_at: DateTime<Utc>,
+    pub programs: Vec<RadioProgram>,
+}
+
+// {dir}/{station}/{YYYYMMDD}/{fetched_at}.json.gz。前回と同じ内容なら書かない
+pub struct SnapshotStore {
+    dir: PathBuf,
+}
+
+fn same_schedule(a: &[RadioProgram], b: &[RadioProgram]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| program_fields(a) == program_fields(b))
+}
+
+fn program_fields(program: &RadioProgram) -> BTreeMap<&'static str, String> {
+    BTreeMap::from([
+        ("ft", program.ft.with_timezone(&jst()).format("%Y-%m-%d %H:%M").to_string()),
+        ("to", program.to.with_timezone(&jst()).format("%Y-%m-%d %H:%M").to_string()),
+        ("title", program.title.clone()),
+        ("pfm", program.pfm.clone().unwrap_or_default()),
+        ("info", program.info.clone().unwrap_or_default()),
+        ("desc", program.desc.clone().unwrap_or_default()),
+    ])
+}
+
+impl SnapshotStore {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(SnapshotStore { dir })
+    }
+    fn day_dir(&self, station_id: &str, date: NaiveDate) -> PathBuf {
+        self.dir.join(station_id).join(date.format("%Y%m%d").to_string())
+    }
+    pub fn save(&self, snapshot: &Snapshot) -> Result<bool> {
+        let mut programs = snapshot.programs.iter().map(|p| RadioProgram { on_air_music: vec![], ..p.clone() }).collect::<Vec<_>>();
+        programs.sort_by_key(|p| (p.ft, p.id));
+        if let Some(latest) = self.fetch_times(&snapshot.station_id, snapshot.date)?.last() {
+            if same_schedule(&self.load(&snapshot.station_id, snapshot.date, *latest)?.programs, &programs) {
+                return Ok(false);
+            }
+        }
+        let dir = self.day_dir(&snapshot.station_id, snapshot.date);
+        fs::create_dir_all(&dir)?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(serde_json::to_string(&Snapshot { programs, ..snapshot.clone() })?.as_bytes())?;
+        let path = dir.join(format!("{}.json.gz", snapshot.fetched_at.format(FETCHED_AT_FORMAT)));
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, encoder.finish()?)?;
+        fs::rename(tmp, path)?;
+        Ok(true)
+    }
+    pub fn load(&self, station_id: &str, date: NaiveDate, fetched_at: DateTime<Utc>) -> Result<Snapshot> {
+        let path = self.day_dir(station_id, date).join(format!("{}.json.gz", fetched_at.format(FETCHED_AT_FORMAT)));
+        let mut json = String::new();
+        GzDecoder::new(fs::File::open(path)?).read_to_string(&mut json)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+    fn names(dir: PathBuf) -> Result<Vec<String>> {
+        match fs::read_dir(dir) {
+            Ok(entries) => Ok(entries.filter_map(|e| e.ok()?.file_name().into_string().ok()).collect()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(err) => Err(err.into()),
+        }
+    }
+    pub fn stations(&self) -> Result<Vec<String>> {
+        let mut stations = Self::names(self.dir.clone())?;
+        stations.sort();
+        Ok(stations)
+    }
+    pub fn dates(&self, station_id: &str) -> Result<Vec<NaiveDate>> {
+        let mut dates = Self::names(self.dir.join(station_id))?.iter().filter_map(|name| NaiveDate::parse_from_str(name, "%Y%m%d").ok()).collect::<Vec<_>>();
+        dates.sort();
+        Ok(dates)
+    }
+    pub fn fetch_times(&self, station_id: &str, date: NaiveDate) -> Result<Vec<DateTime<Utc>>> {
+        let mut times = Self::names(self.day_dir(station_id, date))?.iter().filter_map(|name| {
+            let stem = name.strip_suffix(".json.gz")?;
+            NaiveDateTime::parse_from_str(stem, FETCHED_AT_FORMAT).ok().map(|t| t.and_utc())
+        }).collect::<Vec<_>>();
+        times.sort();
+        Ok(times)
+    }
+    // その時点で見えていた番組表(直前に取ったもの)
+    pub fn at(&self, station_id: &str, date: NaiveDate, time: DateTime<Utc>) -> Result<Option<Snapshot>> {
+        match self.fetch_times(station_id, date)?.into_iter().rev().find(|t| *t <= time) {
+            Some(fetched_at) => Ok(Some(self.load(station_id, date, fetched_at)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProgramDiff {
+    Added { program: RadioProgram },
+    Removed { program: RadioProgram },
+    Changed { before: Box<RadioProgram>, after: Box<RadioProgram>, fields: Vec<&'static str> },
+}
+
+impl ProgramDiff {
+    pub fn programs(&self) -> Vec<&RadioProgram> {
+        match self {
+            ProgramDiff::Added { program } | ProgramDiff::Removed { program } => vec![program],
+            ProgramDiff::Changed { before, after, .. } => vec![before, after],
+        }
+    }
+}
+
+// 番組IDで突き合わせる
+pub fn diff(before: &[RadioProgram], after: &[RadioProgram]) -> Vec<ProgramDiff> {
+    let mut diffs = vec![];
+    for old in before {
+        match after.iter().find(|p| p.id == old.id) {
+            None => diffs.push(ProgramDiff::Removed { program: old.clone() }),
+            Some(new) => {
+                let (old_fields, new_fields) = (program_fields(old), program_fields(new));
+                let fields = old_fields.keys().filter(|k| old_fields[*k] != new_fields[*k]).copied().collect::<Vec<_>>();
+                if !fields.is_empty() {
+                    diffs.push(ProgramDiff::Changed { before: Box::new(old.clone()), after: Box::new(new.clone()), fields });
+                }
+            }
+        }
+    }
+    diffs.extend(after.iter().filter(|new| !before.iter().any(|p| p.id == new.id)).map(|new| ProgramDiff::Added { program: new.clone() }));
+    diffs.sort_by_key(|d| d.programs()[0].ft);
+    diffs
+}
+
+// 差分の1行表示。変更は項目ごとに前後を並べる
+pub fn describe(diff: &ProgramDiff) -> Vec<String> {
+    let line = |mark: char, p: &RadioProgram| format!("{mark} {} {} ({})", p.ft.with_timezone(&jst()).format("%m/%d %H:%M"), p.title, p.id);
+    match diff {
+        ProgramDiff::Added { program } => vec![line('+', program)],
+        ProgramDiff::Removed { program } => vec![line('-', program)],
+        ProgramDiff::Changed { before, after, fields } => {
+            let (old_fields, new_fields) = (program_fields(before), program_fields(after));
+            std::iter::once(line('~', before))
+                .chain(fields.iter().map(|f| format!("    {f}: {} -> {}", old_fields[f], new_fields[f])))
+                .collect()
+        }
+    }
+}
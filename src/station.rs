@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use chrono::{DateTime, TimeDelta, Utc};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::radiko::RadioChannel;
+
+pub const STATION_COLLECTION: &str = "stations";
+// これより古いキャッシュはETag/Last-Modifiedで変わっていないか確かめる
+pub const LOGO_MAX_AGE: TimeDelta = TimeDelta::days(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedImage {
+    pub sha256: String,
+    pub path: PathBuf,
+    pub content_type: Option<String>,
+    // 最後に取った(または変わっていないと確かめた)時刻
+    pub fetched_at: DateTime<Utc>,
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StationRecord {
+    #[serde(flatten)]
+    pub channel: RadioChannel,
+    pub region_id: Option<String>,
+    pub logo_sha256: Option<String>,
+    pub banner_sha256: Option<String>,
+    #[serde(with = "firestore::serialize_as_timestamp")]
+    pub updated_at: DateTime<Utc>,
+}
+
+// ロゴ・バナーは中身のハッシュをファイル名にして保存する。同じ画像は何度取っても1つになる
+pub struct LogoCache {
+    dir: PathBuf,
+    pub index: BTreeMap<String, CachedImage>,
+}
+
+fn extension(content_type: Option<&str>, url: &str) -> &'static str {
+    match content_type {
+        Some(t) if t.contains("png") => "png",
+        Some(t) if t.contains("jpeg") => "jpg",
+        Some(t) if t.contains("gif") => "gif",
+        Some(t) if t.contains("svg") => "svg",
+        _ if url.ends_with(".jpg") || url.ends_with(".jpeg") => "jpg",
+        _ => "png",
+    }
+}
+
+impl LogoCache {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let index = match fs::read_to_string(dir.join("index.json")) {
+            Ok(s) => serde_json::from_str(&s)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(LogoCache { dir, index })
+    }
+    pub fn save(&self) -> Result<()> {
+        let tmp = self.dir.join("index.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.index)?)?;
+        fs::rename(tmp, self.dir.join("index.json"))?;
+        Ok(())
+    }
+    pub fn get(&self, url: &str) -> Option<&CachedImage> {
+        self.index.get(url).filter(|image| image.path.exists())
+    }
+    // 取り直して中身が変わっていればハッシュも変わる。キャッシュがあれば条件付きで取る
+    pub async fn fetch(&mut self, client: &Client, url: &str) -> Result<CachedImage> {
+        let cached = self.get(url).cloned();
+        let mut req = client.get(url);
+        if let Some(cached) = &cached {
+            if let Some(etag) = &cached.etag { req = req.header("If-None-Match", etag); }
+            if let Some(last_modified) = &cached.last_modified { req = req.header("If-Modified-Since", last_modified); }
+        }
+        let res = req.send().await?;
+        if let (StatusCode::NOT_MODIFIED, Some(cached)) = (res.status(), cached) {
+            let image = CachedImage { fetched_at: Utc::now(), ..cached };
+            self.index.insert(url.to_owned(), image.clone());
+            return Ok(image);
+        }
+        let res = res.error_for_status()?;
+        let header = |name: &str| res.headers().get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_owned());
+        let (content_type, etag, last_modified) = (header("content-type"), header("etag"), header("last-modified"));
+        let bytes = res.bytes().await?;
+        let sha256 = format!("{:x}", Sha256::digest(&bytes));
+        let path = self.dir.join(format!("{sha256}.{}", extension(content_type.as_deref(), url)));
+        if !path.exists() {
+            fs::write(&path, &bytes)?;
+        }
+        let image = CachedImage { sha256, path, content_type, fetched_at: Utc::now(), etag, last_modified };
+        self.index.insert(url.to_owned(), image.clone());
+        Ok(image)
+    }
+    // 同じURLのまま画像が差し替わることがあるので、LOGO_MAX_AGEを過ぎたら確かめ直す
+    pub async fn get_or_fetch(&mut self, client: &Client, url: &str) -> Result<CachedImage> {
+        match self.get(url) {
+            Some(image) if Utc::now() - image.fetched_at < LOGO_MAX_AGE => Ok(image.clone()),
+            Some(image) => {
+                let image = image.clone();
+                // 確かめられなければ手元のものを使う
+                Ok(self.fetch(client, url).await.unwrap_or(image))
+            }
+            None => self.fetch(client, url).await,
+        }
+    }
+    pub fn path_of(&self, url: &str) -> Option<&Path> {
+        self.get(url).map(|image| image.path.as_path())
+    }
+}
+
+impl StationRecord {
+    pub async fn build(channel: &RadioChannel, region_id: Option<String>, cache: &mut LogoCache, client: &Client) -> Self {
+        let logo_sha256 = cache.get_or_fetch(client, channel.logo_url()).await.ok().map(|image| image.sha256);
+        let banner_sha256 = cache.get_or_fetch(client, &channel.banner_url).await.ok().map(|image| image.sha256);
+        StationRecord { channel: channel.clone(), region_id, logo_sha256, banner_sha256, updated_at: Utc::now() }
+    }
+}
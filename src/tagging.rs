@@ -0,0 +1,509 @@
+use std::fs;
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{Datelike, TimeDelta, Timelike};
+use id3::frame::{Chapter, Comment, ExtendedText, Picture, PictureType, TableOfContents};
+use id3::{Frame, Tag, TagLike, Timestamp, Version};
+use reqwest::Client;
+use crate::loudness::Loudness;
+use crate::radiko::{jst, RadioProgram};
+
+#[derive(Debug, Clone)]
+pub struct ChapterMark {
+    pub start: TimeDelta,
+    pub end: TimeDelta,
+    pub title: String,
+}
+
+#[derive(Clone)]
+pub struct TagInfo {
+    pub title: String,
+    pub station: String,
+    pub performers: Option<String>,
+    pub air_date: chrono::DateTime<chrono::FixedOffset>,
+    pub description: Option<String>,
+    pub cover: Option<(String, Vec<u8>)>,
+    pub chapters: Vec<ChapterMark>,
+    pub loudness: Option<Loudness>,
+}
+
+impl TagInfo {
+    pub async fn from_program(program: &RadioProgram, client: &Client) -> Self {
+        let cover = match &program.img {
+            None => None,
+            Some(img) => match client.get(img).send().await {
+                Ok(res) => {
+                    let mime = res.headers().get("content-type").and_then(|v| v.to_str().ok()).map(|v| v.to_owned())
+                        .unwrap_or_else(|| if img.ends_with(".png") { "image/png".to_owned() } else { "image/jpeg".to_owned() });
+                    res.bytes().await.ok().map(|bytes| (mime, bytes.to_vec()))
+                }
+                Err(_) => None,
+            }
+        };
+        TagInfo {
+            title: program.title.clone(),
+            station: program.radio_channel.name.clone(),
+            performers: program.pfm.clone(),
+            air_date: program.ft.with_timezone(&jst()),
+            description: program.desc.clone().or(program.info.clone()),
+            cover,
+            chapters: chapters(program),
+            loudness: None,
+        }
+    }
+}
+
+// 曲の終了時刻は取れないので、次の曲の開始(最後は番組終了)までを1チャプターとする
+pub fn chapters(program: &RadioProgram) -> Vec<ChapterMark> {
+    let mut musics = program.on_air_music.iter().filter(|m| m.start_time >= TimeDelta::zero() && m.start_time < program.dur).collect::<Vec<_>>();
+    musics.sort_by_key(|m| m.start_time);
+    let mut marks = vec![];
+    if musics.first().map(|m| m.start_time > TimeDelta::zero()).unwrap_or(false) {
+        marks.push(ChapterMark { start: TimeDelta::zero(), end: TimeDelta::zero(), title: program.title.clone() });
+    }
+    marks.extend(musics.into_iter().map(|m| ChapterMark {
+        start: m.start_time,
+        end: TimeDelta::zero(),
+        title: format!("{} / {}", m.music_title, m.artist_name),
+    }));
+    let ends = marks.iter().skip(1).map(|m| m.start).chain([program.dur]).collect::<Vec<_>>();
+    marks.iter_mut().zip(ends).for_each(|(mark, end)| mark.end = end);
+    marks
+}
+
+// foobar2000等が読むREPLAYGAIN_*と、Opus系プレイヤー向けのR128_TRACK_GAIN
+fn replaygain_fields(info: &TagInfo) -> Vec<(&'static str, String)> {
+    match &info.loudness {
+        None => vec![],
+        Some(loudness) => vec![
+            ("REPLAYGAIN_TRACK_GAIN", format!("{:.2} dB", loudness.replaygain_db())),
+            ("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", loudness.peak)),
+            ("R128_TRACK_GAIN", loudness.r128_gain_q78().to_string()),
+        ],
+    }
+}
+
+// write_tags が扱える拡張子。変換プロファイルの読み込み時にも使う
+pub const TAGGABLE_EXTENSIONS: [&str; 6] = ["m4a", "mp4", "aac", "mp3", "opus", "ogg"];
+
+pub fn write_tags(path: &Path, info: &TagInfo) -> Result<()> {
+    match path.extension().and_then(|e| e.to_str()).unwrap_or_default() {
+        "m4a" | "mp4" => write_mp4_tags(path, info),
+        "aac" | "mp3" => write_id3_tags(path, info),
+        "opus" | "ogg" => write_ogg_tags(path, info),
+        ext => bail!("unsupported extension for tagging: {ext}"),
+    }
+}
+
+fn write_id3_tags(path: &Path, info: &TagInfo) -> Result<()> {
+    let mut tag = Tag::new();
+    tag.set_title(info.title.clone());
+    tag.set_artist(info.station.clone());
+    tag.set_album(info.station.clone());
+    tag.set_album_artist(info.station.clone());
+    tag.set_date_recorded(Timestamp {
+        year: info.air_date.year(),
+        month: Some(info.air_date.month() as u8),
+        day: Some(info.air_date.day() as u8),
+        hour: Some(info.air_date.hour() as u8),
+        minute: Some(info.air_date.minute() as u8),
+        second: None,
+    });
+    if let Some(performers) = &info.performers {
+        tag.add_frame(ExtendedText { description: "PERFORMER".to_owned(), value: performers.clone() });
+    }
+    if let Some(description) = &info.description {
+        tag.add_frame(Comment { lang: "jpn".to_owned(), description: String::new(), text: description.clone() });
+    }
+    if let Some((mime, data)) = &info.cover {
+        tag.add_frame(Picture { mime_type: mime.clone(), picture_type: PictureType::CoverFront, description: String::new(), data: data.clone() });
+    }
+    for (name, value) in replaygain_fields(info) {
+        tag.add_frame(ExtendedText { description: name.to_owned(), value });
+    }
+    for (i, mark) in info.chapters.iter().enumerate() {
+        tag.add_frame(Chapter {
+            element_id: format!("chp{i}"),
+            start_time: mark.start.num_milliseconds() as u32,
+            end_time: mark.end.num_milliseconds() as u32,
+            start_offset: 0xffffffff,
+            end_offset: 0xffffffff,
+            frames: vec![Frame::text("TIT2", mark.title.clone())],
+        });
+    }
+    if !info.chapters.is_empty() {
+        tag.add_frame(TableOfContents {
+            element_id: "toc".to_owned(),
+            top_level: true,
+            ordered: true,
+            elements: (0..info.chapters.len()).map(|i| format!("chp{i}")).collect(),
+            frames: vec![],
+        });
+    }
+    tag.write_to_path(path, Version::Id3v24)?;
+    Ok(())
+}
+
+pub(crate) fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut buf = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    buf.extend_from_slice(kind);
+    buf.extend_from_slice(payload);
+    buf
+}
+
+fn ilst_item(kind: &[u8; 4], type_code: u32, value: &[u8]) -> Vec<u8> {
+    let mut data = type_code.to_be_bytes().to_vec();
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(value);
+    mp4_box(kind, &mp4_box(b"data", &data))
+}
+
+fn ilst_freeform(name: &str, value: &str) -> Vec<u8> {
+    let mut payload = mp4_box(b"mean", &[&[0u8; 4][..], b"com.apple.iTunes"].concat());
+    payload.extend(mp4_box(b"name", &[&[0u8; 4][..], name.as_bytes()].concat()));
+    let mut data = 1u32.to_be_bytes().to_vec();
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(value.as_bytes());
+    payload.extend(mp4_box(b"data", &data));
+    mp4_box(b"----", &payload)
+}
+
+fn truncate_utf8(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) { end -= 1; }
+    &s[..end]
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Mp4Box {
+    pos: usize,
+    header: usize,
+    size: usize,
+    kind: [u8; 4],
+}
+
+fn read_boxes(buf: &[u8], start: usize, end: usize) -> Result<Vec<Mp4Box>> {
+    let mut boxes = vec![];
+    let mut pos = start;
+    while pos + 8 <= end {
+        let size = u32::from_be_bytes(buf[pos..pos + 4].try_into()?) as usize;
+        let kind: [u8; 4] = buf[pos + 4..pos + 8].try_into()?;
+        let (header, size) = match size {
+            0 => (8, end - pos),
+            1 => (16, u64::from_be_bytes(buf[pos + 8..pos + 16].try_into()?) as usize),
+            size => (8, size),
+        };
+        if size < header || pos + size > end { bail!("broken mp4 box: {}", String::from_utf8_lossy(&kind)); }
+        boxes.push(Mp4Box { pos, header, size, kind });
+        pos += size;
+    }
+    Ok(boxes)
+}
+
+// moovがmdatより前にある場合、moovのサイズが変わった分だけチャンクオフセットをずらす
+fn shift_chunk_offsets(buf: &mut [u8], start: usize, end: usize, delta: i64) -> Result<()> {
+    for Mp4Box { pos, header, size, kind } in read_boxes(buf, start, end)? {
+        match &kind {
+            b"trak" | b"mdia" | b"minf" | b"stbl" => shift_chunk_offsets(buf, pos + header, pos + size, delta)?,
+            b"stco" | b"co64" => {
+                let width = if &kind == b"stco" { 4 } else { 8 };
+                let count = u32::from_be_bytes(buf[pos + header + 4..pos + header + 8].try_into()?) as usize;
+                for i in 0..count {
+                    let at = pos + header + 8 + i * width;
+                    if width == 4 {
+                        let v = u32::from_be_bytes(buf[at..at + 4].try_into()?) as i64 + delta;
+                        buf[at..at + 4].copy_from_slice(&(v as u32).to_be_bytes());
+                    } else {
+                        let v = u64::from_be_bytes(buf[at..at + 8].try_into()?) as i64 + delta;
+                        buf[at..at + 8].copy_from_slice(&(v as u64).to_be_bytes());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn write_mp4_tags(path: &Path, info: &TagInfo) -> Result<()> {
+    let buf = fs::read(path)?;
+    let top = read_boxes(&buf, 0, buf.len())?;
+    let Some(&Mp4Box { pos: moov_pos, header: moov_header, size: moov_size, .. }) = top.iter().find(|b| &b.kind == b"moov") else { bail!("moov not found") };
+    let mdat_after_moov = top.iter().any(|b| &b.kind == b"mdat" && b.pos > moov_pos);
+
+    let mut ilst = ilst_item(b"\xa9nam", 1, info.title.as_bytes());
+    ilst.extend(ilst_item(b"\xa9ART", 1, info.station.as_bytes()));
+    ilst.extend(ilst_item(b"aART", 1, info.station.as_bytes()));
+    ilst.extend(ilst_item(b"\xa9alb", 1, info.station.as_bytes()));
+    ilst.extend(ilst_item(b"\xa9day", 1, info.air_date.format("%Y-%m-%dT%H:%M:%S%:z").to_string().as_bytes()));
+    if let Some(performers) = &info.performers {
+        ilst.extend(ilst_freeform("PERFORMER", performers));
+    }
+    if let Some(description) = &info.description {
+        ilst.extend(ilst_item(b"desc", 1, description.as_bytes()));
+        ilst.extend(ilst_item(b"\xa9cmt", 1, description.as_bytes()));
+    }
+    for (name, value) in replaygain_fields(info) {
+        ilst.extend(ilst_freeform(&name.to_lowercase(), &value));
+    }
+    if let Some((mime, data)) = &info.cover {
+        ilst.extend(ilst_item(b"covr", if mime.contains("png") { 14 } else { 13 }, data));
+    }
+    let mut hdlr = vec![0u8; 8];
+    hdlr.extend_from_slice(b"mdirappl");
+    hdlr.extend_from_slice(&[0; 9]);
+    let mut meta = vec![0u8; 4];
+    meta.extend(mp4_box(b"hdlr", &hdlr));
+    meta.extend(mp4_box(b"ilst", &ilst));
+    let mut udta = mp4_box(b"meta", &meta);
+    if !info.chapters.is_empty() {
+        // Nero形式のチャプター(chpl)。件数もタイトル長も1バイトなので255で切る
+        let mut chpl = vec![1, 0, 0, 0, 0, 0, 0, 0, info.chapters.len().min(255) as u8];
+        for mark in info.chapters.iter().take(255) {
+            chpl.extend_from_slice(&((mark.start.num_milliseconds() * 10_000) as u64).to_be_bytes());
+            let title = truncate_utf8(&mark.title, 255);
+            chpl.push(title.len() as u8);
+            chpl.extend_from_slice(title.as_bytes());
+        }
+        udta.extend(mp4_box(b"chpl", &chpl));
+    }
+
+    let mut moov_payload = vec![];
+    for Mp4Box { pos, size, kind, .. } in read_boxes(&buf, moov_pos + moov_header, moov_pos + moov_size)? {
+        if &kind != b"udta" {
+            moov_payload.extend_from_slice(&buf[pos..pos + size]);
+        }
+    }
+    moov_payload.extend(mp4_box(b"udta", &udta));
+    let mut moov = mp4_box(b"moov", &moov_payload);
+    if mdat_after_moov {
+        let delta = moov.len() as i64 - moov_size as i64;
+        let moov_len = moov.len();
+        shift_chunk_offsets(&mut moov, 8, moov_len, delta)?;
+    }
+
+    let mut out = Vec::with_capacity(buf.len() + moov.len());
+    out.extend_from_slice(&buf[..moov_pos]);
+    out.extend(moov);
+    out.extend_from_slice(&buf[moov_pos + moov_size..]);
+    let tmp = path.with_extension("tagging");
+    fs::write(&tmp, out)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+// Ogg(Opus/Vorbis)のCRC。多項式0x04c11db7、反転なし
+fn ogg_crc(buf: &[u8]) -> u32 {
+    buf.iter().fold(0u32, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u32) << 24), |crc, _| if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04c1_1db7 } else { crc << 1 })
+    })
+}
+
+#[derive(Debug, Clone)]
+struct OggPage {
+    header_type: u8,
+    granule: u64,
+    serial: u32,
+    seq: u32,
+    lacing: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl OggPage {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = b"OggS\0".to_vec();
+        buf.push(self.header_type);
+        buf.extend_from_slice(&self.granule.to_le_bytes());
+        buf.extend_from_slice(&self.serial.to_le_bytes());
+        buf.extend_from_slice(&self.seq.to_le_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        buf.push(self.lacing.len() as u8);
+        buf.extend_from_slice(&self.lacing);
+        buf.extend_from_slice(&self.data);
+        let crc = ogg_crc(&buf);
+        buf[22..26].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+}
+
+fn read_ogg_pages(buf: &[u8]) -> Result<Vec<OggPage>> {
+    let mut pages = vec![];
+    let mut pos = 0;
+    while pos < buf.len() {
+        let header = buf.get(pos..pos + 27).context("truncated ogg page")?;
+        if &header[..4] != b"OggS" { bail!("ogg page not found at {pos}") }
+        let count = header[26] as usize;
+        let lacing = buf.get(pos + 27..pos + 27 + count).context("truncated ogg page")?.to_vec();
+        let start = pos + 27 + count;
+        let len = lacing.iter().map(|&l| l as usize).sum::<usize>();
+        let data = buf.get(start..start + len).context("truncated ogg page")?.to_vec();
+        pages.push(OggPage {
+            header_type: header[5],
+            granule: u64::from_le_bytes(header[6..14].try_into()?),
+            serial: u32::from_le_bytes(header[14..18].try_into()?),
+            seq: u32::from_le_bytes(header[18..22].try_into()?),
+            lacing,
+            data,
+        });
+        pos = start + len;
+    }
+    Ok(pages)
+}
+
+// ヘッダーのパケット(Opusは2つ、Vorbisは3つ)と、それが入っていたページ数
+fn ogg_header_packets(pages: &[OggPage]) -> Result<(Vec<Vec<u8>>, usize)> {
+    let mut packets = vec![];
+    let mut packet = vec![];
+    for (index, page) in pages.iter().enumerate() {
+        if page.serial != pages[0].serial { bail!("multiplexed ogg streams are not supported") }
+        let mut offset = 0;
+        for &l in &page.lacing {
+            packet.extend_from_slice(&page.data[offset..offset + l as usize]);
+            offset += l as usize;
+            if l < 255 { packets.push(std::mem::take(&mut packet)); }
+        }
+        let needed = match packets.first() {
+            Some(first) if first.starts_with(b"OpusHead") => 2,
+            Some(first) if first.starts_with(b"\x01vorbis") => 3,
+            Some(_) => bail!("unsupported ogg codec"),
+            None => continue,
+        };
+        if packets.len() >= needed {
+            if packets.len() > needed || !packet.is_empty() { bail!("audio data shares a page with the ogg headers") }
+            return Ok((packets, index + 1));
+        }
+    }
+    bail!("ogg headers not found")
+}
+
+// ヘッダーのページは granule 0。途中で終わるパケットしかないページは -1
+fn ogg_paginate(packets: &[Vec<u8>], serial: u32, first_seq: u32) -> Vec<OggPage> {
+    let mut pages = vec![];
+    let mut page = OggPage { header_type: 0, granule: u64::MAX, serial, seq: first_seq, lacing: vec![], data: vec![] };
+    for packet in packets {
+        let mut rest = &packet[..];
+        loop {
+            if page.lacing.len() == 255 {
+                let continued = page.lacing.last() == Some(&255);
+                let next = OggPage { header_type: if continued { 1 } else { 0 }, granule: u64::MAX, serial, seq: page.seq + 1, lacing: vec![], data: vec![] };
+                pages.push(std::mem::replace(&mut page, next));
+            }
+            let len = rest.len().min(255);
+            page.lacing.push(len as u8);
+            page.data.extend_from_slice(&rest[..len]);
+            rest = &rest[len..];
+            if len < 255 {
+                page.granule = 0;
+                break;
+            }
+        }
+    }
+    pages.push(page);
+    pages
+}
+
+fn ogg_comment_fields(info: &TagInfo, opus: bool) -> Comments {
+    let mut fields = vec![
+        ("TITLE".to_owned(), info.title.clone()),
+        ("ARTIST".to_owned(), info.station.clone()),
+        ("ALBUM".to_owned(), info.station.clone()),
+        ("ALBUMARTIST".to_owned(), info.station.clone()),
+        ("DATE".to_owned(), info.air_date.format("%Y-%m-%dT%H:%M:%S%:z").to_string()),
+    ];
+    if let Some(performers) = &info.performers {
+        fields.push(("PERFORMER".to_owned(), performers.clone()));
+    }
+    if let Some(description) = &info.description {
+        fields.push(("DESCRIPTION".to_owned(), description.clone()));
+        fields.push(("COMMENT".to_owned(), description.clone()));
+    }
+    // OpusではREPLAYGAIN_*ではなくR128_TRACK_GAIN(出力ゲインからの相対値)を使う
+    fields.extend(replaygain_fields(info).into_iter().filter(|(name, _)| name.starts_with("R128_") == opus).map(|(name, value)| (name.to_owned(), value)));
+    if let Some((mime, data)) = &info.cover {
+        // FLACのPICTUREブロックをbase64にしたもの
+        let mut block = 3u32.to_be_bytes().to_vec();
+        block.extend_from_slice(&(mime.len() as u32).to_be_bytes());
+        block.extend_from_slice(mime.as_bytes());
+        block.extend_from_slice(&[0; 20]);
+        block.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        block.extend_from_slice(data);
+        fields.push(("METADATA_BLOCK_PICTURE".to_owned(), STANDARD.encode(block)));
+    }
+    for (i, mark) in info.chapters.iter().enumerate() {
+        let ms = mark.start.num_milliseconds();
+        fields.push((format!("CHAPTER{:03}", i + 1), format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)));
+        fields.push((format!("CHAPTER{:03}NAME", i + 1), mark.title.clone()));
+    }
+    fields
+}
+
+type Comments = Vec<(String, String)>;
+
+// コメントヘッダーの中身。vendorとコメントの組
+fn parse_comment_packet(packet: &[u8], magic: &[u8]) -> Result<(Vec<u8>, Comments)> {
+    let body = packet.strip_prefix(magic).context("broken comment header")?;
+    let mut pos = 0;
+    let mut read = |n: usize| -> Result<&[u8]> {
+        let slice = body.get(pos..pos + n).context("broken comment header")?;
+        pos += n;
+        Ok(slice)
+    };
+    let vendor_len = u32::from_le_bytes(read(4)?.try_into()?) as usize;
+    let vendor = read(vendor_len)?.to_vec();
+    let count = u32::from_le_bytes(read(4)?.try_into()?);
+    let mut comments = vec![];
+    for _ in 0..count {
+        let len = u32::from_le_bytes(read(4)?.try_into()?) as usize;
+        let comment = String::from_utf8_lossy(read(len)?).into_owned();
+        let (name, value) = comment.split_once('=').unwrap_or((comment.as_str(), ""));
+        comments.push((name.to_owned(), value.to_owned()));
+    }
+    Ok((vendor, comments))
+}
+
+fn comment_magic(packets: &[Vec<u8>]) -> (&'static [u8], bool) {
+    if packets[0].starts_with(b"OpusHead") { (b"OpusTags", true) } else { (b"\x03vorbis", false) }
+}
+
+pub fn read_ogg_comments(path: &Path) -> Result<Comments> {
+    let (packets, _) = ogg_header_packets(&read_ogg_pages(&fs::read(path)?)?)?;
+    let (magic, _) = comment_magic(&packets);
+    Ok(parse_comment_packet(&packets[1], magic)?.1)
+}
+
+// コメントヘッダーを書き直し、後ろのページは通し番号を振り直す
+fn write_ogg_tags(path: &Path, info: &TagInfo) -> Result<()> {
+    let pages = read_ogg_pages(&fs::read(path)?)?;
+    let (mut packets, header_pages) = ogg_header_packets(&pages)?;
+    let (magic, opus) = comment_magic(&packets);
+    let (vendor, _) = parse_comment_packet(&packets[1], magic)?;
+    let fields = ogg_comment_fields(info, opus);
+    let mut comment = magic.to_vec();
+    comment.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    comment.extend_from_slice(&vendor);
+    comment.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+    for (name, value) in &fields {
+        let field = format!("{name}={value}");
+        comment.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        comment.extend_from_slice(field.as_bytes());
+    }
+    if !opus {
+        comment.push(1);
+    }
+    packets[1] = comment;
+
+    let serial = pages[0].serial;
+    let mut out = pages[0].to_bytes();
+    let headers = ogg_paginate(&packets[1..], serial, pages[0].seq + 1);
+    let next_seq = headers.last().map(|p| p.seq + 1).unwrap_or_default();
+    headers.iter().for_each(|page| out.extend(page.to_bytes()));
+    for (i, page) in pages[header_pages..].iter().enumerate() {
+        out.extend(OggPage { seq: next_seq + i as u32, ..page.clone() }.to_bytes());
+    }
+    let tmp = path.with_extension("tagging");
+    fs::write(&tmp, out)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use std::collections::{BTreeMap, BTreeSet};
+use unicode_normalization::UnicodeNormalization;
+use crate::radiko::RadioProgram;
+
+// NFKCでは寄らない異体字。人名でよく揺れるものを代表字に寄せる
+pub const ITAIJI: [(char, char); 34] = [
+    ('﨑', '崎'), ('嵜', '崎'), ('髙', '高'), ('𠮷', '吉'), ('濵', '浜'), ('濱', '浜'), ('邊', '辺'), ('邉', '辺'),
+    ('澤', '沢'), ('齋', '斎'), ('齊', '斉'), ('櫻', '桜'), ('國', '国'), ('廣', '広'), ('嶋', '島'), ('嶌', '島'),
+    ('眞', '真'), ('瀨', '瀬'), ('惠', '恵'), ('曾', '曽'), ('德', '徳'), ('凜', '凛'), ('榮', '栄'), ('冨', '富'),
+    ('槇', '槙'), ('𣘺', '橋'), ('檜', '桧'), ('峯', '峰'), ('龍', '竜'), ('藝', '芸'), ('實', '実'), ('壽', '寿'),
+    ('彌', '弥'), ('萬', '万'),
+];
+
+// 検索・マッチング用に寄せる: NFKC、異体字、カタカナ→ひらがな、英字は小文字、空白と中黒は無視
+pub fn fold(text: &str) -> String {
+    fold_tokens(text).chars().filter(|c| *c != ' ').collect()
+}
+
+// foldと同じだが、空白と中黒は語の区切りとして半角空白1つにして残す。本文側に使う
+pub fn fold_tokens(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.nfkc() {
+        if c.is_whitespace() || c == '・' {
+            if !out.is_empty() && !out.ends_with(' ') { out.push(' '); }
+            continue;
+        }
+        let c = ITAIJI.iter().find(|(from, _)| *from == c).map(|(_, to)| *to).unwrap_or(c);
+        // ァ(U+30A1)〜ヶ(U+30F6)はひらがなと0x60ずれている(ヵヶはひらがなにもある)
+        let c = match c {
+            'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+            c => c,
+        };
+        out.extend(c.to_lowercase());
+    }
+    out.trim_end().to_owned()
+}
+
+pub(crate) fn is_kanji(c: char) -> bool {
+    matches!(c, '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' | '\u{f900}'..='\u{faff}' | '\u{20000}'..='\u{2ffff}' | '々')
+}
+
+// fold_tokensした本文に、foldした語が含まれるか。区切りをまたぐ一致は、直後が語の終わりか漢字以外のときだけ認める
+// (「譜久村 聖さん」に譜久村聖は当たるが、「後藤 花子」に後藤花は当たらない)
+pub fn contains_folded(tokens: &str, needle: &str) -> bool {
+    let needle = needle.chars().collect::<Vec<_>>();
+    if needle.is_empty() { return false; }
+    let mut chars = vec![];
+    let mut boundary = vec![true];
+    for c in tokens.chars() {
+        if c == ' ' {
+            *boundary.last_mut().unwrap() = true;
+        } else {
+            chars.push(c);
+            boundary.push(false);
+        }
+    }
+    *boundary.last_mut().unwrap() = true;
+    (0..chars.len()).filter(|&start| chars[start..].starts_with(&needle)).any(|start| {
+        let end = start + needle.len();
+        !boundary[start + 1..end].contains(&true) || boundary[end] || !is_kanji(chars[end])
+    })
+}
+
+pub fn fold_contains(haystack: &str, needle: &str) -> bool {
+    contains_folded(&fold_tokens(haystack), &fold(needle))
+}
+
+// 形態素解析の辞書は持たないので、寄せた文字列の2-gramで引く
+pub fn bigrams(folded: &str) -> BTreeSet<String> {
+    let chars = folded.chars().collect::<Vec<_>>();
+    if chars.len() == 1 { return BTreeSet::from([folded.to_owned()]); }
+    chars.windows(2).map(|w| w.iter().collect()).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Title,
+    Pfm,
+    Desc,
+    Info,
+}
+
+impl Field {
+    pub const ALL: [Field; 4] = [Field::Title, Field::Pfm, Field::Desc, Field::Info];
+    // タイトル・出演者に出てくる方が、説明文のついでに名前が出るより重い
+    pub fn weight(&self) -> f64 {
+        match self {
+            Field::Title => 3.0,
+            Field::Pfm => 2.5,
+            Field::Desc => 1.0,
+            Field::Info => 0.5,
+        }
+    }
+    pub fn text<'a>(&self, program: &'a RadioProgram) -> Option<&'a str> {
+        match self {
+            Field::Title => Some(program.title.as_str()),
+            Field::Pfm => program.pfm.as_deref(),
+            Field::Desc => program.desc.as_deref(),
+            Field::Info => program.info.as_deref(),
+        }
+    }
+}
+
+// 読み(かな)と表記の対応。本文に読みだけが書かれていても表記で引けるようにする
+#[derive(Debug, Clone, Default)]
+pub struct Readings {
+    pairs: Vec<(String, String)>,
+    raw: BTreeMap<String, String>,
+}
+
+impl Readings {
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        let raw: BTreeMap<String, String> = serde_json::from_str(json)?;
+        let pairs = raw.iter().map(|(surface, reading)| (fold(surface), fold(reading))).collect();
+        Ok(Readings { pairs, raw: raw.into_iter().map(|(surface, reading)| (fold(&surface), reading)).collect() })
+    }
+    // 姓と名の区切り(空白)を残した読み
+    pub fn reading_of(&self, surface: &str) -> Option<&str> {
+        self.raw.get(&fold(surface)).map(|r| r.as_str())
+    }
+    // 表記か読みのどちらかが含まれていれば、もう片方も付け足す
+    pub fn expand(&self, folded: &str) -> String {
+        let mut out = folded.to_owned();
+        for (surface, reading) in &self.pairs {
+            if folded.contains(surface.as_str()) && !folded.contains(reading.as_str()) {
+                out.push('\u{1f}');
+                out.push_str(reading);
+            } else if folded.contains(reading.as_str()) && !folded.contains(surface.as_str()) {
+                out.push('\u{1f}');
+                out.push_str(surface);
+            }
+        }
+        out
+    }
+}
+
+// 番組の各フィールドを寄せて読みも足したもの
+pub fn indexed_fields(program: &RadioProgram, readings: &Readings) -> Vec<(Field, String)> {
+    Field::ALL.iter().filter_map(|field| field.text(program).map(|text| (*field, readings.expand(&fold(text))))).collect()
+}
+
+pub fn query_terms(query: &str) -> Vec<String> {
+    query.split_whitespace().map(fold).filter(|t| !t.is_empty()).collect()
+}
+
+// すべての語を含むときだけ、語ごとに一番重いフィールドの重みを足す
+pub fn score(fields: &[(Field, String)], terms: &[String]) -> Option<f64> {
+    terms.iter().map(|term| {
+        fields.iter().filter(|(_, text)| text.contains(term.as_str())).map(|(field, _)| field.weight()).reduce(f64::max)
+    }).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_within_one_token() {
+        assert!(fold_contains("ゲスト:譜久村聖(モーニング娘。'24)", "譜久村聖"));
+        assert!(fold_contains("カントリー・ガールズの新曲", "カントリーガールズ"));
+        assert!(fold_contains("ＭＯＲＮＩＮＧ　ＭＵＳＵＭＥ", "morning musume"));
+        assert!(!fold_contains("譜久村", "譜久村聖"));
+        assert!(!contains_folded("abc", ""));
+    }
+
+    #[test]
+    fn match_across_a_separator_needs_a_word_end() {
+        // 区切りのあとが本文の終わり・区切り・漢字以外なら語の終わりとみなす
+        assert!(fold_contains("譜久村 聖", "譜久村聖"));
+        assert!(fold_contains("譜久村 聖 石田亜佑美", "譜久村聖"));
+        assert!(fold_contains("譜久村 聖(モーニング娘。)", "譜久村聖"));
+        assert!(fold_contains("譜久村 聖さん", "譜久村聖"));
+        assert!(fold_contains("カントリー・ガールズの", "カントリーガールズ"));
+        // 直後に漢字が続くと別の名前の途中かもしれない
+        assert!(!fold_contains("後藤 花子", "後藤花"));
+        assert!(!fold_contains("譜久村 聖子", "譜久村聖"));
+    }
+
+    #[test]
+    fn later_occurrence_can_match() {
+        assert!(fold_contains("後藤 花子と後藤 花", "後藤花"));
+        assert!(fold_contains("後藤 花子、後藤花子", "後藤花"));
+    }
+}
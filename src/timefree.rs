@@ -0,0 +1,86 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use crate::radiko::RadioProgram;
+
+// タイムフリーは放送開始から1週間(7日)まで聴ける
+pub const TIMEFREE_WINDOW: TimeDelta = TimeDelta::days(7);
+pub const EXPIRY_WARNING: TimeDelta = TimeDelta::hours(24);
+
+pub fn deadline(program: &RadioProgram) -> DateTime<Utc> {
+    program.ft + TIMEFREE_WINDOW
+}
+
+pub fn remaining(program: &RadioProgram, now: DateTime<Utc>) -> TimeDelta {
+    deadline(program) - now
+}
+
+// 放送が終わっていて、まだ期限切れになっていないもの
+pub fn is_available(program: &RadioProgram, now: DateTime<Utc>) -> bool {
+    program.to <= now && now < deadline(program)
+}
+
+pub fn is_expiring(program: &RadioProgram, now: DateTime<Utc>) -> bool {
+    is_available(program, now) && remaining(program, now) < EXPIRY_WARNING
+}
+
+// 期限が近い(=古い)ものから順に並べる
+pub fn plan<T>(mut queue: Vec<(RadioProgram, T)>, now: DateTime<Utc>) -> Vec<(RadioProgram, T)> {
+    queue.retain(|(program, _)| is_available(program, now));
+    queue.sort_by_key(|(program, _)| (deadline(program), program.radio_channel.id.clone()));
+    queue
+}
+
+pub fn format_remaining(remaining: TimeDelta) -> String {
+    format!("{}日{}時間{}分", remaining.num_days(), remaining.num_hours() % 24, remaining.num_minutes() % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn program(station: &str, id: u64, ft: &str) -> RadioProgram {
+        let ft = DateTime::parse_from_rfc3339(ft).unwrap().with_timezone(&Utc);
+        serde_json::from_value(json!({
+            "station_id": station, "id": id, "ft": ft, "to": ft + TimeDelta::hours(1), "dur": 3600,
+            "title": "ラジオA", "img": null, "info": null, "desc": null, "pfm": null, "on_air_music": []
+        })).unwrap()
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2026-10-18T12:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn plan_orders_by_deadline_then_station() {
+        let queue = vec![
+            (program("TBS", 1, "2026-10-17T12:00:00Z"), "a"),
+            (program("TBS", 2, "2026-10-12T00:00:00Z"), "b"),
+            (program("QRR", 3, "2026-10-15T09:00:00Z"), "c"),
+            (program("LFR", 4, "2026-10-15T09:00:00Z"), "d"),
+        ];
+        assert_eq!(plan(queue, now()).iter().map(|(_, t)| *t).collect::<Vec<_>>(), ["b", "d", "c", "a"]);
+    }
+
+    #[test]
+    fn plan_drops_unavailable_programs() {
+        let queue = vec![
+            // 期限切れ
+            (program("TBS", 1, "2026-10-11T11:00:00Z"), 1),
+            // ちょうど期限
+            (program("TBS", 2, "2026-10-11T12:00:00Z"), 2),
+            // 放送中
+            (program("TBS", 3, "2026-10-18T11:30:00Z"), 3),
+            // ちょうど終わった
+            (program("TBS", 4, "2026-10-18T11:00:00Z"), 4),
+            (program("TBS", 5, "2026-10-11T12:01:00Z"), 5),
+        ];
+        assert_eq!(plan(queue, now()).iter().map(|(p, _)| p.id).collect::<Vec<_>>(), [5, 4]);
+    }
+
+    #[test]
+    fn expiring_within_a_day() {
+        assert!(is_expiring(&program("TBS", 1, "2026-10-11T13:00:00Z"), now()));
+        assert!(!is_expiring(&program("TBS", 1, "2026-10-12T12:00:00Z"), now()));
+        assert_eq!(format_remaining(remaining(&program("TBS", 1, "2026-10-12T13:30:00Z"), now())), "1日1時間30分");
+    }
+}
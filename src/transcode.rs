@@ -0,0 +1,257 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::adts::scan_frames;
+use crate::loudness::Loudness;
+use crate::radiko::{jst, RadioProgram};
+use crate::tagging::{mp4_box, TAGGABLE_EXTENSIONS};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "encoder", rename_all = "snake_case")]
+pub enum Profile {
+    // 再エンコードせずADTSをMP4(.m4a)に詰め替える。プロセス内で完結するのはこれだけ
+    Remux,
+    // {input} {output} {bitrate} {gain} {title} {station} {date} を置き換えて外部コマンドを呼ぶ
+    // {gain}はnormalizeがtrueのときだけ基準音量までのdB、それ以外は0
+    // Opus/MP3へのプロセス内エンコードは扱わない。再エンコードは常にffmpeg等の外部コマンドで、
+    // 入っていなければ読み込み時にエラーにする
+    External { command: Vec<String>, ext: String, bitrate: Option<String>, #[serde(default)] normalize: bool },
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfileRule {
+    pub profile: String,
+    pub title: Option<String>,
+    pub station: Option<String>,
+    pub member: Option<String>,
+    // titleをコンパイルしたもの。load で埋める
+    #[serde(skip)]
+    title_regex: Option<Regex>,
+}
+
+// 例: {"default":"m4a","profiles":{"m4a":{"encoder":"remux"},"talk":{"encoder":"external","ext":"opus","bitrate":"32k","normalize":true,
+//       "command":["ffmpeg","-y","-i","{input}","-af","volume={gain}dB","-c:a","libopus","-b:a","{bitrate}","{output}"]}},"rules":[{"title":"トーク","profile":"talk"}]}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TranscodeConfig {
+    pub default: Option<String>,
+    #[serde(default)]
+    pub profiles: std::collections::HashMap<String, Profile>,
+    #[serde(default)]
+    pub rules: Vec<ProfileRule>,
+}
+
+impl TranscodeConfig {
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(s) => {
+                let mut config: Self = serde_json::from_str(&s)?;
+                for rule in &mut config.rules {
+                    rule.title_regex = rule.title.as_deref().map(Regex::new).transpose().with_context(|| format!("rule for profile {}: invalid title pattern", rule.profile))?;
+                }
+                config.validate()?;
+                Ok(Some(config))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+    // 録音を始めてから失敗しないよう、タグを書けない拡張子や見つからないコマンド、ないプロファイル名はここで弾く
+    pub fn validate(&self) -> Result<()> {
+        for name in self.default.iter().chain(self.rules.iter().map(|r| &r.profile)) {
+            if !self.profiles.contains_key(name) {
+                bail!("profile {name} not found.");
+            }
+        }
+        for rule in &self.rules {
+            if rule.title.is_some() != rule.title_regex.is_some() {
+                bail!("rule for profile {}: title pattern is not compiled", rule.profile);
+            }
+        }
+        for (name, profile) in &self.profiles {
+            if let Profile::External { command, ext, .. } = profile {
+                if !TAGGABLE_EXTENSIONS.contains(&ext.as_str()) {
+                    bail!("profile {name}: cannot tag .{ext} files (supported: {})", TAGGABLE_EXTENSIONS.join(", "));
+                }
+                let Some(program_name) = command.first() else { bail!("profile {name}: empty encoder command") };
+                if !command_exists(program_name) {
+                    bail!("profile {name}: {program_name} not found in PATH");
+                }
+            }
+        }
+        Ok(())
+    }
+    // 最初に当てはまったルールのプロファイル。どれにも当たらなければdefault
+    // プロファイル名は validate で確かめてあるので、ここでは失敗しない
+    pub fn profile_for(&self, program: &RadioProgram, matched: &[String]) -> Option<(&str, &Profile)> {
+        let mut name = self.default.as_deref();
+        for rule in &self.rules {
+            let title_ok = rule.title_regex.as_ref().is_none_or(|r| r.is_match(&program.title));
+            let station_ok = rule.station.as_ref().map(|s| s == &program.radio_channel.id).unwrap_or(true);
+            let member_ok = rule.member.as_ref().map(|m| matched.contains(m)).unwrap_or(true);
+            if title_ok && station_ok && member_ok {
+                name = Some(rule.profile.as_str());
+                break;
+            }
+        }
+        name.and_then(|name| Some((name, self.profiles.get(name)?)))
+    }
+}
+
+fn command_exists(program_name: &str) -> bool {
+    let path = Path::new(program_name);
+    if path.components().count() > 1 {
+        return path.is_file();
+    }
+    env::var_os("PATH").is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join(program_name).is_file()))
+}
+
+fn full_box(kind: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut buf = (((version as u32) << 24) | flags).to_be_bytes().to_vec();
+    buf.extend_from_slice(payload);
+    mp4_box(kind, &buf)
+}
+
+const MATRIX: [u32; 9] = [0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000];
+
+fn esds(profile: u8, sample_rate_index: u8, channels: u8) -> Vec<u8> {
+    let asc = (((profile as u16 + 1) << 11) | ((sample_rate_index as u16) << 7) | ((channels as u16) << 3)).to_be_bytes();
+    let mut decoder_config = vec![0x40, 0x15, 0, 0, 0];
+    decoder_config.extend_from_slice(&[0; 8]);
+    decoder_config.extend_from_slice(&[0x05, asc.len() as u8]);
+    decoder_config.extend_from_slice(&asc);
+    let mut es = vec![0, 0, 0];
+    es.extend_from_slice(&[0x04, decoder_config.len() as u8]);
+    es.extend(decoder_config);
+    es.extend_from_slice(&[0x06, 0x01, 0x02]);
+    let mut payload = vec![0x03, es.len() as u8];
+    payload.extend(es);
+    full_box(b"esds", 0, 0, &payload)
+}
+
+// ADTSのフレームをそのままサンプルとして、moovを先頭に置いたm4aを組み立てる
+pub fn remux_adts_to_m4a(input: &Path, output: &Path) -> Result<()> {
+    let buf = fs::read(input)?;
+    let frames = scan_frames(&buf)?;
+    let Some(first) = frames.first().copied() else { bail!("no ADTS frame in {}", input.display()) };
+    if frames.iter().any(|f| f.samples != 1024) { bail!("multiple raw data blocks per ADTS frame are not supported") }
+    let timescale = first.sample_rate;
+    let duration = frames.len() as u32 * 1024;
+
+    let ftyp = mp4_box(b"ftyp", b"M4A \x00\x00\x02\x00M4A mp42isom");
+    let moov = |mdat_offset: u32| {
+        let mut mvhd = vec![0u8; 8];
+        mvhd.extend_from_slice(&timescale.to_be_bytes());
+        mvhd.extend_from_slice(&duration.to_be_bytes());
+        mvhd.extend_from_slice(&0x00010000u32.to_be_bytes());
+        mvhd.extend_from_slice(&0x0100u16.to_be_bytes());
+        mvhd.extend_from_slice(&[0; 10]);
+        MATRIX.iter().for_each(|v| mvhd.extend_from_slice(&v.to_be_bytes()));
+        mvhd.extend_from_slice(&[0; 24]);
+        mvhd.extend_from_slice(&2u32.to_be_bytes());
+
+        let mut tkhd = vec![0u8; 8];
+        tkhd.extend_from_slice(&1u32.to_be_bytes());
+        tkhd.extend_from_slice(&[0; 4]);
+        tkhd.extend_from_slice(&duration.to_be_bytes());
+        tkhd.extend_from_slice(&[0; 8]);
+        tkhd.extend_from_slice(&[0; 4]);
+        tkhd.extend_from_slice(&0x0100u16.to_be_bytes());
+        tkhd.extend_from_slice(&[0; 2]);
+        MATRIX.iter().for_each(|v| tkhd.extend_from_slice(&v.to_be_bytes()));
+        tkhd.extend_from_slice(&[0; 8]);
+
+        let mut mdhd = vec![0u8; 8];
+        mdhd.extend_from_slice(&timescale.to_be_bytes());
+        mdhd.extend_from_slice(&duration.to_be_bytes());
+        mdhd.extend_from_slice(&0x55C4u16.to_be_bytes());
+        mdhd.extend_from_slice(&[0; 2]);
+
+        let mut hdlr = vec![0u8; 4];
+        hdlr.extend_from_slice(b"soun");
+        hdlr.extend_from_slice(&[0; 12]);
+        hdlr.extend_from_slice(b"SoundHandler\0");
+
+        let mut mp4a = vec![0u8; 6];
+        mp4a.extend_from_slice(&1u16.to_be_bytes());
+        mp4a.extend_from_slice(&[0; 8]);
+        mp4a.extend_from_slice(&(first.channels.max(1) as u16).to_be_bytes());
+        mp4a.extend_from_slice(&16u16.to_be_bytes());
+        mp4a.extend_from_slice(&[0; 4]);
+        mp4a.extend_from_slice(&(timescale << 16).to_be_bytes());
+        mp4a.extend(esds(first.profile, first.sample_rate_index, first.channels));
+        let mut stsd = 1u32.to_be_bytes().to_vec();
+        stsd.extend(mp4_box(b"mp4a", &mp4a));
+
+        let mut stts = 1u32.to_be_bytes().to_vec();
+        stts.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+        stts.extend_from_slice(&1024u32.to_be_bytes());
+        let mut stsc = 1u32.to_be_bytes().to_vec();
+        stsc.extend_from_slice(&1u32.to_be_bytes());
+        stsc.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+        stsc.extend_from_slice(&1u32.to_be_bytes());
+        let mut stsz = 0u32.to_be_bytes().to_vec();
+        stsz.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+        frames.iter().for_each(|f| stsz.extend_from_slice(&((f.len - f.header_len) as u32).to_be_bytes()));
+        let mut stco = 1u32.to_be_bytes().to_vec();
+        stco.extend_from_slice(&mdat_offset.to_be_bytes());
+
+        let stbl = [full_box(b"stsd", 0, 0, &stsd), full_box(b"stts", 0, 0, &stts), full_box(b"stsc", 0, 0, &stsc),
+            full_box(b"stsz", 0, 0, &stsz), full_box(b"stco", 0, 0, &stco)].concat();
+        let dinf = mp4_box(b"dinf", &full_box(b"dref", 0, 0, &[&1u32.to_be_bytes()[..], &full_box(b"url ", 0, 1, &[])].concat()));
+        let minf = [full_box(b"smhd", 0, 0, &[0; 4]), dinf, mp4_box(b"stbl", &stbl)].concat();
+        let mdia = [full_box(b"mdhd", 0, 0, &mdhd), full_box(b"hdlr", 0, 0, &hdlr), mp4_box(b"minf", &minf)].concat();
+        let trak = [full_box(b"tkhd", 0, 7, &tkhd), mp4_box(b"mdia", &mdia)].concat();
+        mp4_box(b"moov", &[full_box(b"mvhd", 0, 0, &mvhd), mp4_box(b"trak", &trak)].concat())
+    };
+    let mdat_offset = (ftyp.len() + moov(0).len() + 8) as u32;
+
+    let mut out = ftyp;
+    out.extend(moov(mdat_offset));
+    let payload_len = frames.iter().map(|f| f.len - f.header_len).sum::<usize>();
+    out.extend_from_slice(&((payload_len + 8) as u32).to_be_bytes());
+    out.extend_from_slice(b"mdat");
+    frames.iter().for_each(|f| out.extend_from_slice(&buf[f.offset + f.header_len..f.offset + f.len]));
+    fs::write(output, out)?;
+    Ok(())
+}
+
+// 基準音量に合わせるためのゲイン。ピークが0dBFSを超えない範囲に抑える
+pub fn normalize_gain(loudness: &Loudness) -> f64 {
+    let headroom = if loudness.peak > 0.0 { -20.0 * loudness.peak.log10() } else { f64::INFINITY };
+    loudness.replaygain_db().min(headroom)
+}
+
+// 出力先と、実際にかけたゲイン(dB)を返す
+pub fn transcode(input: &Path, program: &RadioProgram, profile: &Profile, loudness: Option<&Loudness>) -> Result<(PathBuf, f64)> {
+    match profile {
+        Profile::Remux => {
+            let output = input.with_extension("m4a");
+            remux_adts_to_m4a(input, &output)?;
+            Ok((output, 0.0))
+        }
+        Profile::External { command, ext, bitrate, normalize } => {
+            let output = input.with_extension(ext);
+            let gain = match (normalize, loudness) {
+                (true, Some(loudness)) => normalize_gain(loudness),
+                _ => 0.0,
+            };
+            let args = command.iter().map(|arg| {
+                arg.replace("{input}", &input.display().to_string())
+                    .replace("{output}", &output.display().to_string())
+                    .replace("{bitrate}", bitrate.as_deref().unwrap_or("64k"))
+                    .replace("{gain}", &format!("{gain:.2}"))
+                    .replace("{title}", &program.title)
+                    .replace("{station}", &program.radio_channel.name)
+                    .replace("{date}", &program.ft.with_timezone(&jst()).format("%Y-%m-%d").to_string())
+            }).collect::<Vec<_>>();
+            let Some((program_name, args)) = args.split_first() else { bail!("empty encoder command") };
+            let status = Command::new(program_name).args(args).status()?;
+            if !status.success() { bail!("{program_name} exited with {status}") }
+            Ok((output, gain))
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeSet;
+use serde::Serialize;
+use crate::text::{fold, is_kanji, ITAIJI};
+
+const HONORIFICS: [&str; 4] = ["さん", "ちゃん", "くん", "様"];
+// 異体字の組み合わせが爆発しないように
+const MAX_ITAIJI_FORMS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VariantKind {
+    Canonical,
+    Honorific,
+    Itaiji,
+    Spacing,
+    Width,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Variant {
+    pub text: String,
+    pub kind: VariantKind,
+}
+
+fn strip_honorific(name: &str) -> &str {
+    HONORIFICS.iter().find_map(|h| name.strip_suffix(h)).filter(|s| !s.is_empty()).unwrap_or(name)
+}
+
+// 代表字と異体字を行き来できるようにまとめる(崎→﨑・嵜、﨑→崎・嵜)
+fn itaiji_alternatives(c: char) -> Vec<char> {
+    let canonical = ITAIJI.iter().find(|(from, _)| *from == c).map(|(_, to)| *to).unwrap_or(c);
+    let mut alternatives = vec![canonical];
+    alternatives.extend(ITAIJI.iter().filter(|(_, to)| *to == canonical).map(|(from, _)| *from));
+    alternatives.retain(|a| *a != c);
+    alternatives
+}
+
+fn itaiji_forms(name: &str) -> Vec<String> {
+    let mut forms = vec![String::new()];
+    for c in name.chars() {
+        let mut next = vec![];
+        for form in &forms {
+            for alternative in [c].into_iter().chain(itaiji_alternatives(c)) {
+                if next.len() >= MAX_ITAIJI_FORMS { break; }
+                next.push(format!("{form}{alternative}"));
+            }
+        }
+        forms = next;
+    }
+    forms.retain(|form| form != name);
+    forms
+}
+
+// 姓と名の境目の候補。漢字→かなの切れ目があればそこ、漢字だけなら4文字は2+2、それ以外は前後どちらもありうる
+fn name_boundaries(name: &str) -> Vec<usize> {
+    let chars = name.chars().collect::<Vec<_>>();
+    if chars.len() < 3 || !chars.iter().all(|c| is_kanji(*c) || matches!(c, 'ぁ'..='ゖ' | 'ァ'..='ヺ' | 'ー')) { return vec![]; }
+    if let Some(i) = (1..chars.len()).find(|i| is_kanji(chars[i - 1]) && !is_kanji(chars[*i])) {
+        return vec![i];
+    }
+    if !chars.iter().all(|c| is_kanji(*c)) { return vec![]; }
+    match chars.len() {
+        3 => vec![1, 2],
+        4 => vec![2],
+        _ => vec![2, 3],
+    }
+}
+
+fn spacing_forms(name: &str) -> Vec<String> {
+    let chars = name.chars().collect::<Vec<_>>();
+    name_boundaries(name).into_iter().flat_map(|i| {
+        let (family, given) = (chars[..i].iter().collect::<String>(), chars[i..].iter().collect::<String>());
+        [format!("{family} {given}"), format!("{family}\u{3000}{given}")]
+    }).collect()
+}
+
+fn width_forms(name: &str) -> Vec<String> {
+    if !name.chars().any(|c| c.is_ascii_graphic()) { return vec![]; }
+    let full = name.chars().map(|c| match c {
+        '!'..='~' => char::from_u32(c as u32 + 0xFEE0).unwrap_or(c),
+        ' ' => '\u{3000}',
+        c => c,
+    }).collect::<String>();
+    vec![full]
+}
+
+// 正規の表記から、見かける揺れを列挙する(レビュー用)。どれもfoldすると正規の表記と同じになるものだけ残すので、
+// マッチングでは正規の表記をfoldすれば足りる
+pub fn expand(literal: &str) -> Vec<Variant> {
+    let canonical = strip_honorific(literal.trim());
+    let mut variants = vec![Variant { text: canonical.to_owned(), kind: VariantKind::Canonical }];
+    if canonical != literal.trim() {
+        variants.push(Variant { text: literal.trim().to_owned(), kind: VariantKind::Honorific });
+    }
+    let mut bases = vec![canonical.to_owned()];
+    for form in itaiji_forms(canonical) {
+        variants.push(Variant { text: form.clone(), kind: VariantKind::Itaiji });
+        bases.push(form);
+    }
+    for base in &bases {
+        variants.extend(spacing_forms(base).into_iter().map(|text| Variant { text, kind: VariantKind::Spacing }));
+        variants.extend(width_forms(base).into_iter().map(|text| Variant { text, kind: VariantKind::Width }));
+    }
+    let folded = fold(canonical);
+    let mut seen = BTreeSet::new();
+    variants.retain(|v| seen.insert(v.text.clone()) && (v.kind == VariantKind::Honorific || fold(&v.text) == folded));
+    variants
+}
+
+// マッチングに使う形。揺れはfoldで吸収するので、敬称を外して寄せた結果が同じものをまとめるだけ
+pub fn match_literals<'a>(literals: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut seen = BTreeSet::new();
+    literals.into_iter()
+        .map(|literal| strip_honorific(literal.trim()).to_owned())
+        .filter(|literal| seen.insert(fold(literal)))
+        .collect()
+}
@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveTime, Weekday};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::matcher::{search_artist, MatchType};
+use crate::notify::NotifyChannel;
+use crate::radiko::{jst, RadioProgram};
+use crate::series::{legacy_series_id, series_id, Series};
+use crate::text::fold_contains;
+
+// 放送開始時刻(JST)の範囲。from > to なら日付をまたぐ
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TimeOfDay {
+    pub from: NaiveTime,
+    pub to: NaiveTime,
+}
+
+impl TimeOfDay {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.from <= self.to {
+            self.from <= time && time < self.to
+        } else {
+            self.from <= time || time < self.to
+        }
+    }
+}
+
+// 例: {"name":"深夜のアニソン","keywords":["アニソン"],"stations":["QRR"],"weekdays":["Sat","Sun"],"time":{"from":"22:00:00","to":"04:00:00"}}
+// 局・曜日・時間帯は全部満たす必要があり、キーワード・出演者・正規表現・シリーズはどれか1つに当たればよい
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Target {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    // 出演者欄と、オンエア曲のアーティスト名を見る
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub performers: Vec<String>,
+    // 番組名に対する正規表現
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    // 番組名・説明・出演者欄のどれかに対する正規表現
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    // series::series_id で決まる番組シリーズ(曜日・時刻の枠ごと)。シリーズごと購読する
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub series: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stations: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub weekdays: Vec<Weekday>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<TimeOfDay>,
+    // title/regexをコンパイルしたもの。load_watchlists で埋める
+    #[serde(skip)]
+    title_regex: Option<Regex>,
+    #[serde(skip)]
+    text_regex: Option<Regex>,
+}
+
+fn texts(program: &RadioProgram) -> Vec<&str> {
+    [Some(&program.title), program.desc.as_ref(), program.info.as_ref(), program.pfm.as_ref()].into_iter().flatten().map(|t| t.as_str()).collect()
+}
+
+impl Target {
+    // シリーズ1つを購読するだけのターゲット
+    pub fn for_series(name: &str, series_id: &str) -> Self {
+        Target { name: name.to_owned(), series: vec![series_id.to_owned()], ..Target::default() }
+    }
+    fn has_text_condition(&self) -> bool {
+        !self.keywords.is_empty() || !self.performers.is_empty() || self.title.is_some() || self.regex.is_some() || !self.series.is_empty()
+    }
+    fn has_schedule_condition(&self) -> bool {
+        !self.stations.is_empty() || !self.weekdays.is_empty() || self.time.is_some()
+    }
+    // 条件を確かめ、正規表現は番組ごとに作り直さないようここでコンパイルしておく
+    pub fn compile(&mut self) -> Result<()> {
+        if !self.has_text_condition() && !self.has_schedule_condition() {
+            return Err(anyhow!("target {} has no conditions", self.name));
+        }
+        let compile = |pattern: &Option<String>, field: &str| pattern.as_deref().map(Regex::new).transpose().map_err(|err| anyhow!("target {}: invalid {field}: {err}", self.name));
+        self.title_regex = compile(&self.title, "title")?;
+        self.text_regex = compile(&self.regex, "regex")?;
+        Ok(())
+    }
+    fn on_schedule(&self, program: &RadioProgram) -> bool {
+        let ft = program.ft.with_timezone(&jst());
+        (self.stations.is_empty() || self.stations.contains(&program.radio_channel.id))
+            && (self.weekdays.is_empty() || self.weekdays.contains(&ft.weekday()))
+            && self.time.is_none_or(|time| time.contains(ft.time()))
+    }
+    pub fn match_program(&self, program: &RadioProgram) -> Result<Vec<MatchType>> {
+        if !self.on_schedule(program) {
+            return Ok(vec![]);
+        }
+        if !self.has_text_condition() {
+            return Ok(vec![MatchType::Schedule]);
+        }
+        if self.title.is_some() != self.title_regex.is_some() || self.regex.is_some() != self.text_regex.is_some() {
+            return Err(anyhow!("target {} is not compiled", self.name));
+        }
+        let texts = texts(program);
+        let mut found = vec![];
+        let title_hit = self.title_regex.as_ref().is_some_and(|re| re.is_match(&program.title));
+        let regex_hit = self.text_regex.as_ref().is_some_and(|re| texts.iter().any(|t| re.is_match(t)));
+        let keyword_hit = self.keywords.iter().any(|k| texts.iter().any(|t| fold_contains(t, k)));
+        let performer_hit = self.performers.iter().any(|p| program.pfm.as_ref().is_some_and(|pfm| fold_contains(pfm, p)));
+        if title_hit || regex_hit || keyword_hit || performer_hit {
+            found.push(MatchType::Text);
+        }
+        if !self.series.is_empty() {
+            let series_id = series_id(program);
+            if self.series.contains(&series_id) || self.series.contains(&legacy_series_id(program)) {
+                found.push(MatchType::Series { series_id });
+            }
+        }
+        found.extend(program.on_air_music.iter().filter(|music| self.performers.iter().any(|p| fold_contains(&music.artist_name, p))).map(|music| MatchType::SongPlayed {
+            start_time: music.start_time,
+            music_title: music.music_title.clone(),
+            artist_name: music.artist_name.clone(),
+        }));
+        Ok(found)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Watchlist {
+    pub name: String,
+    // 書き込み先のルートコレクション(その下にcatalog/programs/...を作る)
+    pub collection: String,
+    // members.json と match_rules.json でハロプロのメンバーを探す
+    #[serde(default)]
+    pub hello_project: bool,
+    #[serde(default)]
+    pub targets: Vec<Target>,
+    #[serde(default)]
+    pub notify: Vec<NotifyChannel>,
+}
+
+impl Watchlist {
+    pub fn compile(&mut self) -> Result<()> {
+        if !self.hello_project && self.targets.is_empty() {
+            return Err(anyhow!("watchlist {} has no targets", self.name));
+        }
+        self.targets.iter_mut().try_for_each(|target| target.compile())
+    }
+    // 該当者(ターゲット)名ごとのマッチ
+    pub fn match_program(&self, program: &RadioProgram, member_json: &Value, match_rules: &Value) -> Result<BTreeMap<String, Vec<MatchType>>> {
+        let mut matched = BTreeMap::<String, Vec<MatchType>>::new();
+        if self.hello_project {
+            for (name, match_type) in search_artist(program.clone(), member_json.clone(), match_rules.clone()) {
+                matched.entry(name).or_default().push(match_type);
+            }
+        }
+        for target in &self.targets {
+            let found = target.match_program(program)?;
+            if !found.is_empty() {
+                matched.entry(target.name.clone()).or_default().extend(found);
+            }
+        }
+        Ok(matched)
+    }
+    // そのシリーズを購読しているターゲット名(曜日を含まない古いIDでの購読も含む)
+    pub fn series_targets(&self, series: &Series) -> Vec<String> {
+        let legacy_id = series.legacy_id();
+        self.targets.iter().filter(|t| t.series.iter().any(|s| *s == series.id || *s == legacy_id)).map(|t| t.name.clone()).collect()
+    }
+    // group/memberの区別やexpiryに使うメンバー一覧。ハロプロ以外は空
+    pub fn member_json<'a>(&self, member_json: &'a Value, empty: &'a Value) -> &'a Value {
+        if self.hello_project { member_json } else { empty }
+    }
+}
+
+// 組み込みのもの(src/watchlists.json)に、ファイルがあればそこに書かれたものを足す
+pub fn load_watchlists(builtin: &str, extra: &Path) -> Result<Vec<Watchlist>> {
+    let mut watchlists: Vec<Watchlist> = serde_json::from_str(builtin)?;
+    match fs::read_to_string(extra) {
+        Ok(s) => watchlists.extend(serde_json::from_str::<Vec<Watchlist>>(&s)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    for watchlist in &mut watchlists {
+        watchlist.compile()?;
+    }
+    for watchlist in &watchlists {
+        if watchlists.iter().filter(|w| w.name == watchlist.name).count() > 1 {
+            return Err(anyhow!("watchlist {} is defined twice", watchlist.name));
+        }
+    }
+    Ok(watchlists)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(title: &str) -> RadioProgram {
+        serde_json::from_value(serde_json::json!({
+            "station_id": "QRR", "id": 1, "ft": "2026-10-17T14:00:00Z", "to": "2026-10-17T15:00:00Z", "dur": 3600,
+            "title": title, "img": null, "info": null, "desc": "今週もアニソン特集", "pfm": null, "on_air_music": []
+        })).unwrap()
+    }
+
+    fn load(extra: &str) -> Result<Vec<Watchlist>> {
+        let path = std::env::temp_dir().join(format!("radiko_watchlists_{}_{}.json", std::process::id(), extra.len()));
+        fs::write(&path, extra).unwrap();
+        let watchlists = load_watchlists("[]", &path);
+        fs::remove_file(&path).unwrap();
+        watchlists
+    }
+
+    #[test]
+    fn patterns_are_compiled_at_load() {
+        let watchlists = load(r#"[{"name":"anison","collection":"anison","targets":[{"name":"深夜","title":"^深夜","regex":"アニソン"}]}]"#).unwrap();
+        let target = &watchlists[0].targets[0];
+        assert!(matches!(target.match_program(&program("深夜の番組")).unwrap()[..], [MatchType::Text]));
+        // 説明のアニソンにはregexが当たる
+        assert!(matches!(target.match_program(&program("朝の番組")).unwrap()[..], [MatchType::Text]));
+        assert!(Target { regex: None, text_regex: None, ..target.clone() }.match_program(&program("朝の番組")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected_at_load() {
+        let err = load(r#"[{"name":"anison","collection":"anison","targets":[{"name":"深夜","regex":"(アニソン"}]}]"#).unwrap_err();
+        assert!(err.to_string().contains("invalid regex"));
+    }
+
+    #[test]
+    fn uncompiled_targets_fail_instead_of_matching_nothing() {
+        let target = Target { name: "深夜".to_owned(), title: Some("^深夜".to_owned()), ..Target::default() };
+        assert!(target.match_program(&program("深夜の番組")).is_err());
+    }
+}
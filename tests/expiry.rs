@@ -0,0 +1,33 @@
+use serde_json::Value;
+use unicode_normalization::UnicodeNormalization;
+use radiko_cacher::expiry::ExpiryPolicy;
+use radiko_cacher::radiko::RadioProgram;
+
+fn program() -> RadioProgram {
+    serde_json::from_str(r#"{
+        "station_id": "TBS", "id": 1, "ft": "2026-10-18T12:00:00Z", "to": "2026-10-18T13:00:00Z", "dur": 3600,
+        "title": "テスト番組", "img": null, "info": null, "desc": null, "pfm": "中澤裕子", "on_air_music": []
+    }"#).unwrap()
+}
+
+fn load() -> (ExpiryPolicy, Value) {
+    let member_json = serde_json::from_str(include_str!("../src/members.json").nfkc().collect::<String>().as_str()).unwrap();
+    (serde_json::from_str(include_str!("../src/expiry_rules.json")).unwrap(), member_json)
+}
+
+#[test]
+fn og_member_is_kept_forever() {
+    let (policy, member_json) = load();
+    let program = program();
+    assert_eq!(policy.expire_at_for(&program, "中澤裕子", &member_json), None);
+    assert_eq!(policy.program_expire_at(&program, &["中澤裕子".to_owned()], &member_json), None);
+}
+
+#[test]
+fn other_members_use_the_default_rule() {
+    let (policy, member_json) = load();
+    let program = program();
+    let group = member_json.as_object().unwrap().iter().find(|(group, _)| *group != "OG").unwrap();
+    let member = group.1.as_object().unwrap().keys().next().unwrap();
+    assert_eq!(policy.expire_at_for(&program, member, &member_json), Some(program.to + chrono::TimeDelta::days(14)));
+}
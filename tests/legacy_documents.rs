@@ -0,0 +1,30 @@
+use radiko_cacher::matcher::{MatchType, MatchedProgram};
+use radiko_cacher::schema::merge_legacy;
+
+// スキーマ変更前の main.rs が書いていた形(局を埋め込み、match_types なし)
+const LEGACY: &str = r#"{
+    "radio_channel": {"id": "TBS", "name": "TBSラジオ", "banner_url": "https://radiko.jp/res/banner/TBS/20200101.png", "area_id": "JP13"},
+    "id": 12345,
+    "ft": "2024-04-01T12:00:00Z",
+    "to": "2024-04-01T13:00:00Z",
+    "dur": 3600,
+    "title": "テスト番組",
+    "img": null,
+    "info": null,
+    "desc": "ゲスト:譜久村聖",
+    "pfm": "パーソナリティ",
+    "on_air_music": [],
+    "expire_at": "2024-04-15T13:00:00Z"
+}"#;
+
+#[test]
+fn legacy_document_without_match_types() {
+    let program: MatchedProgram = serde_json::from_str(LEGACY).unwrap();
+    assert_eq!(program.program.id, 12345);
+    assert_eq!(program.program.radio_channel.id, "TBS");
+    assert!(program.match_types.is_empty());
+
+    let merged = merge_legacy(vec![("譜久村聖".to_owned(), program)]);
+    assert_eq!(merged.len(), 1);
+    assert!(matches!(merged[0].1["譜久村聖"].as_slice(), [MatchType::Text]));
+}
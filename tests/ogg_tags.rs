@@ -0,0 +1,85 @@
+use std::fs;
+use chrono::{TimeDelta, TimeZone};
+use radiko_cacher::loudness::Loudness;
+use radiko_cacher::radiko::jst;
+use radiko_cacher::tagging::{read_ogg_comments, write_tags, ChapterMark, TagInfo};
+
+// CRCは読む側で見ないので0のまま
+fn page(header_type: u8, granule: u64, seq: u32, packets: &[&[u8]]) -> Vec<u8> {
+    let mut lacing = vec![];
+    let mut data = vec![];
+    for packet in packets {
+        lacing.extend(std::iter::repeat_n(255u8, packet.len() / 255));
+        lacing.push((packet.len() % 255) as u8);
+        data.extend_from_slice(packet);
+    }
+    let mut buf = b"OggS\0".to_vec();
+    buf.push(header_type);
+    buf.extend_from_slice(&granule.to_le_bytes());
+    buf.extend_from_slice(&7u32.to_le_bytes());
+    buf.extend_from_slice(&seq.to_le_bytes());
+    buf.extend_from_slice(&[0; 4]);
+    buf.push(lacing.len() as u8);
+    buf.extend(lacing);
+    buf.extend(data);
+    buf
+}
+
+fn opus_tags(vendor: &str) -> Vec<u8> {
+    let mut packet = b"OpusTags".to_vec();
+    packet.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    packet.extend_from_slice(vendor.as_bytes());
+    packet.extend_from_slice(&1u32.to_le_bytes());
+    packet.extend_from_slice(&9u32.to_le_bytes());
+    packet.extend_from_slice(b"TITLE=old");
+    packet
+}
+
+fn info(loudness: Loudness) -> TagInfo {
+    TagInfo {
+        title: "テスト番組".to_owned(),
+        station: "TBSラジオ".to_owned(),
+        performers: Some("譜久村聖".to_owned()),
+        air_date: jst().with_ymd_and_hms(2026, 10, 18, 21, 0, 0).unwrap(),
+        description: None,
+        // 1ページ(255×255バイト)に収まらない大きさにする
+        cover: Some(("image/jpeg".to_owned(), vec![0xab; 100_000])),
+        chapters: vec![ChapterMark { start: TimeDelta::seconds(90), end: TimeDelta::seconds(300), title: "曲 / 歌手".to_owned() }],
+        loudness: Some(loudness),
+    }
+}
+
+#[test]
+fn opus_r128_gain_round_trips() {
+    let dir = std::env::temp_dir().join(format!("radiko_ogg_tags_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("program.opus");
+    let audio = [&[0x11u8; 300][..], &[0x22u8; 40][..]];
+    let mut file = page(2, 0, 0, &[b"OpusHead\x01\x02\x38\x01\x80\xbb\0\0\0\0\0"]);
+    file.extend(page(0, 0, 1, &[&opus_tags("test vendor")]));
+    file.extend(page(0, 960, 2, &audio[..1]));
+    file.extend(page(4, 1920, 3, &audio[1..]));
+    fs::write(&path, &file).unwrap();
+
+    let loudness = Loudness { integrated_lufs: -30.0, peak: 0.5 };
+    write_tags(&path, &info(loudness)).unwrap();
+    let comments = read_ogg_comments(&path).unwrap();
+    let get = |name: &str| comments.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+    assert_eq!(get("R128_TRACK_GAIN"), Some(loudness.r128_gain_q78().to_string().as_str()));
+    assert_eq!(get("R128_TRACK_GAIN"), Some("1792"));
+    assert_eq!(get("REPLAYGAIN_TRACK_GAIN"), None);
+    assert_eq!(get("TITLE"), Some("テスト番組"));
+    assert_eq!(get("CHAPTER001"), Some("00:01:30.000"));
+    assert!(get("METADATA_BLOCK_PICTURE").is_some());
+
+    // 音声のページはそのまま後ろに残る
+    let tagged = fs::read(&path).unwrap();
+    assert!(tagged.ends_with(&audio[1..].concat()));
+    assert!(tagged.windows(300).any(|w| w == audio[0]));
+
+    // 2回書いても壊れない
+    write_tags(&path, &info(loudness.with_gain(7.0))).unwrap();
+    let comments = read_ogg_comments(&path).unwrap();
+    assert_eq!(comments.iter().find(|(n, _)| n == "R128_TRACK_GAIN").map(|(_, v)| v.as_str()), Some("0"));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,38 @@
+use std::fs;
+use radiko_cacher::radiko::RadioProgram;
+use radiko_cacher::transcode::{Profile, TranscodeConfig};
+
+fn program(title: &str) -> RadioProgram {
+    serde_json::from_str(&format!(r#"{{
+        "station_id": "TBS", "id": 1, "ft": "2026-10-18T12:00:00Z", "to": "2026-10-18T13:00:00Z", "dur": 3600,
+        "title": "{title}", "img": null, "info": null, "desc": null, "pfm": null, "on_air_music": []
+    }}"#)).unwrap()
+}
+
+fn load(name: &str, json: &str) -> anyhow::Result<Option<TranscodeConfig>> {
+    let path = std::env::temp_dir().join(format!("radiko_transcode_{name}_{}.json", std::process::id()));
+    fs::write(&path, json).unwrap();
+    let config = TranscodeConfig::load(&path);
+    fs::remove_file(&path).unwrap();
+    config
+}
+
+#[test]
+fn rules_are_compiled_at_load() {
+    let config = load("ok", r#"{"default":"m4a","profiles":{"m4a":{"encoder":"remux"},"talk":{"encoder":"remux"}},
+        "rules":[{"title":"^トーク","profile":"talk"}]}"#).unwrap().unwrap();
+    assert_eq!(config.profile_for(&program("トーク番組"), &[]).map(|(name, _)| name), Some("talk"));
+    assert!(matches!(config.profile_for(&program("音楽番組"), &[]), Some(("m4a", Profile::Remux))));
+}
+
+#[test]
+fn invalid_title_pattern_is_rejected_at_load() {
+    let err = load("regex", r#"{"profiles":{"m4a":{"encoder":"remux"}},"rules":[{"title":"(トーク","profile":"m4a"}]}"#).unwrap_err();
+    assert!(format!("{err:#}").contains("invalid title pattern"));
+}
+
+#[test]
+fn unknown_profile_names_are_rejected_at_load() {
+    assert!(load("default", r#"{"default":"opus","profiles":{"m4a":{"encoder":"remux"}}}"#).is_err());
+    assert!(load("rule", r#"{"profiles":{"m4a":{"encoder":"remux"}},"rules":[{"station":"TBS","profile":"talk"}]}"#).is_err());
+}